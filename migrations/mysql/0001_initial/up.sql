@@ -0,0 +1,94 @@
+-- Tokens are generated by the application and stored as CHAR(36), and timestamps are stored as
+-- DATETIME in UTC, as MySQL has no UUID type and TIMESTAMP columns end in 2038.
+
+CREATE TABLE clients (
+  id INTEGER NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  identifier VARCHAR(256) NOT NULL,
+  secret VARCHAR(256) NOT NULL,
+  response_type VARCHAR(64) NOT NULL,
+  CONSTRAINT clients__unique_identifier
+    UNIQUE (identifier)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE grant_types (
+  id INTEGER NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  name VARCHAR(32) NOT NULL,
+  CONSTRAINT grant_types__unique_name
+    UNIQUE (name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE client_redirect_uris (
+  id INTEGER NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  client_id INTEGER NOT NULL,
+  redirect_uri VARCHAR(128) NOT NULL,
+  CONSTRAINT client_redirect_uris__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE access_tokens (
+  id INTEGER NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  token CHAR(36) NOT NULL,
+  client_id INTEGER NOT NULL,
+  grant_id INTEGER NOT NULL,
+  scope VARCHAR(255) NOT NULL,
+  issued_at DATETIME NOT NULL,
+  expires_at DATETIME NOT NULL,
+  CONSTRAINT access_tokens__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id),
+  CONSTRAINT access_tokens__grant_id
+    FOREIGN KEY (grant_id)
+    REFERENCES grant_types (id),
+  CONSTRAINT access_tokens__unique_token
+    UNIQUE(token)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE refresh_tokens (
+  id INTEGER NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  token CHAR(36) NOT NULL,
+  client_id INTEGER NOT NULL,
+  scope VARCHAR(255) NOT NULL,
+  issued_at DATETIME NOT NULL,
+  expires_at DATETIME,
+  CONSTRAINT refresh_tokens__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id),
+  CONSTRAINT refresh_tokens__token
+    UNIQUE(token)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE auth_codes (
+  id INTEGER NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  client_id INTEGER NOT NULL,
+  name VARCHAR(64) NOT NULL,
+  scope VARCHAR(255) NOT NULL,
+  expires_at DATETIME NOT NULL,
+  redirect_uri VARCHAR(128) NOT NULL,
+  user_id INTEGER,
+  CONSTRAINT auth_codes__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE token_lifetimes (
+  id INTEGER NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  client_id INTEGER,
+  grant_id INTEGER,
+  scope VARCHAR(255),
+  access_token_ttl INTEGER,
+  refresh_token_ttl INTEGER,
+  CONSTRAINT token_lifetimes__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id),
+  CONSTRAINT token_lifetimes__grant_id
+    FOREIGN KEY (grant_id)
+    REFERENCES grant_types (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+INSERT INTO grant_types (name) VALUES
+  ('authorization_code'),
+  ('token'),
+  ('password'),
+  ('client_credentials'),
+  ('refresh_token');
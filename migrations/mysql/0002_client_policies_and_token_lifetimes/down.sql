@@ -0,0 +1,15 @@
+DROP TABLE token_lifetimes;
+
+ALTER TABLE refresh_tokens
+  DROP COLUMN absolute_expires_at,
+  DROP COLUMN last_used_at;
+
+-- The index also backs the access_tokens__client_id foreign key, so MySQL
+-- only lets it go once the constraint has another index to use.
+CREATE INDEX access_tokens__client_id ON access_tokens (client_id);
+DROP INDEX access_tokens__client_grant_expiry ON access_tokens;
+
+ALTER TABLE clients
+  DROP COLUMN rotate_refresh_tokens,
+  DROP COLUMN issue_refresh_tokens,
+  DROP COLUMN token_reuse_min_ttl;
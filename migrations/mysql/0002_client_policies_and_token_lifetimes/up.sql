@@ -0,0 +1,26 @@
+ALTER TABLE clients
+  ADD COLUMN token_reuse_min_ttl INTEGER,
+  ADD COLUMN issue_refresh_tokens BOOLEAN,
+  ADD COLUMN rotate_refresh_tokens BOOLEAN;
+
+CREATE INDEX access_tokens__client_grant_expiry
+  ON access_tokens (client_id, grant_id, expires_at);
+
+ALTER TABLE refresh_tokens
+  ADD COLUMN last_used_at DATETIME,
+  ADD COLUMN absolute_expires_at DATETIME;
+
+CREATE TABLE token_lifetimes (
+  id INTEGER NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  client_id INTEGER,
+  grant_id INTEGER,
+  scope VARCHAR(255),
+  access_token_ttl INTEGER,
+  refresh_token_ttl INTEGER,
+  CONSTRAINT token_lifetimes__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id),
+  CONSTRAINT token_lifetimes__grant_id
+    FOREIGN KEY (grant_id)
+    REFERENCES grant_types (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
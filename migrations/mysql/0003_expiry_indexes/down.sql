@@ -0,0 +1,3 @@
+DROP INDEX auth_codes__expires_at ON auth_codes;
+DROP INDEX refresh_tokens__expires_at ON refresh_tokens;
+DROP INDEX access_tokens__expires_at ON access_tokens;
@@ -0,0 +1,3 @@
+CREATE INDEX access_tokens__expires_at ON access_tokens (expires_at);
+CREATE INDEX refresh_tokens__expires_at ON refresh_tokens (expires_at);
+CREATE INDEX auth_codes__expires_at ON auth_codes (expires_at);
@@ -0,0 +1,13 @@
+-- Tokens issued since the upgrade only have a digest, so they cannot be kept.
+DELETE FROM refresh_tokens WHERE token IS NULL;
+DELETE FROM access_tokens WHERE token IS NULL;
+
+ALTER TABLE refresh_tokens
+  DROP INDEX refresh_tokens__unique_token_hash,
+  DROP COLUMN token_hash,
+  MODIFY token CHAR(36) NOT NULL;
+
+ALTER TABLE access_tokens
+  DROP INDEX access_tokens__unique_token_hash,
+  DROP COLUMN token_hash,
+  MODIFY token CHAR(36) NOT NULL;
@@ -0,0 +1,12 @@
+-- Only the SHA-256 digest of new tokens is stored. Rows issued before this
+-- migration keep their plaintext `token` until they expire, or for refresh
+-- tokens, until they are next used.
+ALTER TABLE access_tokens
+  MODIFY token CHAR(36) NULL,
+  ADD COLUMN token_hash CHAR(64) AFTER token,
+  ADD CONSTRAINT access_tokens__unique_token_hash UNIQUE (token_hash);
+
+ALTER TABLE refresh_tokens
+  MODIFY token CHAR(36) NULL,
+  ADD COLUMN token_hash CHAR(64) AFTER token,
+  ADD CONSTRAINT refresh_tokens__unique_token_hash UNIQUE (token_hash);
@@ -0,0 +1,10 @@
+DROP INDEX access_tokens__refresh_token_id ON access_tokens;
+DROP INDEX refresh_tokens__parent_id ON refresh_tokens;
+
+ALTER TABLE access_tokens
+  DROP COLUMN revoked_at,
+  DROP COLUMN refresh_token_id;
+
+ALTER TABLE refresh_tokens
+  DROP COLUMN revoked_at,
+  DROP COLUMN parent_id;
@@ -0,0 +1,13 @@
+-- Links every token to what it was derived from, so that revoking a token
+-- can revoke everything derived from it. The links are not foreign keys, as
+-- parents may be pruned before their children.
+ALTER TABLE refresh_tokens
+  ADD COLUMN parent_id INTEGER,
+  ADD COLUMN revoked_at DATETIME;
+
+ALTER TABLE access_tokens
+  ADD COLUMN refresh_token_id INTEGER,
+  ADD COLUMN revoked_at DATETIME;
+
+CREATE INDEX refresh_tokens__parent_id ON refresh_tokens (parent_id);
+CREATE INDEX access_tokens__refresh_token_id ON access_tokens (refresh_token_id);
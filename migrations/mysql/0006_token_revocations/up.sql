@@ -0,0 +1,13 @@
+-- The audit trail of every revocation: who revoked what, why, and how many tokens it revoked,
+-- counting the ones derived from them. `selection` describes what was revoked, e.g.
+-- `client=automation scope=admin` or `refresh_token=42`. Instances compare the latest id with
+-- the one they last saw to tell whether their introspection cache may be stale.
+CREATE TABLE token_revocations (
+  id INTEGER NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  revoked_at DATETIME NOT NULL,
+  actor VARCHAR(256) NOT NULL,
+  reason VARCHAR(1024),
+  selection VARCHAR(1024) NOT NULL,
+  access_tokens INTEGER NOT NULL,
+  refresh_tokens INTEGER NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
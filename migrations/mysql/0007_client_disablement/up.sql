@@ -0,0 +1,3 @@
+-- Disabled clients can neither authenticate nor be issued tokens, and every token they held is
+-- revoked when they are disabled. Clearing `disabled_at` enables the client again.
+ALTER TABLE clients ADD COLUMN disabled_at DATETIME;
@@ -0,0 +1,2 @@
+DROP INDEX refresh_tokens__revoked_at ON refresh_tokens;
+DROP INDEX access_tokens__revoked_at ON access_tokens;
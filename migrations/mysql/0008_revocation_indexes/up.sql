@@ -0,0 +1,4 @@
+-- Revoked tokens are pruned once they have been revoked for longer than the grace period, even if
+-- they have not expired yet.
+CREATE INDEX access_tokens__revoked_at ON access_tokens (revoked_at);
+CREATE INDEX refresh_tokens__revoked_at ON refresh_tokens (revoked_at);
@@ -0,0 +1,12 @@
+DROP TABLE token_lifetimes;
+
+ALTER TABLE refresh_tokens
+  DROP COLUMN absolute_expires_at,
+  DROP COLUMN last_used_at;
+
+DROP INDEX access_tokens__client_grant_expiry;
+
+ALTER TABLE clients
+  DROP COLUMN rotate_refresh_tokens,
+  DROP COLUMN issue_refresh_tokens,
+  DROP COLUMN token_reuse_min_ttl;
@@ -0,0 +1,3 @@
+DROP INDEX auth_codes__expires_at;
+DROP INDEX refresh_tokens__expires_at;
+DROP INDEX access_tokens__expires_at;
@@ -0,0 +1,13 @@
+-- Tokens issued since the upgrade only have a digest, so they cannot be kept.
+DELETE FROM refresh_tokens WHERE token IS NULL;
+DELETE FROM access_tokens WHERE token IS NULL;
+
+ALTER TABLE refresh_tokens
+  DROP COLUMN token_hash,
+  ALTER COLUMN token SET DEFAULT uuid_generate_v4(),
+  ALTER COLUMN token SET NOT NULL;
+
+ALTER TABLE access_tokens
+  DROP COLUMN token_hash,
+  ALTER COLUMN token SET DEFAULT uuid_generate_v4(),
+  ALTER COLUMN token SET NOT NULL;
@@ -0,0 +1,14 @@
+-- Tokens are now generated by the application, and only their SHA-256 digest
+-- is stored. Rows issued before this migration keep their plaintext `token`
+-- until they expire, or for refresh tokens, until they are next used.
+ALTER TABLE access_tokens
+  ALTER COLUMN token DROP NOT NULL,
+  ALTER COLUMN token DROP DEFAULT,
+  ADD COLUMN token_hash CHAR(64),
+  ADD CONSTRAINT access_tokens__unique_token_hash UNIQUE (token_hash);
+
+ALTER TABLE refresh_tokens
+  ALTER COLUMN token DROP NOT NULL,
+  ALTER COLUMN token DROP DEFAULT,
+  ADD COLUMN token_hash CHAR(64),
+  ADD CONSTRAINT refresh_tokens__unique_token_hash UNIQUE (token_hash);
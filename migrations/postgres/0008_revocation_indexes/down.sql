@@ -0,0 +1,2 @@
+DROP INDEX refresh_tokens__revoked_at;
+DROP INDEX access_tokens__revoked_at;
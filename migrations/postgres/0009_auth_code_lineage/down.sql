@@ -0,0 +1,5 @@
+DROP INDEX access_tokens__auth_code_id;
+DROP INDEX refresh_tokens__auth_code_id;
+
+ALTER TABLE access_tokens DROP COLUMN auth_code_id;
+ALTER TABLE refresh_tokens DROP COLUMN auth_code_id;
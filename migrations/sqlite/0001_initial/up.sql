@@ -0,0 +1,77 @@
+CREATE TABLE clients (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  identifier VARCHAR(256) NOT NULL,
+  secret VARCHAR(256) NOT NULL,
+  response_type VARCHAR(64) NOT NULL,
+  CONSTRAINT clients__unique_identifier
+    UNIQUE (identifier)
+);
+
+CREATE TABLE grant_types (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name VARCHAR(32) NOT NULL,
+  CONSTRAINT grant_types__unique_name
+    UNIQUE (name)
+);
+
+CREATE TABLE client_redirect_uris (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  client_id INTEGER NOT NULL,
+  redirect_uri VARCHAR(128) NOT NULL,
+  CONSTRAINT client_redirect_uris__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id)
+);
+
+-- Tokens are generated by the application, as SQLite has no UUID support.
+CREATE TABLE access_tokens (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  token VARCHAR(36) NOT NULL,
+  client_id INTEGER NOT NULL,
+  grant_id INTEGER NOT NULL,
+  scope VARCHAR(255) NOT NULL,
+  issued_at TIMESTAMP NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  CONSTRAINT access_tokens__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id),
+  CONSTRAINT access_tokens__grant_id
+    FOREIGN KEY (grant_id)
+    REFERENCES grant_types (id),
+  CONSTRAINT access_tokens__unique_token
+    UNIQUE(token)
+);
+
+CREATE TABLE refresh_tokens (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  token VARCHAR(36) NOT NULL,
+  client_id INTEGER NOT NULL,
+  scope VARCHAR(255) NOT NULL,
+  issued_at TIMESTAMP NOT NULL,
+  expires_at TIMESTAMP,
+  CONSTRAINT refresh_tokens__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id),
+  CONSTRAINT refresh_tokens__token
+    UNIQUE(token)
+);
+
+CREATE TABLE auth_codes (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  client_id INTEGER NOT NULL,
+  name VARCHAR(64) NOT NULL,
+  scope VARCHAR(255) NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  redirect_uri VARCHAR(128) NOT NULL,
+  user_id INTEGER,
+  CONSTRAINT auth_codes__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id)
+);
+
+INSERT INTO grant_types (name) VALUES
+  ('authorization_code'),
+  ('token'),
+  ('password'),
+  ('client_credentials'),
+  ('refresh_token');
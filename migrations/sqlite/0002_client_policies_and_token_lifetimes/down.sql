@@ -0,0 +1,11 @@
+-- DROP COLUMN needs SQLite 3.35 or later.
+DROP TABLE token_lifetimes;
+
+ALTER TABLE refresh_tokens DROP COLUMN absolute_expires_at;
+ALTER TABLE refresh_tokens DROP COLUMN last_used_at;
+
+DROP INDEX access_tokens__client_grant_expiry;
+
+ALTER TABLE clients DROP COLUMN rotate_refresh_tokens;
+ALTER TABLE clients DROP COLUMN issue_refresh_tokens;
+ALTER TABLE clients DROP COLUMN token_reuse_min_ttl;
@@ -0,0 +1,61 @@
+-- Only the SHA-256 digest of new tokens is stored. Rows issued before this
+-- migration keep their plaintext `token` until they expire, or for refresh
+-- tokens, until they are next used. SQLite cannot drop a NOT NULL constraint,
+-- so both tables are rebuilt; nothing references them.
+CREATE TABLE access_tokens_new (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  token VARCHAR(36),
+  token_hash CHAR(64),
+  client_id INTEGER NOT NULL,
+  grant_id INTEGER NOT NULL,
+  scope VARCHAR(255) NOT NULL,
+  issued_at TIMESTAMP NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  CONSTRAINT access_tokens__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id),
+  CONSTRAINT access_tokens__grant_id
+    FOREIGN KEY (grant_id)
+    REFERENCES grant_types (id),
+  CONSTRAINT access_tokens__unique_token
+    UNIQUE(token),
+  CONSTRAINT access_tokens__unique_token_hash
+    UNIQUE(token_hash)
+);
+
+INSERT INTO access_tokens_new (id, token, client_id, grant_id, scope, issued_at, expires_at)
+  SELECT id, token, client_id, grant_id, scope, issued_at, expires_at FROM access_tokens;
+DROP TABLE access_tokens;
+ALTER TABLE access_tokens_new RENAME TO access_tokens;
+
+CREATE INDEX access_tokens__client_grant_expiry
+  ON access_tokens (client_id, grant_id, expires_at);
+CREATE INDEX access_tokens__expires_at ON access_tokens (expires_at);
+
+CREATE TABLE refresh_tokens_new (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  token VARCHAR(36),
+  token_hash CHAR(64),
+  client_id INTEGER NOT NULL,
+  scope VARCHAR(255) NOT NULL,
+  issued_at TIMESTAMP NOT NULL,
+  expires_at TIMESTAMP,
+  last_used_at TIMESTAMP,
+  absolute_expires_at TIMESTAMP,
+  CONSTRAINT refresh_tokens__client_id
+    FOREIGN KEY (client_id)
+    REFERENCES clients (id),
+  CONSTRAINT refresh_tokens__token
+    UNIQUE(token),
+  CONSTRAINT refresh_tokens__unique_token_hash
+    UNIQUE(token_hash)
+);
+
+INSERT INTO refresh_tokens_new
+    (id, token, client_id, scope, issued_at, expires_at, last_used_at, absolute_expires_at)
+  SELECT id, token, client_id, scope, issued_at, expires_at, last_used_at, absolute_expires_at
+    FROM refresh_tokens;
+DROP TABLE refresh_tokens;
+ALTER TABLE refresh_tokens_new RENAME TO refresh_tokens;
+
+CREATE INDEX refresh_tokens__expires_at ON refresh_tokens (expires_at);
@@ -0,0 +1,9 @@
+-- DROP COLUMN needs SQLite 3.35 or later.
+DROP INDEX access_tokens__refresh_token_id;
+DROP INDEX refresh_tokens__parent_id;
+
+ALTER TABLE access_tokens DROP COLUMN revoked_at;
+ALTER TABLE access_tokens DROP COLUMN refresh_token_id;
+
+ALTER TABLE refresh_tokens DROP COLUMN revoked_at;
+ALTER TABLE refresh_tokens DROP COLUMN parent_id;
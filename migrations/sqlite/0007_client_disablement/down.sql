@@ -0,0 +1,2 @@
+-- DROP COLUMN needs SQLite 3.35 or later.
+ALTER TABLE clients DROP COLUMN disabled_at;
@@ -0,0 +1,8 @@
+-- Links every token to the authorization code that started its grant, so that revoking the code
+-- revokes the whole grant. `auth_code_id` is inherited by every descendant of a code, and is not a
+-- foreign key as codes are deleted once used.
+ALTER TABLE refresh_tokens ADD COLUMN auth_code_id INTEGER;
+ALTER TABLE access_tokens ADD COLUMN auth_code_id INTEGER;
+
+CREATE INDEX refresh_tokens__auth_code_id ON refresh_tokens (auth_code_id);
+CREATE INDEX access_tokens__auth_code_id ON access_tokens (auth_code_id);
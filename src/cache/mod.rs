@@ -0,0 +1,465 @@
+//! The cache module holds the in-process introspection cache. Introspection is
+//! the hottest endpoint the provider serves, so token lookups are kept in a
+//! bounded LRU map to avoid a database round trip for every request.
+//!
+//! Entries never outlive the token they describe, and are additionally capped
+//! by the configured `max_ttl` so that out-of-band changes are eventually
+//! picked up. Anything that revokes tokens or disables a client must call
+//! `invalidate_ids` or `invalidate_client` so the cache never answers for stale
+//! data. Its hit and miss counters are served on `GET /admin/cache`.
+//! Revocations made by other instances or from the command line are only
+//! noticed through `sync_revocations`, on every hit unless a check interval is
+//! configured.
+//!
+//! Entries are keyed by token digest, like the tokens in the database, so the
+//! cache never holds a usable token either.
+
+mod reuse;
+
+pub use self::reuse::ReusableTokens;
+
+use crate::models::configuration::CacheSettings;
+use crate::models::db::AccessToken;
+use crate::storage::StorageResult;
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use chrono::offset::Utc;
+use linked_hash_map::LinkedHashMap;
+use std::cmp;
+use std::ops::Add;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+struct CacheEntry {
+    token: AccessToken,
+    cached_until: NaiveDateTime,
+}
+
+// The latest revocation the cache knows about, and when it last asked.
+#[derive(Default)]
+struct RevocationMark {
+    latest: Option<i32>,
+    checked_at: Option<NaiveDateTime>,
+}
+
+/// Point in time counters describing how well the cache is performing.
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+// The limits are atomics, so that a configuration reload can change them
+// while requests are being served.
+pub struct IntrospectionCache {
+    capacity: AtomicUsize,
+    max_ttl: AtomicI64,
+    revocation_check_interval: AtomicI64,
+    entries: Mutex<LinkedHashMap<String, CacheEntry>>,
+    revocations: Mutex<RevocationMark>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl IntrospectionCache {
+    /// Creates a new cache holding at most `capacity` tokens, each for no
+    /// longer than `max_ttl` seconds, which checks for revocations on every
+    /// hit, or at most once every `revocation_check_interval` seconds when
+    /// that is not 0. A non-zero interval saves a query per hit, but a token
+    /// revoked elsewhere may be answered for as active until it is up. A
+    /// disabled cache holds nothing.
+    pub fn new(settings: &CacheSettings) -> IntrospectionCache {
+        IntrospectionCache {
+            capacity: AtomicUsize::new(capacity(settings)),
+            max_ttl: AtomicI64::new(settings.max_ttl),
+            revocation_check_interval: AtomicI64::new(settings.revocation_check_interval),
+            entries: Mutex::new(LinkedHashMap::new()),
+            revocations: Mutex::new(RevocationMark::default()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Applies reloaded settings. Entries over the new capacity are evicted
+    /// straight away, while the entries left keep the expiry they were cached
+    /// with.
+    pub fn reconfigure(&self, settings: &CacheSettings) {
+        let capacity = capacity(settings);
+        self.max_ttl.store(settings.max_ttl, Ordering::Relaxed);
+        self.revocation_check_interval
+            .store(settings.revocation_check_interval, Ordering::Relaxed);
+        self.capacity.store(capacity, Ordering::Relaxed);
+
+        let mut entries = self.entries();
+        while entries.len() > capacity {
+            entries.pop_front();
+        }
+    }
+
+    /// Looks up a token by its digest, refreshing its position in the LRU on a
+    /// hit.
+    ///
+    /// Returns: Option<AccessToken>
+    /// - Some(AccessToken) --- the cached token, which is still fresh
+    /// - None              --- the caller needs to consult the database
+    pub fn get(&self, token_hash: &str) -> Option<AccessToken> {
+        if self.capacity() == 0 {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let now = Utc::now().naive_utc();
+        let mut entries = self.entries();
+        let fresh = match entries.get_refresh(token_hash) {
+            Some(entry) => {
+                if entry.cached_until > now {
+                    Some(entry.token.clone())
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        match fresh {
+            Some(access_token) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(access_token)
+            }
+            None => {
+                entries.remove(token_hash);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Stores a token under its digest, evicting the least recently used entry
+    /// if the cache is full. Tokens that have already expired are not cached.
+    pub fn insert(&self, token_hash: &str, access_token: &AccessToken) {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return;
+        }
+
+        let now = Utc::now().naive_utc();
+        let max_ttl = Duration::seconds(self.max_ttl.load(Ordering::Relaxed));
+        let cached_until = cmp::min(access_token.expires_at, now.add(max_ttl));
+        if cached_until <= now {
+            return;
+        }
+
+        let mut entries = self.entries();
+        entries.insert(
+            token_hash.to_owned(),
+            CacheEntry {
+                // Legacy tokens still carry their plaintext, which the cache
+                // has no use for.
+                token: AccessToken {
+                    token: None,
+                    ..access_token.clone()
+                },
+                cached_until,
+            },
+        );
+        while entries.len() > capacity {
+            entries.pop_front();
+        }
+    }
+
+    /// Drops the tokens with the given ids. Must be called when tokens are
+    /// revoked by id rather than by their plaintext.
+    pub fn invalidate_ids(&self, ids: &[i32]) {
+        let mut entries = self.entries();
+        let stale: Vec<String> = entries
+            .iter()
+            .filter(|&(_, entry)| ids.contains(&entry.token.id))
+            .map(|(token_hash, _)| token_hash.clone())
+            .collect();
+        for token_hash in &stale {
+            entries.remove(token_hash);
+        }
+    }
+
+    /// Drops every token belonging to the given client. Must be called when a
+    /// client is disabled, or has its tokens revoked in bulk, see
+    /// `utils::revocation`.
+    pub fn invalidate_client(&self, client_id: i32) {
+        let mut entries = self.entries();
+        let stale: Vec<String> = entries
+            .iter()
+            .filter(|&(_, entry)| entry.token.client_id == client_id)
+            .map(|(token_hash, _)| token_hash.clone())
+            .collect();
+        for token in &stale {
+            entries.remove(token);
+        }
+    }
+
+    /// Drops every cached token.
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+
+    /// Clears the cache if tokens have been revoked since it last looked, by
+    /// this instance or any other sharing the backend. `latest_revocation_id`
+    /// is only called once the check interval has passed, and the cache is
+    /// cleared if it fails, so a revoked token is never answered for once the
+    /// interval is up.
+    pub fn sync_revocations<F>(&self, latest_revocation_id: F)
+    where
+        F: FnOnce() -> StorageResult<Option<i32>>,
+    {
+        if self.capacity() == 0 {
+            return;
+        }
+
+        let now = Utc::now().naive_utc();
+        let interval = Duration::seconds(self.revocation_check_interval.load(Ordering::Relaxed));
+        let due = match self.revocations().checked_at {
+            Some(checked_at) => checked_at.add(interval) <= now,
+            None => true,
+        };
+        if !due {
+            return;
+        }
+
+        // The lock is not held across the lookup, so a slow backend does not
+        // hold up every other introspection request.
+        match latest_revocation_id() {
+            Ok(latest) => {
+                let mut mark = self.revocations();
+                if mark.latest != latest {
+                    debug!("Tokens were revoked, clearing the introspection cache");
+                    self.clear();
+                }
+                mark.latest = latest;
+                mark.checked_at = Some(now);
+            }
+            Err(e) => {
+                warn!("Unable to check for revocations, clearing the introspection cache: {}", e);
+                self.clear();
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries().len(),
+            capacity: self.capacity(),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    // A panic while holding the lock cannot leave the map half-updated in a
+    // way that matters to us, so recover the guard rather than poisoning every
+    // subsequent introspection request.
+    fn entries(&self) -> MutexGuard<'_, LinkedHashMap<String, CacheEntry>> {
+        match self.entries.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn revocations(&self) -> MutexGuard<'_, RevocationMark> {
+        match self.revocations.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+// Disabling the cache leaves it without room for anything.
+fn capacity(settings: &CacheSettings) -> usize {
+    if settings.enabled {
+        settings.capacity
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageError;
+    use std::cell::Cell;
+
+    fn cache(capacity: usize, max_ttl: i64, revocation_check_interval: i64) -> IntrospectionCache {
+        IntrospectionCache::new(&CacheSettings {
+            enabled: true,
+            capacity,
+            max_ttl,
+            revocation_check_interval,
+        })
+    }
+
+    fn token(id: i32, client_id: i32, expires_in: i64) -> AccessToken {
+        let now = Utc::now().naive_utc();
+        AccessToken {
+            id,
+            token: None,
+            token_hash: Some(format!("hash-{}", id)),
+            client_id,
+            grant_id: 1,
+            scope: "read".to_owned(),
+            issued_at: now,
+            expires_at: now + Duration::seconds(expires_in),
+            refresh_token_id: None,
+            auth_code_id: None,
+            revoked_at: None,
+        }
+    }
+
+    fn cached(cache: &IntrospectionCache, ids: &[i32]) -> Vec<bool> {
+        ids.iter()
+            .map(|id| cache.get(&format!("hash-{}", id)).is_some())
+            .collect()
+    }
+
+    fn insert(cache: &IntrospectionCache, token: &AccessToken) {
+        cache.insert(token.token_hash.as_ref().unwrap(), token);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_token() {
+        let cache = cache(2, 60, 0);
+        insert(&cache, &token(1, 1, 600));
+        insert(&cache, &token(2, 1, 600));
+        assert!(cache.get("hash-1").is_some());
+
+        insert(&cache, &token(3, 1, 600));
+        assert_eq!(cached(&cache, &[1, 2, 3]), vec![true, false, true]);
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn caps_entries_at_the_token_expiry_and_max_ttl() {
+        let cache = cache(10, 60, 0);
+        let short = token(1, 1, 10);
+        let long = token(2, 1, 600);
+        insert(&cache, &short);
+        insert(&cache, &long);
+
+        let entries = cache.entries();
+        assert_eq!(entries["hash-1"].cached_until, short.expires_at);
+        let max = Utc::now().naive_utc() + Duration::seconds(60);
+        assert!(entries["hash-2"].cached_until <= max);
+        assert!(entries["hash-2"].cached_until < long.expires_at);
+    }
+
+    #[test]
+    fn drops_stale_entries_on_lookup() {
+        let cache = cache(10, 60, 0);
+        insert(&cache, &token(1, 1, 600));
+        cache.entries().get_mut("hash-1").unwrap().cached_until =
+            Utc::now().naive_utc() - Duration::seconds(1);
+
+        assert!(cache.get("hash-1").is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn does_not_cache_expired_tokens() {
+        let cache = cache(10, 60, 0);
+        insert(&cache, &token(1, 1, -1));
+        assert_eq!(cache.stats().entries, 0);
+
+        let no_ttl = self::cache(10, 0, 0);
+        insert(&no_ttl, &token(2, 1, 600));
+        assert_eq!(no_ttl.stats().entries, 0);
+    }
+
+    #[test]
+    fn a_disabled_cache_holds_nothing() {
+        let cache = IntrospectionCache::new(&CacheSettings {
+            enabled: false,
+            ..CacheSettings::default()
+        });
+        insert(&cache, &token(1, 1, 600));
+        assert!(cache.get("hash-1").is_none());
+        assert_eq!(cache.stats().capacity, 0);
+    }
+
+    #[test]
+    fn shrinking_the_capacity_evicts_entries() {
+        let cache = cache(3, 60, 0);
+        for id in 1..=3 {
+            insert(&cache, &token(id, 1, 600));
+        }
+        cache.reconfigure(&CacheSettings {
+            capacity: 1,
+            ..CacheSettings::default()
+        });
+        assert_eq!(cached(&cache, &[1, 2, 3]), vec![false, false, true]);
+    }
+
+    #[test]
+    fn invalidates_tokens_by_id_and_client() {
+        let cache = cache(10, 60, 0);
+        insert(&cache, &token(1, 1, 600));
+        insert(&cache, &token(2, 1, 600));
+        insert(&cache, &token(3, 2, 600));
+        insert(&cache, &token(4, 3, 600));
+
+        cache.invalidate_ids(&[1]);
+        assert_eq!(cached(&cache, &[1, 2, 3, 4]), vec![false, true, true, true]);
+        cache.invalidate_client(1);
+        assert_eq!(cached(&cache, &[2, 3, 4]), vec![false, true, true]);
+        cache.clear();
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn clears_when_tokens_were_revoked_elsewhere() {
+        let cache = cache(10, 60, 0);
+        cache.sync_revocations(|| Ok(Some(1)));
+        insert(&cache, &token(1, 1, 600));
+
+        cache.sync_revocations(|| Ok(Some(1)));
+        assert_eq!(cache.stats().entries, 1);
+        cache.sync_revocations(|| Ok(Some(2)));
+        assert_eq!(cache.stats().entries, 0);
+
+        insert(&cache, &token(1, 1, 600));
+        cache.sync_revocations(|| Err(StorageError::Unavailable("down".to_owned())));
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn checks_for_revocations_once_per_interval() {
+        let cache = cache(10, 60, 3600);
+        let checks = Cell::new(0);
+        let check = || {
+            checks.set(checks.get() + 1);
+            Ok(None)
+        };
+        cache.sync_revocations(check);
+        cache.sync_revocations(check);
+        assert_eq!(checks.get(), 1);
+
+        let every_time = self::cache(10, 60, 0);
+        every_time.sync_revocations(check);
+        every_time.sync_revocations(check);
+        assert_eq!(checks.get(), 3);
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = cache(10, 60, 0);
+        insert(&cache, &token(1, 1, 600));
+        cache.get("hash-1");
+        cache.get("hash-1");
+        cache.get("hash-2");
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert_eq!((stats.entries, stats.capacity), (1, 10));
+    }
+}
@@ -0,0 +1,47 @@
+//! Access token reuse (see `utils::find_reusable_access_token`) has to hand
+//! the client a token it was given before, but the database only keeps
+//! digests. The plaintext of tokens issued to clients that opt into reuse is
+//! therefore kept in memory by the instance that issued them, until they
+//! expire. Another instance, or this one after a restart, simply issues a new
+//! token instead.
+
+use chrono::NaiveDateTime;
+use chrono::offset::Utc;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+#[derive(Default)]
+pub struct ReusableTokens {
+    tokens: Mutex<HashMap<i32, (String, NaiveDateTime)>>,
+}
+
+impl ReusableTokens {
+    /// Remembers the plaintext of the access token with the given id, until
+    /// it expires. Expired tokens are dropped along the way.
+    pub fn remember(&self, id: i32, token: &str, expires_at: NaiveDateTime) {
+        let now = Utc::now().naive_utc();
+        let mut tokens = self.tokens();
+        tokens.retain(|_, &mut (_, expiry)| expiry > now);
+        tokens.insert(id, (token.to_owned(), expires_at));
+    }
+
+    /// Returns: Option<String> --- the plaintext of the access token with the
+    /// given id, if this instance issued it.
+    pub fn plaintext(&self, id: i32) -> Option<String> {
+        self.tokens().get(&id).map(|(token, _)| token.clone())
+    }
+
+    /// Forgets a token. Must be called when a token is revoked.
+    pub fn forget(&self, id: i32) {
+        self.tokens().remove(&id);
+    }
+
+    // As with the introspection cache, a panic while holding the lock cannot
+    // leave the map in a state that matters.
+    fn tokens(&self) -> MutexGuard<'_, HashMap<i32, (String, NaiveDateTime)>> {
+        match self.tokens.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
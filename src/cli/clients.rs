@@ -0,0 +1,70 @@
+//! `oa2p clients` disables compromised clients, revoking every token they
+//! hold, and enables them again.
+
+use crate::cli;
+use crate::storage::{self, Storage};
+use crate::utils::revocation::{self, Audit};
+use std::env;
+
+const USAGE: &str = "Usage:
+    oa2p clients disable <identifier> [--reason <text>]
+    oa2p clients enable <identifier> [--reason <text>]
+
+A disabled client cannot authenticate, and every token it held is revoked along with everything
+derived from it. Enabling the client again does not bring its tokens back.";
+
+pub fn run(args: &[String]) -> i32 {
+    let store = match storage::connect() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Unable to connect to the database: {}", e);
+            return 1;
+        }
+    };
+
+    let result = match args.first().map(|a| a.as_str()) {
+        Some("disable") if args.len() >= 2 => disable(&*store, &args[1], &args[2..]),
+        Some("enable") if args.len() >= 2 => enable(&*store, &args[1], &args[2..]),
+        _ => Err(USAGE.to_owned()),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            1
+        }
+    }
+}
+
+fn disable(store: &dyn Storage, identifier: &str, args: &[String]) -> Result<(), String> {
+    let revoked = revocation::disable_client(store, identifier, &audit(args)?)
+        .map_err(|e| format!("Unable to disable the client: {}", e))?;
+    println!(
+        "Disabled {}, revoking {} access tokens and {} refresh tokens.",
+        identifier, revoked.access_tokens, revoked.refresh_tokens
+    );
+    Ok(())
+}
+
+fn enable(store: &dyn Storage, identifier: &str, args: &[String]) -> Result<(), String> {
+    revocation::enable_client(store, identifier, &audit(args)?)
+        .map_err(|e| format!("Unable to enable the client: {}", e))?;
+    println!("Enabled {}.", identifier);
+    Ok(())
+}
+
+// Like revocations, changes from the command line are audited under the name
+// of the user running it.
+fn audit(args: &[String]) -> Result<Audit, String> {
+    let mut flags = cli::parse_flags(args)?;
+    if let Some(key) = flags.keys().find(|k| *k != "reason") {
+        return Err(format!("unknown flag `--{}`\n\n{}", key, USAGE));
+    }
+
+    let user = env::var("USER").unwrap_or_else(|_| "unknown".to_owned());
+    Ok(Audit {
+        actor: format!("cli:{}", user),
+        reason: flags.remove("reason"),
+    })
+}
@@ -0,0 +1,185 @@
+//! `oa2p lifetimes` manages the `token_lifetimes` overrides, which shorten or
+//! lengthen tokens for particular clients, grant types and scopes without
+//! touching the defaults in the config file.
+
+use crate::cli;
+use crate::models::db::*;
+use crate::storage::{self, Storage, StorageError};
+
+const USAGE: &str = "Usage:
+    oa2p lifetimes list
+    oa2p lifetimes set [--client <identifier>] [--grant <grant type>] [--scope <scope>]
+                       [--access-ttl <seconds>] [--refresh-ttl <seconds>]
+    oa2p lifetimes unset <id>
+
+An override without --client, --grant or --scope applies to every client, grant type or
+scope respectively. Lifetimes must be positive, except that a --refresh-ttl of -1
+disables the refresh token idle timeout.";
+
+pub fn run(args: &[String]) -> i32 {
+    let store = match storage::connect() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Unable to connect to the database: {}", e);
+            return 1;
+        }
+    };
+
+    let result = match args.first().map(|a| a.as_str()) {
+        Some("list") => list(&*store),
+        Some("set") => set(&*store, &args[1..]),
+        Some("unset") if args.len() == 2 => unset(&*store, &args[1]),
+        _ => Err(USAGE.to_owned()),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            1
+        }
+    }
+}
+
+fn list(store: &dyn Storage) -> Result<(), String> {
+    let overrides = store.list_token_lifetimes().map_err(|e| e.to_string())?;
+
+    println!("id\tclient\tgrant\tscope\taccess_ttl\trefresh_ttl");
+    for o in overrides {
+        let client = match o.client_id {
+            Some(id) => store
+                .find_client(id)
+                .map_err(|e| e.to_string())?
+                .identifier,
+            None => "*".to_owned(),
+        };
+        let grant = match o.grant_id {
+            Some(id) => store
+                .find_grant_type(id)
+                .map_err(|e| e.to_string())?
+                .name,
+            None => "*".to_owned(),
+        };
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            o.id,
+            client,
+            grant,
+            o.scope.unwrap_or_else(|| "*".to_owned()),
+            display_ttl(o.access_token_ttl),
+            display_ttl(o.refresh_token_ttl)
+        );
+    }
+    Ok(())
+}
+
+fn set(store: &dyn Storage, args: &[String]) -> Result<(), String> {
+    let flags = cli::parse_flags(args)?;
+    for key in flags.keys() {
+        match key.as_str() {
+            "client" | "grant" | "scope" | "access-ttl" | "refresh-ttl" => {}
+            _ => return Err(format!("unknown flag `--{}`\n\n{}", key, USAGE)),
+        }
+    }
+
+    let client_id = match flags.get("client") {
+        Some(identifier) => Some(store
+            .find_client_by_identifier(identifier)
+            .map_err(|_| format!("no client with identifier `{}`", identifier))?
+            .id),
+        None => None,
+    };
+    let grant_id = match flags.get("grant") {
+        Some(name) => Some(store
+            .find_grant_type_by_name(name)
+            .map_err(|_| format!("no grant type named `{}`", name))?
+            .id),
+        None => None,
+    };
+    let scope = flags.get("scope").cloned();
+    let access_token_ttl = parse_ttl(flags.get("access-ttl"), false)?;
+    let refresh_token_ttl = parse_ttl(flags.get("refresh-ttl"), true)?;
+    if access_token_ttl.is_none() && refresh_token_ttl.is_none() {
+        return Err(format!(
+            "at least one of --access-ttl or --refresh-ttl is required\n\n{}",
+            USAGE
+        ));
+    }
+
+    // Setting the same client, grant type and scope again replaces the old
+    // override rather than adding a competing one.
+    let new_override = NewTokenLifetimeBuilder::default()
+        .client_id(client_id)
+        .grant_id(grant_id)
+        .scope(scope)
+        .access_token_ttl(access_token_ttl)
+        .refresh_token_ttl(refresh_token_ttl)
+        .build()?;
+    let saved = store
+        .save_token_lifetime(&new_override)
+        .map_err(|e| e.to_string())?;
+    println!("Saved token lifetime override {}", saved.id);
+    Ok(())
+}
+
+fn unset(store: &dyn Storage, id: &str) -> Result<(), String> {
+    let id = id.parse::<i32>()
+        .map_err(|_| format!("`{}` is not an override id", id))?;
+
+    match store.delete_token_lifetime(id) {
+        Ok(()) => {
+            println!("Removed token lifetime override {}", id);
+            Ok(())
+        }
+        Err(StorageError::NotFound) => Err(format!("no token lifetime override with id {}", id)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Only a refresh token may go without an (idle) expiry, written as -1.
+fn parse_ttl(value: Option<&String>, allow_never: bool) -> Result<Option<i32>, String> {
+    match value {
+        Some(v) => match v.parse::<i32>() {
+            Ok(ttl) if ttl > 0 || (allow_never && ttl == -1) => Ok(Some(ttl)),
+            _ => Err(format!("`{}` is not a valid lifetime in seconds", v)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn display_ttl(ttl: Option<i32>) -> String {
+    match ttl {
+        Some(t) => t.to_string(),
+        None => "-".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str, allow_never: bool) -> Result<Option<i32>, String> {
+        parse_ttl(Some(&value.to_owned()), allow_never)
+    }
+
+    #[test]
+    fn accepts_positive_lifetimes() {
+        assert_eq!(parse("60", false), Ok(Some(60)));
+        assert_eq!(parse("60", true), Ok(Some(60)));
+        assert_eq!(parse_ttl(None, false), Ok(None));
+    }
+
+    #[test]
+    fn accepts_minus_one_only_for_refresh_tokens() {
+        assert_eq!(parse("-1", true), Ok(Some(-1)));
+        assert!(parse("-1", false).is_err());
+    }
+
+    #[test]
+    fn refuses_other_lifetimes() {
+        for value in &["0", "-2", "soon", ""] {
+            assert!(parse(value, false).is_err());
+            assert!(parse(value, true).is_err());
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! `oa2p migrate` creates and upgrades the database schema from the
+//! migrations embedded in the binary.
+
+use crate::SETTINGS;
+use crate::migrations;
+use crate::storage::{self, Storage};
+
+const USAGE: &str = "Usage:
+    oa2p migrate status
+    oa2p migrate up
+    oa2p migrate down
+    oa2p migrate baseline <version>
+
+`up` applies every pending migration and `down` reverts the most recent one. `baseline`
+records every migration up to and including <version> as applied without running it, for
+databases created from the old schema files.";
+
+pub fn run(args: &[String]) -> i32 {
+    let store = match storage::connect() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Unable to connect to the database: {}", e);
+            return 1;
+        }
+    };
+
+    let result = match args.first().map(|a| a.as_str()) {
+        Some("status") => status(&*store),
+        Some("up") => up(&*store),
+        Some("down") => down(&*store),
+        Some("baseline") if args.len() == 2 => baseline(&*store, &args[1]),
+        _ => Err(USAGE.to_owned()),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            1
+        }
+    }
+}
+
+fn status(store: &dyn Storage) -> Result<(), String> {
+    let status = migrations::status(store, SETTINGS.db.backend).map_err(|e| e.to_string())?;
+
+    println!("version\tstate\tname");
+    for m in &status.applied {
+        println!("{}\tapplied\t{}", m.version, m.name);
+    }
+    for m in &status.pending {
+        println!("{}\tpending\t{}", m.version, m.name);
+    }
+    for v in &status.unknown {
+        println!("{}\tunknown\t(applied by a newer release)", v);
+    }
+    Ok(())
+}
+
+fn up(store: &dyn Storage) -> Result<(), String> {
+    let status = migrations::status(store, SETTINGS.db.backend).map_err(|e| e.to_string())?;
+    if status.is_current() {
+        println!("The schema is up to date.");
+        return Ok(());
+    }
+
+    for m in status.pending {
+        println!("Applying {}", m.name);
+        store
+            .apply_migration(m)
+            .map_err(|e| format!("Migration {} failed: {}", m.name, e))?;
+    }
+    Ok(())
+}
+
+fn down(store: &dyn Storage) -> Result<(), String> {
+    let status = migrations::status(store, SETTINGS.db.backend).map_err(|e| e.to_string())?;
+    if !status.unknown.is_empty() {
+        return Err(
+            "The database has migrations applied by a newer release; revert them with that release first."
+                .to_owned(),
+        );
+    }
+
+    match status.applied.last() {
+        Some(m) => {
+            println!("Reverting {}", m.name);
+            store
+                .revert_migration(m)
+                .map_err(|e| format!("Reverting {} failed: {}", m.name, e))
+        }
+        None => {
+            println!("No migrations have been applied.");
+            Ok(())
+        }
+    }
+}
+
+fn baseline(store: &dyn Storage, version: &str) -> Result<(), String> {
+    let version = version
+        .parse::<i32>()
+        .map_err(|_| format!("`{}` is not a migration version", version))?;
+    let status = migrations::status(store, SETTINGS.db.backend).map_err(|e| e.to_string())?;
+
+    for m in status.pending.into_iter().filter(|m| m.version <= version) {
+        println!("Marking {} as applied", m.name);
+        store.mark_migration(m).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! The cli module holds the administrative subcommands of the `oa2p` binary.
+//! Running the binary without any arguments starts the provider as usual;
+//! anything else is dispatched to one of the subcommands here, which share
+//! the provider's configuration and database pool but never start a server.
+
+pub mod clients;
+pub mod lifetimes;
+pub mod migrate;
+pub mod revoke;
+
+use std::collections::HashMap;
+
+const USAGE: &str = "Usage:
+    oa2p                      Start the OAuth 2.0 provider
+    oa2p clients <command>    Disable or enable a client
+    oa2p lifetimes <command>  Manage token lifetime overrides
+    oa2p migrate <command>    Create or upgrade the database schema
+    oa2p revoke <kind> <id>   Revoke a token and everything derived from it
+
+Every command takes --config <path> before the command name to read a config file other than
+config.toml, as does the OA2P_CONFIG environment variable.";
+
+/// Runs the subcommand named by the first argument.
+///
+/// Returns: i32 --- the exit code for the process.
+pub fn run(args: &[String]) -> i32 {
+    match args[0].as_str() {
+        "clients" => clients::run(&args[1..]),
+        "lifetimes" => lifetimes::run(&args[1..]),
+        "migrate" => migrate::run(&args[1..]),
+        "revoke" => revoke::run(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    }
+}
+
+/// Splits a list of `--flag value` pairs into a map keyed by the flag name.
+///
+/// Returns: Result<HashMap<String, String>, String>
+/// - Ok(HashMap)  --- the flags and their values
+/// - Err(String)  --- a message describing the malformed argument
+pub fn parse_flags(args: &[String]) -> Result<HashMap<String, String>, String> {
+    let mut flags = HashMap::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            return Err(format!("unexpected argument `{}`", arg));
+        }
+        match iter.next() {
+            Some(value) => flags.insert(arg[2..].to_owned(), value.clone()),
+            None => return Err(format!("missing a value for `{}`", arg)),
+        };
+    }
+    Ok(flags)
+}
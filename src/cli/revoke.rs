@@ -0,0 +1,192 @@
+//! `oa2p revoke` revokes tokens along with everything derived from them,
+//! either a single token (by id or by the token itself) or authorization
+//! code, or every token matching a selection, and lists the audit trail of
+//! past revocations.
+
+use crate::cli;
+use crate::storage::{self, Storage};
+use crate::utils::revocation::{self, Audit, Revoked, Selection};
+use std::collections::HashMap;
+use std::env;
+use std::io;
+
+const USAGE: &str = "Usage:
+    oa2p revoke access-token <id> [--reason <text>]
+    oa2p revoke refresh-token <id> [--reason <text>]
+    oa2p revoke auth-code <id> [--reason <text>]
+    oa2p revoke token <token> [--reason <text>]
+    oa2p revoke tokens [--client <identifier>] [--scope <scope>] [--issued-after <time>]
+                       [--issued-before <time>] [--reason <text>] [--dry-run]
+    oa2p revoke all [--reason <text>] [--dry-run]
+    oa2p revoke log [--limit <count>]
+
+Revoking a refresh token also revokes the refresh tokens that replaced it and every access
+token issued with or minted from them. Revoking an authorization code deletes it and revokes
+every token issued for it. <id> is the row id, since the tokens themselves are not stored.
+`token` revokes an access or refresh token given the token itself, such as one found in a leak.
+Pass - to read it from standard input rather than leave it in the shell history.
+
+`tokens` revokes every live token matching all of the given criteria, and `all` revokes every
+live token, both along with everything derived from them. Times are RFC 3339, e.g.
+2018-03-01T12:00:00Z. --dry-run only counts the tokens that would be revoked.";
+
+pub fn run(args: &[String]) -> i32 {
+    let store = match storage::connect() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Unable to connect to the database: {}", e);
+            return 1;
+        }
+    };
+
+    let result = match args.first().map(|a| a.as_str()) {
+        Some("access-token") if args.len() >= 2 => single(&*store, &args[1..], |s, id, a| {
+            revocation::revoke_access_token(s, id, a)
+        }),
+        Some("refresh-token") if args.len() >= 2 => single(&*store, &args[1..], |s, id, a| {
+            revocation::revoke_refresh_token(s, id, a)
+        }),
+        Some("auth-code") if args.len() >= 2 => single(&*store, &args[1..], |s, id, a| {
+            revocation::revoke_auth_code(s, id, a)
+        }),
+        Some("token") if args.len() >= 2 => by_token(&*store, &args[1..]),
+        Some("tokens") => bulk(&*store, &args[1..], false),
+        Some("all") => bulk(&*store, &args[1..], true),
+        Some("log") => log(&*store, &args[1..]),
+        _ => Err(USAGE.to_owned()),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            1
+        }
+    }
+}
+
+fn single<F>(store: &dyn Storage, args: &[String], revoke: F) -> Result<(), String>
+where
+    F: Fn(&dyn Storage, i32, &Audit) -> storage::StorageResult<Revoked>,
+{
+    let id = args[0]
+        .parse::<i32>()
+        .map_err(|_| format!("`{}` is not an id", args[0]))?;
+    let flags = flags(&args[1..], &["reason"])?;
+
+    let revoked =
+        revoke(store, id, &audit(&flags)).map_err(|e| format!("Revocation failed: {}", e))?;
+    print_revoked(&revoked, false);
+    Ok(())
+}
+
+fn by_token(store: &dyn Storage, args: &[String]) -> Result<(), String> {
+    let token = match args[0].as_str() {
+        "-" => {
+            let mut token = String::new();
+            io::stdin()
+                .read_line(&mut token)
+                .map_err(|e| format!("Unable to read the token: {}", e))?;
+            token.trim().to_owned()
+        }
+        token => token.to_owned(),
+    };
+    let flags = flags(&args[1..], &["reason"])?;
+
+    let revoked = revocation::revoke_token(store, &token, &audit(&flags))
+        .map_err(|e| format!("Revocation failed: {}", e))?;
+    print_revoked(&revoked, false);
+    Ok(())
+}
+
+fn bulk(store: &dyn Storage, args: &[String], all: bool) -> Result<(), String> {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let args: Vec<String> = args.iter().filter(|a| *a != "--dry-run").cloned().collect();
+    let flags = if all {
+        flags(&args, &["reason"])?
+    } else {
+        flags(
+            &args,
+            &["client", "scope", "issued-after", "issued-before", "reason"],
+        )?
+    };
+
+    let selection = Selection {
+        client: flags.get("client").cloned(),
+        scope: flags.get("scope").cloned(),
+        issued_after: match flags.get("issued-after") {
+            Some(t) => Some(revocation::parse_time(t)?),
+            None => None,
+        },
+        issued_before: match flags.get("issued-before") {
+            Some(t) => Some(revocation::parse_time(t)?),
+            None => None,
+        },
+        all,
+    };
+
+    let revoked = revocation::revoke_matching(store, &selection, &audit(&flags), dry_run)
+        .map_err(|e| format!("Revocation failed: {}", e))?;
+    print_revoked(&revoked, dry_run);
+    Ok(())
+}
+
+fn log(store: &dyn Storage, args: &[String]) -> Result<(), String> {
+    let flags = flags(args, &["limit"])?;
+    let limit = match flags.get("limit") {
+        Some(l) => l
+            .parse::<i64>()
+            .map_err(|_| format!("`{}` is not a count", l))?,
+        None => 20,
+    };
+
+    let revocations = store.list_revocations(limit).map_err(|e| e.to_string())?;
+    println!("id\trevoked_at\tactor\taccess_tokens\trefresh_tokens\tselection\treason");
+    for r in revocations {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            r.id,
+            r.revoked_at,
+            r.actor,
+            r.access_tokens,
+            r.refresh_tokens,
+            r.selection,
+            r.reason.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+fn flags(args: &[String], allowed: &[&str]) -> Result<HashMap<String, String>, String> {
+    let flags = cli::parse_flags(args)?;
+    for key in flags.keys() {
+        if !allowed.contains(&key.as_str()) {
+            return Err(format!("unknown flag `--{}`\n\n{}", key, USAGE));
+        }
+    }
+    Ok(flags)
+}
+
+// Revocations from the command line are audited under the name of the user
+// running it.
+fn audit(flags: &HashMap<String, String>) -> Audit {
+    let user = env::var("USER").unwrap_or_else(|_| "unknown".to_owned());
+    Audit {
+        actor: format!("cli:{}", user),
+        reason: flags.get("reason").cloned(),
+    }
+}
+
+fn print_revoked(revoked: &Revoked, dry_run: bool) {
+    if dry_run {
+        println!(
+            "Would revoke {} access tokens and {} refresh tokens.",
+            revoked.access_tokens, revoked.refresh_tokens
+        );
+    } else {
+        println!(
+            "Revoked {} access tokens and {} refresh tokens.",
+            revoked.access_tokens, revoked.refresh_tokens
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! The health module answers whether the provider is able to serve requests,
+//! for orchestrators deciding whether to route traffic to an instance.
+//! Liveness needs nothing beyond the process answering at all, so only
+//! readiness is checked here.
+
+use crate::SETTINGS;
+use crate::migrations;
+use crate::shutdown;
+use crate::storage;
+use std::collections::BTreeMap;
+
+/// The outcome of a single readiness check.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn pass() -> Check {
+        Check {
+            ok: true,
+            detail: None,
+        }
+    }
+
+    fn fail(detail: String) -> Check {
+        Check {
+            ok: false,
+            detail: Some(detail),
+        }
+    }
+}
+
+/// Whether the provider is ready to serve requests, and the checks that
+/// decided it.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+/// Checks that the provider is not shutting down, that storage can be reached
+/// and that its schema is current.
+///
+/// Returns: Readiness --- ready only if every check passed.
+pub fn readiness() -> Readiness {
+    let mut checks = BTreeMap::new();
+
+    if shutdown::is_draining() {
+        checks.insert("shutdown", Check::fail("shutting down".to_owned()));
+    } else {
+        checks.insert("shutdown", Check::pass());
+    }
+
+    // Reading the applied migrations doubles as the round trip proving that
+    // storage is reachable.
+    let status =
+        storage::connect().and_then(|store| migrations::status(&*store, SETTINGS.db.backend));
+    match status {
+        Ok(status) => {
+            checks.insert("storage", Check::pass());
+            if status.is_current() {
+                checks.insert("schema", Check::pass());
+            } else {
+                let pending: Vec<&str> = status.pending.iter().map(|m| m.name).collect();
+                checks.insert(
+                    "schema",
+                    Check::fail(format!("pending migrations: {}", pending.join(", "))),
+                );
+            }
+        }
+        Err(e) => {
+            checks.insert("storage", Check::fail(e.to_string()));
+            checks.insert(
+                "schema",
+                Check::fail("unknown, storage is unreachable".to_owned()),
+            );
+        }
+    }
+
+    Readiness {
+        ready: checks.values().all(|c| c.ok),
+        checks,
+    }
+}
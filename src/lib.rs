@@ -0,0 +1,256 @@
+// Diesel 1.x's `table!` and `sql_function!` macros and derives implement
+// traits inside of functions.
+#![allow(non_local_definitions)]
+
+extern crate anyhow;
+extern crate base64;
+extern crate bcrypt;
+extern crate chrono;
+extern crate config;
+#[macro_use]
+extern crate lazy_static;
+extern crate libc;
+extern crate linked_hash_map;
+extern crate uuid;
+#[macro_use]
+extern crate diesel;
+extern crate r2d2;
+extern crate r2d2_diesel;
+extern crate ring;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate derive_builder;
+#[macro_use]
+extern crate rocket;
+#[macro_use]
+extern crate log;
+extern crate log4rs;
+extern crate regex;
+extern crate toml;
+extern crate ureq;
+
+use diesel::pg::PgConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use std::env;
+use std::process;
+
+mod cache;
+mod cli;
+mod health;
+mod logging;
+mod metrics;
+mod migrations;
+mod models;
+mod persistence;
+mod pruning;
+mod server;
+mod settings;
+mod shutdown;
+mod signals;
+mod storage;
+mod tracing;
+mod utils;
+mod web;
+
+lazy_static! {
+    static ref SETTINGS: models::configuration::AppSettings = settings::initial();
+}
+
+lazy_static! {
+    pub static ref DB_POOL: storage::pool::ClosablePool<ConnectionManager<PgConnection>> = {
+        let db_url = format!(
+            "postgres://{}:{}@{}:{}/{}",
+            &SETTINGS.db.user,
+            &SETTINGS.db.pass,
+            &SETTINGS.db.host,
+            SETTINGS.db.port,
+            &SETTINGS.db.db_name
+        );
+        debug!(
+            "db url: postgres://{}:[REDACTED]@{}:{}/{}",
+            &SETTINGS.db.user, &SETTINGS.db.host, SETTINGS.db.port, &SETTINGS.db.db_name
+        );
+        let manager = ConnectionManager::<PgConnection>::new(db_url);
+
+        let pool = Pool::builder()
+            .max_size(SETTINGS.db.pool_size)
+            .build(manager)
+            .expect("Failed to initialize the DB connection pool");
+        storage::pool::ClosablePool::new(pool)
+    };
+}
+
+lazy_static! {
+    pub static ref INTROSPECTION_CACHE: cache::IntrospectionCache =
+        cache::IntrospectionCache::new(&SETTINGS.cache);
+}
+
+lazy_static! {
+    pub static ref REUSABLE_TOKENS: cache::ReusableTokens = cache::ReusableTokens::default();
+}
+
+/// Builds the public listener of the provider without launching it. Besides
+/// being used by `run`, this lets other crates host the provider in-process,
+/// e.g. through `rocket::local::blocking::Client` in their integration tests,
+/// ideally with the `memory` storage backend, whose seed file is loaded here.
+/// Without an internal listener, it also serves the metrics and health routes.
+pub fn rocket() -> rocket::Rocket<rocket::Build> {
+    storage::init().expect("Invalid [db] settings");
+    let config = server::public_config().expect("Invalid [server] settings");
+    let mut rocket = rocket::custom(config)
+        .attach(shutdown::DrainRequests)
+        .attach(logging::RequestLog)
+        .mount(
+            "/",
+            routes![web::handlers::token::post, web::handlers::introspect::post],
+        );
+
+    if SETTINGS.tracing.enabled {
+        rocket = rocket.attach(tracing::TraceRequest);
+    }
+    if SETTINGS.metrics.enabled {
+        rocket = rocket.attach(metrics::RequestTimer);
+    }
+    if !SETTINGS.server.internal.enabled {
+        rocket = rocket.mount(
+            "/",
+            routes![web::handlers::health::healthz, web::handlers::health::readyz],
+        );
+        if SETTINGS.metrics.enabled {
+            rocket = rocket.mount("/", routes![web::handlers::metrics::get]);
+        }
+    }
+    rocket
+}
+
+/// Builds the internal listener of the provider, serving the admin, metrics
+/// and health routes, without launching it.
+pub fn internal_rocket() -> rocket::Rocket<rocket::Build> {
+    let config = server::internal_config().expect("Invalid [server.internal] settings");
+    let rocket = rocket::custom(config)
+        .attach(shutdown::DrainRequests)
+        .attach(logging::RequestLog)
+        .mount(
+            "/",
+            routes![
+                web::handlers::admin::revoke,
+                web::handlers::admin::history,
+                web::handlers::admin::disable_client,
+                web::handlers::admin::enable_client,
+                web::handlers::admin::cache,
+                web::handlers::health::healthz,
+                web::handlers::health::readyz,
+            ],
+        );
+
+    if SETTINGS.metrics.enabled {
+        rocket.mount("/", routes![web::handlers::metrics::get])
+    } else {
+        rocket
+    }
+}
+
+/// The entry point of the `oa2p` binary: runs the subcommand named on the
+/// command line, or launches the provider if there is none.
+pub fn run() {
+    let args = match settings::take_config_flag(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = settings::preload() {
+        eprintln!("Invalid configuration:\n{}", e);
+        process::exit(1);
+    }
+
+    if let Err(e) = logging::init(&SETTINGS.logging) {
+        eprintln!("Unable to set up logging: {}", e);
+        process::exit(1);
+    }
+
+    if let Err(msg) = storage::init() {
+        error!("{}", msg);
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+
+    if !args.is_empty() {
+        process::exit(cli::run(&args));
+    }
+
+    if let Err(msg) = check_schema() {
+        error!("{}", msg);
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+
+    if let Err(msg) = server::public_config().and(server::internal_config()) {
+        error!("{}", msg);
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+
+    if SETTINGS.pruning.enabled {
+        pruning::spawn().expect("Failed to start the pruning thread");
+    }
+    let exporter = if SETTINGS.tracing.enabled {
+        Some(tracing::spawn().expect("Failed to start the trace exporter thread"))
+    } else {
+        None
+    };
+
+    signals::install();
+    settings::watch().expect("Failed to start the configuration reloader thread");
+    shutdown::spawn().expect("Failed to start the shutdown thread");
+
+    if let Err(msg) = server::launch() {
+        error!("{}", msg);
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+    shutdown::finish(exporter);
+}
+
+/// Compares the database schema with the migrations embedded in the binary.
+/// A schema that is behind is only logged, unless `db.require_current_schema`
+/// is set.
+///
+/// Returns: Result<(), String>
+/// - Ok(())       --- the provider may start
+/// - Err(String)  --- why the provider must not start
+fn check_schema() -> Result<(), String> {
+    let store = storage::connect().map_err(|e| format!("Unable to connect to the database: {}", e))?;
+    let status = migrations::status(&*store, SETTINGS.db.backend)
+        .map_err(|e| format!("Unable to read the schema version: {}", e))?;
+
+    if !status.unknown.is_empty() {
+        warn!(
+            "The database has migrations {:?} applied, which this release does not know about",
+            status.unknown
+        );
+    }
+    if status.is_current() {
+        return Ok(());
+    }
+
+    let pending: Vec<&str> = status.pending.iter().map(|m| m.name).collect();
+    if SETTINGS.db.require_current_schema {
+        Err(format!(
+            "The database schema is behind, pending migrations: {}; run `oa2p migrate up`",
+            pending.join(", ")
+        ))
+    } else {
+        warn!(
+            "The database schema is behind, pending migrations: {}; run `oa2p migrate up`",
+            pending.join(", ")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use crate::logging::ACCESS_TARGET;
+use crate::server;
+use crate::tracing::{self, encode_hex};
+use chrono::offset::Utc;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::time::Instant;
+use uuid::Uuid;
+
+/// The header a request ID is taken from, and returned in.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// The ID and arrival time live with the request, since the worker thread
+// handling it may change at every await.
+struct Tagged {
+    request_id: Option<String>,
+    started: Instant,
+}
+
+/// Tags every request with an ID, and writes one JSON line per request to the
+/// access log.
+///
+/// The ID is taken from the `X-Request-Id` header when a proxy in front of
+/// the provider already set one, and generated otherwise. Either way it is
+/// returned in the `X-Request-Id` response header, and tags every message
+/// logged while the request is handled.
+pub struct RequestLog;
+
+/// The ID a request was tagged with, if it was.
+pub fn request_id_of(request: &Request<'_>) -> Option<String> {
+    tagged(request).request_id.clone()
+}
+
+fn tagged<'a>(request: &'a Request<'_>) -> &'a Tagged {
+    request.local_cache(|| Tagged {
+        request_id: None,
+        started: Instant::now(),
+    })
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        let request_id = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .and_then(|id| {
+                if is_valid_request_id(id) {
+                    Some(id.to_owned())
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+
+        request.local_cache(|| Tagged {
+            request_id: Some(request_id),
+            started: Instant::now(),
+        });
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let tagged = tagged(request);
+        let request_id = match tagged.request_id {
+            Some(ref request_id) => request_id.clone(),
+            None => return,
+        };
+        let elapsed = tagged.started.elapsed();
+
+        // The query string is left out, since it may carry credentials
+        let line = json!({
+            "time": Utc::now().to_rfc3339(),
+            "request_id": request_id,
+            "trace_id": tracing::span_context_of(request).map(|c| encode_hex(&c.trace_id)),
+            "method": request.method().as_str(),
+            "path": request.uri().path().as_str(),
+            "route": request.route().map(|route| route.uri.path()),
+            "status": response.status().code,
+            "duration_ms": elapsed.as_secs_f64() * 1e3,
+            "remote_addr": server::remote_addr(request).map(|addr| addr.ip().to_string()),
+            "user_agent": request.headers().get_one("User-Agent"),
+        });
+        info!(target: ACCESS_TARGET, "{}", line);
+
+        response.set_raw_header(REQUEST_ID_HEADER, request_id);
+    }
+}
+
+// Request IDs from outside end up in logs and response headers, so only short
+// IDs made of safe characters are taken over.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
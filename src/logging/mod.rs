@@ -0,0 +1,132 @@
+//! The logging module sets up log4rs from the `[logging]` section of the
+//! configuration, and sits between the `log` macros and log4rs. Every message
+//! passes through `redact` before any appender sees it, so that a token or
+//! password handed to a log macro by mistake never reaches a log file, and
+//! messages logged while a request is handled are tagged with its request ID.
+//!
+//! The `RequestLog` fairing assigns the request IDs and writes one JSON line
+//! per request to the `oa2p::access` target.
+
+mod fairing;
+mod output;
+mod redact;
+
+pub use self::fairing::{request_id_of, RequestLog};
+pub use self::redact::redact;
+
+use crate::models::configuration::LoggingSettings;
+use log::{self, Log, Metadata, Record};
+use std::cell::RefCell;
+use std::sync::RwLock;
+
+/// The target access log lines are written to, so that they can be routed to
+/// their own appender.
+pub const ACCESS_TARGET: &str = "oa2p::access";
+
+lazy_static! {
+    // The logger the redacting logger hands messages to, replaced whenever
+    // the configuration is reloaded.
+    static ref LOG4RS: RwLock<Option<log4rs::Logger>> = RwLock::new(None);
+}
+
+// The ID of the request being handled on this thread. Requests are handled on
+// async workers, so it is only set on the blocking thread doing a request's
+// work, see `web::context`.
+thread_local! {
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// The ID of the request being handled on this thread, if any.
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/// Tags the messages logged on this thread with a request ID, until the
+/// returned guard is dropped.
+pub fn enter(request_id: Option<String>) -> Entered {
+    REQUEST_ID.with(|id| *id.borrow_mut() = request_id);
+    Entered
+}
+
+/// Keeps messages tagged with a request ID, see `enter`.
+pub struct Entered;
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|id| *id.borrow_mut() = None);
+    }
+}
+
+/// Sets up log4rs as described by the `[logging]` section, behind the
+/// redacting logger.
+///
+/// Returns: Result<(), String>
+/// - Ok(())       --- logging is set up
+/// - Err(String)  --- a setting is invalid, the log file could not be opened,
+///   or a logger was already set up
+pub fn init(settings: &LoggingSettings) -> Result<(), String> {
+    reconfigure(settings)?;
+    log::set_boxed_logger(Box::new(RedactingLogger)).map_err(|e| e.to_string())
+}
+
+/// Replaces the log4rs configuration, e.g. when the configuration is
+/// reloaded. The current configuration stays in place if the new one cannot
+/// be set up.
+///
+/// Returns: Result<(), String>
+/// - Ok(())       --- messages go to the new configuration
+/// - Err(String)  --- a setting is invalid, or the log file could not be
+///   opened
+pub fn reconfigure(settings: &LoggingSettings) -> Result<(), String> {
+    let logger = log4rs::Logger::new(output::config(settings)?);
+    log::set_max_level(logger.max_log_level());
+    *LOG4RS.write().unwrap() = Some(logger); // TODO: remove unwrap
+    Ok(())
+}
+
+struct RedactingLogger;
+
+impl Log for RedactingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match LOG4RS.read() {
+            Ok(ref logger) => logger.as_ref().is_some_and(|l| l.enabled(metadata)),
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = record.args().to_string();
+        let message = redact(&message);
+        // Access log lines already carry the request ID as a field
+        let tag = match request_id() {
+            Some(ref id) if record.target() != ACCESS_TARGET => format!("[{}] ", id),
+            _ => String::new(),
+        };
+
+        if let Ok(ref logger) = LOG4RS.read() {
+            if let Some(ref logger) = **logger {
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("{}{}", tag, message))
+                        .metadata(record.metadata().clone())
+                        .module_path(record.module_path())
+                        .file(record.file())
+                        .line(record.line())
+                        .build(),
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(ref logger) = LOG4RS.read() {
+            if let Some(ref logger) = **logger {
+                logger.flush();
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::logging::ACCESS_TARGET;
+use crate::models::configuration::{LogDestination, LogFormat, LoggingSettings};
+use log::{LevelFilter, Record};
+use log4rs::append::Append;
+use log4rs::append::console::ConsoleAppender;
+use log4rs::append::file::FileAppender;
+use log4rs::append::rolling_file::RollingFileAppender;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
+use log4rs::config::{Appender, Config, Logger, Root};
+use log4rs::encode::json::JsonEncoder;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::{self, Encode};
+
+const APPENDER: &str = "output";
+
+/// Builds the log4rs configuration described by the `[logging]` section.
+///
+/// Returns: Result<Config, String>
+/// - Ok(Config)   --- the configuration to hand to log4rs
+/// - Err(String)  --- which setting is invalid, or why the log file could not
+///   be opened
+pub fn config(settings: &LoggingSettings) -> Result<Config, String> {
+    let root_level = parse_level("logging.level", &settings.level)?;
+
+    let mut builder =
+        Config::builder().appender(Appender::builder().build(APPENDER, appender(settings)?));
+    for (module, level) in &settings.modules {
+        let level = parse_level(&format!("logging.modules.{}", module), level)?;
+        builder = builder.logger(Logger::builder().build(module.as_str(), level));
+    }
+
+    builder
+        .build(Root::builder().appender(APPENDER).build(root_level))
+        .map_err(|e| e.to_string())
+}
+
+fn parse_level(setting: &str, level: &str) -> Result<LevelFilter, String> {
+    level.parse().map_err(|_| {
+        format!(
+            "{}: `{}` is not one of off, error, warn, info, debug or trace",
+            setting, level
+        )
+    })
+}
+
+fn appender(settings: &LoggingSettings) -> Result<Box<dyn Append>, String> {
+    let encoder = Box::new(LineEncoder {
+        inner: encoder(settings),
+    });
+
+    match settings.destination {
+        LogDestination::Stdout => Ok(Box::new(
+            ConsoleAppender::builder().encoder(encoder).build(),
+        )),
+        LogDestination::File => FileAppender::builder()
+            .encoder(encoder)
+            .build(&settings.path)
+            .map(|appender| Box::new(appender) as Box<dyn Append>)
+            .map_err(|e| format!("Unable to open the log file {}: {}", settings.path, e)),
+        LogDestination::RollingFile => {
+            // Rolled over files are numbered from newest to oldest, e.g.
+            // oa2p.log.0 is the file most recently rolled over.
+            let roller = FixedWindowRoller::builder()
+                .build(&format!("{}.{{}}", settings.path), settings.max_files)
+                .map_err(|e| format!("logging.path: {}", e))?;
+            let policy = CompoundPolicy::new(
+                Box::new(SizeTrigger::new(settings.max_size)),
+                Box::new(roller),
+            );
+
+            RollingFileAppender::builder()
+                .encoder(encoder)
+                .build(&settings.path, Box::new(policy))
+                .map(|appender| Box::new(appender) as Box<dyn Append>)
+                .map_err(|e| format!("Unable to open the log file {}: {}", settings.path, e))
+        }
+    }
+}
+
+fn encoder(settings: &LoggingSettings) -> Box<dyn Encode> {
+    match settings.format {
+        LogFormat::Json => Box::new(JsonEncoder::new()),
+        LogFormat::Text => {
+            // Levels are only coloured on a terminal, not in files
+            let level = if settings.destination == LogDestination::Stdout {
+                "{h({l})}"
+            } else {
+                "{l}"
+            };
+            Box::new(PatternEncoder::new(&format!(
+                "[{{d({})(utc)}}][{}][{{M}}] - {{m}}{{n}}",
+                settings.time_format, level
+            )))
+        }
+    }
+}
+
+// Access log lines are JSON already, and are written as they are whatever the
+// format of the other lines.
+#[derive(Debug)]
+struct LineEncoder {
+    inner: Box<dyn Encode>,
+}
+
+impl Encode for LineEncoder {
+    fn encode(
+        &self,
+        w: &mut dyn encode::Write,
+        record: &Record,
+    ) -> anyhow::Result<()> {
+        if record.target() == ACCESS_TARGET {
+            writeln!(w, "{}", record.args())?;
+            Ok(())
+        } else {
+            self.inner.encode(w, record)
+        }
+    }
+}
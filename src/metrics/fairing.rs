@@ -0,0 +1,43 @@
+use crate::metrics::METRICS;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::time::Instant;
+
+// The arrival time lives with the request, since the worker thread handling
+// it may change at every await.
+struct Started(Option<Instant>);
+
+/// Records how long every request took in `oa2p_request_duration_seconds`.
+/// Requests that match no route are counted under the route `unmatched`, so
+/// that probing for random paths cannot create any number of series.
+pub struct RequestTimer;
+
+#[rocket::async_trait]
+impl Fairing for RequestTimer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request timer",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        request.local_cache(|| Started(Some(Instant::now())));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let started = match request.local_cache(|| Started(None)).0 {
+            Some(started) => started,
+            None => return,
+        };
+
+        let route = request
+            .route()
+            .map_or("unmatched", |route| route.uri.path());
+        let status = response.status().code.to_string();
+        METRICS.request_seconds.observe(
+            &[request.method().as_str(), route, &status],
+            started.elapsed(),
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! The metrics module counts what the provider does and renders it in the
+//! Prometheus text exposition format, for `GET /metrics`.
+//!
+//! The handful of metric types needed here are kept in process, next to the
+//! statistics the cache and pruning modules already keep, rather than pulling
+//! in a client library. Labelled series are created on first use, so a label
+//! only ever shows up once it has been counted.
+
+mod fairing;
+
+pub use self::fairing::RequestTimer;
+
+use crate::INTROSPECTION_CACHE;
+use crate::SETTINGS;
+use crate::pruning::PRUNE_STATS;
+use crate::storage;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+// The default Prometheus client buckets, in seconds. They suit both request
+// latencies and bcrypt verification, which is tuned to take tens to hundreds
+// of milliseconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub struct Metrics {
+    pub tokens_issued: Counter,
+    pub tokens_revoked: Counter,
+    pub introspections: Counter,
+    pub oauth_errors: Counter,
+    pub bcrypt_seconds: Histogram,
+    pub request_seconds: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics {
+            tokens_issued: Counter::new(
+                "oa2p_tokens_issued_total",
+                "Tokens issued, by token type, grant type and client.",
+                &["token", "grant_type", "client"],
+            ),
+            tokens_revoked: Counter::new(
+                "oa2p_tokens_revoked_total",
+                "Tokens revoked, by token type.",
+                &["token"],
+            ),
+            introspections: Counter::new(
+                "oa2p_introspections_total",
+                "Introspection requests, by outcome and the reason a token was reported inactive.",
+                &["outcome", "reason"],
+            ),
+            oauth_errors: Counter::new(
+                "oa2p_oauth_errors_total",
+                "OAuth 2.0 error responses returned by the token endpoint, by error code.",
+                &["error"],
+            ),
+            bcrypt_seconds: Histogram::new(
+                "oa2p_bcrypt_verify_seconds",
+                "Time taken to verify client secrets.",
+                &[],
+            ),
+            request_seconds: Histogram::new(
+                "oa2p_request_duration_seconds",
+                "Time taken to handle requests, by method, route and status code.",
+                &["method", "route", "status"],
+            ),
+        }
+    }
+}
+
+/// A counter with one series per combination of label values.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    series: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl Counter {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Counter {
+        Counter {
+            name,
+            help,
+            labels,
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Adds one to the series with the given label values, which must be in
+    /// the order the labels were declared in.
+    pub fn inc(&self, values: &[&str]) {
+        self.inc_by(values, 1);
+    }
+
+    pub fn inc_by(&self, values: &[&str], amount: u64) {
+        let key = values.iter().map(|v| (*v).to_owned()).collect();
+        *lock(&self.series).entry(key).or_insert(0) += amount;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "counter");
+        for (values, count) in lock(&self.series).iter() {
+            writeln!(
+                out,
+                "{}{} {}",
+                self.name,
+                label_set(self.labels, values, None),
+                count
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// A histogram over `BUCKETS`, with one series per combination of label
+/// values.
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    series: Mutex<BTreeMap<Vec<String>, HistogramSeries>>,
+}
+
+#[derive(Default)]
+struct HistogramSeries {
+    // Observations per bucket, not yet cumulative.
+    buckets: [u64; 11],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Histogram {
+        Histogram {
+            name,
+            help,
+            labels,
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records how long something took in the series with the given label
+    /// values.
+    pub fn observe(&self, values: &[&str], elapsed: Duration) {
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        let key = values.iter().map(|v| (*v).to_owned()).collect();
+
+        let mut series = lock(&self.series);
+        let entry = series.entry(key).or_default();
+        if let Some(i) = BUCKETS.iter().position(|b| seconds <= *b) {
+            entry.buckets[i] += 1;
+        }
+        entry.count += 1;
+        entry.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "histogram");
+        for (values, series) in lock(&self.series).iter() {
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(series.buckets.iter()) {
+                cumulative += *count;
+                let le = bound.to_string();
+                writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    self.name,
+                    label_set(self.labels, values, Some(le.as_str())),
+                    cumulative
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "{}_bucket{} {}",
+                self.name,
+                label_set(self.labels, values, Some("+Inf")),
+                series.count
+            )
+            .unwrap();
+            let labels = label_set(self.labels, values, None);
+            writeln!(out, "{}_sum{} {}", self.name, labels, series.sum).unwrap();
+            writeln!(out, "{}_count{} {}", self.name, labels, series.count).unwrap();
+        }
+    }
+}
+
+/// Renders every metric, along with the statistics kept by the introspection
+/// cache, the pruning thread and the connection pool.
+///
+/// Returns: String --- the metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    METRICS.tokens_issued.render(&mut out);
+    METRICS.tokens_revoked.render(&mut out);
+    METRICS.introspections.render(&mut out);
+    METRICS.oauth_errors.render(&mut out);
+    METRICS.bcrypt_seconds.render(&mut out);
+    METRICS.request_seconds.render(&mut out);
+
+    let cache = INTROSPECTION_CACHE.stats();
+    let pruned = PRUNE_STATS.counts();
+    let mut samples = vec![
+        (
+            "oa2p_introspection_cache_hits_total",
+            "counter",
+            "Introspection cache hits.",
+            cache.hits,
+        ),
+        (
+            "oa2p_introspection_cache_misses_total",
+            "counter",
+            "Introspection cache misses.",
+            cache.misses,
+        ),
+        (
+            "oa2p_introspection_cache_entries",
+            "gauge",
+            "Tokens in the introspection cache.",
+            cache.entries,
+        ),
+        (
+            "oa2p_introspection_cache_capacity",
+            "gauge",
+            "The most tokens the introspection cache holds.",
+            cache.capacity,
+        ),
+        (
+            "oa2p_prune_runs_total",
+            "counter",
+            "Pruning runs that pruned every table.",
+            pruned.runs,
+        ),
+        (
+            "oa2p_prune_skipped_total",
+            "counter",
+            "Pruning runs skipped as another instance held the lock.",
+            pruned.skipped,
+        ),
+        (
+            "oa2p_prune_failures_total",
+            "counter",
+            "Pruning runs that failed.",
+            pruned.failures,
+        ),
+        (
+            "oa2p_pruned_access_tokens_total",
+            "counter",
+            "Expired access tokens deleted.",
+            pruned.access_tokens,
+        ),
+        (
+            "oa2p_pruned_refresh_tokens_total",
+            "counter",
+            "Expired refresh tokens deleted.",
+            pruned.refresh_tokens,
+        ),
+        (
+            "oa2p_pruned_auth_codes_total",
+            "counter",
+            "Expired authorization codes deleted.",
+            pruned.auth_codes,
+        ),
+    ];
+    if let Some(state) = storage::pool_state() {
+        samples.push((
+            "oa2p_db_pool_connections",
+            "gauge",
+            "Connections held by the database pool.",
+            state.connections as usize,
+        ));
+        samples.push((
+            "oa2p_db_pool_idle_connections",
+            "gauge",
+            "Idle connections in the database pool.",
+            state.idle_connections as usize,
+        ));
+        samples.push((
+            "oa2p_db_pool_max_connections",
+            "gauge",
+            "The most connections the database pool opens.",
+            SETTINGS.db.pool_size as usize,
+        ));
+    }
+
+    for (name, kind, help, value) in samples {
+        header(&mut out, name, help, kind);
+        writeln!(out, "{} {}", name, value).unwrap();
+    }
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn label_set(labels: &[&str], values: &[String], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .zip(values.iter())
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Counting carries on after a panic elsewhere; a series left half-updated
+// only skews one sample.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
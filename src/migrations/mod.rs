@@ -0,0 +1,121 @@
+//! The migrations module embeds the SQL scripts under `migrations/` in the
+//! binary, so a deployment only ever needs the `oa2p` executable to create or
+//! upgrade its schema. Every backend has its own scripts, but versions are
+//! kept in step so that version N means the same schema on all of them. The
+//! versions applied to a database are recorded in its `schema_migrations`
+//! table by the storage backend.
+
+use crate::models::configuration::DatabaseBackend;
+use crate::storage::{MigrationStore, StorageResult};
+
+/// A single schema change and the script undoing it.
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+macro_rules! migration {
+    ($backend:tt, $version:expr, $name:tt) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../../migrations/", $backend, "/", $name, "/up.sql")),
+            down: include_str!(concat!("../../migrations/", $backend, "/", $name, "/down.sql")),
+        }
+    };
+}
+
+static POSTGRES: [Migration; 9] = [
+    migration!("postgres", 1, "0001_initial"),
+    migration!("postgres", 2, "0002_client_policies_and_token_lifetimes"),
+    migration!("postgres", 3, "0003_expiry_indexes"),
+    migration!("postgres", 4, "0004_hashed_tokens"),
+    migration!("postgres", 5, "0005_token_lineage"),
+    migration!("postgres", 6, "0006_token_revocations"),
+    migration!("postgres", 7, "0007_client_disablement"),
+    migration!("postgres", 8, "0008_revocation_indexes"),
+    migration!("postgres", 9, "0009_auth_code_lineage"),
+];
+
+static SQLITE: [Migration; 9] = [
+    migration!("sqlite", 1, "0001_initial"),
+    migration!("sqlite", 2, "0002_client_policies_and_token_lifetimes"),
+    migration!("sqlite", 3, "0003_expiry_indexes"),
+    migration!("sqlite", 4, "0004_hashed_tokens"),
+    migration!("sqlite", 5, "0005_token_lineage"),
+    migration!("sqlite", 6, "0006_token_revocations"),
+    migration!("sqlite", 7, "0007_client_disablement"),
+    migration!("sqlite", 8, "0008_revocation_indexes"),
+    migration!("sqlite", 9, "0009_auth_code_lineage"),
+];
+
+static MYSQL: [Migration; 9] = [
+    migration!("mysql", 1, "0001_initial"),
+    migration!("mysql", 2, "0002_client_policies_and_token_lifetimes"),
+    migration!("mysql", 3, "0003_expiry_indexes"),
+    migration!("mysql", 4, "0004_hashed_tokens"),
+    migration!("mysql", 5, "0005_token_lineage"),
+    migration!("mysql", 6, "0006_token_revocations"),
+    migration!("mysql", 7, "0007_client_disablement"),
+    migration!("mysql", 8, "0008_revocation_indexes"),
+    migration!("mysql", 9, "0009_auth_code_lineage"),
+];
+
+/// The migrations for a backend, oldest first. The memory backend has no
+/// schema, so it has no migrations either.
+pub fn for_backend(backend: DatabaseBackend) -> &'static [Migration] {
+    match backend {
+        DatabaseBackend::Postgres => &POSTGRES,
+        DatabaseBackend::Sqlite => &SQLITE,
+        DatabaseBackend::Mysql => &MYSQL,
+        DatabaseBackend::Memory => &[],
+    }
+}
+
+/// Where a database stands relative to the migrations embedded in the binary.
+#[derive(Debug)]
+pub struct SchemaStatus {
+    /// The embedded migrations that have been applied, oldest first.
+    pub applied: Vec<&'static Migration>,
+    /// The embedded migrations that have not been applied yet, oldest first.
+    pub pending: Vec<&'static Migration>,
+    /// Applied versions this binary knows nothing about, i.e. the database
+    /// was migrated by a newer release.
+    pub unknown: Vec<i32>,
+}
+
+impl SchemaStatus {
+    pub fn is_current(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Compares the migrations applied to a database with the embedded ones.
+///
+/// Returns: StorageResult<SchemaStatus>
+/// - Ok(SchemaStatus)   --- the applied, pending and unknown migrations
+/// - Err(StorageError)  --- the applied versions could not be read
+pub fn status<S: MigrationStore + ?Sized>(
+    store: &S,
+    backend: DatabaseBackend,
+) -> StorageResult<SchemaStatus> {
+    let versions = store.applied_migrations()?;
+    let migrations = for_backend(backend);
+
+    let (applied, pending) = migrations
+        .iter()
+        .partition(|m| versions.contains(&m.version));
+    let unknown = versions
+        .into_iter()
+        .filter(|v| !migrations.iter().any(|m| m.version == *v))
+        .collect();
+
+    Ok(SchemaStatus {
+        applied,
+        pending,
+        unknown,
+    })
+}
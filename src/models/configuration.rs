@@ -1,27 +1,325 @@
-#[derive(Deserialize)]
+use std::collections::HashMap;
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct AppSettings {
+    #[serde(default)]
+    pub server: ServerSettings,
+    #[serde(default)]
     pub logging: LoggingSettings,
     pub db: DatabaseSettings,
     pub oauth: OauthSettings,
+    #[serde(default)]
+    pub cache: CacheSettings,
+    #[serde(default)]
+    pub pruning: PruningSettings,
+    #[serde(default)]
+    pub admin: AdminSettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    #[serde(default)]
+    pub tracing: TracingSettings,
+    #[serde(default)]
+    pub shutdown: ShutdownSettings,
+}
+
+// The OAuth routes are served on `address` and `port`, over TLS when
+// `tls.enabled`. The admin routes are only ever served by the internal
+// listener; without one, the metrics and health routes move to the public
+// listener.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub address: String,
+    pub port: u16,
+    pub workers: u16,
+    pub tls: TlsSettings,
+    pub internal: InternalListenerSettings,
+}
+
+impl Default for ServerSettings {
+    fn default() -> ServerSettings {
+        ServerSettings {
+            address: "127.0.0.1".to_owned(),
+            port: 8000,
+            workers: 16,
+            tls: TlsSettings::default(),
+            internal: InternalListenerSettings::default(),
+        }
+    }
+}
+
+// PEM files holding the certificate chain, leaf first, and its private key.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TlsSettings {
+    pub enabled: bool,
+    pub certs: String,
+    pub key: String,
 }
 
-#[derive(Debug, Deserialize)]
+impl Default for TlsSettings {
+    fn default() -> TlsSettings {
+        TlsSettings {
+            enabled: false,
+            certs: "tls/cert.pem".to_owned(),
+            key: "tls/key.pem".to_owned(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct InternalListenerSettings {
+    pub enabled: bool,
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for InternalListenerSettings {
+    fn default() -> InternalListenerSettings {
+        InternalListenerSettings {
+            enabled: true,
+            address: "127.0.0.1".to_owned(),
+            port: 9000,
+        }
+    }
+}
+
+// Module levels override `level` for the named module and everything below
+// it, e.g. `rocket = "warn"` or `"oa2p::access" = "off"`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct LoggingSettings {
+    pub level: String,
+    pub modules: HashMap<String, String>,
+    pub format: LogFormat,
+    pub destination: LogDestination,
     pub time_format: String,
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: u32,
 }
 
-#[derive(Debug, Deserialize)]
+impl Default for LoggingSettings {
+    fn default() -> LoggingSettings {
+        LoggingSettings {
+            level: "info".to_owned(),
+            modules: HashMap::new(),
+            format: LogFormat::Text,
+            destination: LogDestination::Stdout,
+            time_format: "%Y-%m-%d %H:%M:%S %Z".to_owned(),
+            path: "log/oa2p.log".to_owned(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogDestination {
+    Stdout,
+    File,
+    RollingFile,
+}
+
+// The connection details only used by some backends are optional, so that a
+// SQLite deployment does not need to fill in server settings and vice versa.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DatabaseSettings {
+    #[serde(default)]
+    pub backend: DatabaseBackend,
+    #[serde(default)]
     pub host: String,
+    #[serde(default)]
     pub port: u32,
+    #[serde(default)]
     pub user: String,
+    #[serde(default)]
     pub pass: String,
+    #[serde(default)]
     pub db_name: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub seed_file: String,
     pub pool_size: u32,
+    #[serde(default)]
+    pub require_current_schema: bool,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum DatabaseBackend {
+    #[default]
+    Postgres,
+    Sqlite,
+    Mysql,
+    Memory,
 }
 
-#[derive(Debug, Deserialize)]
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct OauthSettings {
     pub access_token_ttl: i64,
     pub refresh_token_ttl: i64,
+    #[serde(default = "default_refresh_token_max_lifetime")]
+    pub refresh_token_max_lifetime: i64,
+    #[serde(default = "default_online_refresh_token_ttl")]
+    pub online_refresh_token_ttl: i64,
+    #[serde(default)]
+    pub refresh_policy: RefreshPolicySettings,
+}
+
+fn default_refresh_token_max_lifetime() -> i64 {
+    -1
+}
+
+fn default_online_refresh_token_ttl() -> i64 {
+    86_400
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RefreshPolicySettings {
+    pub issue_for_grants: Vec<String>,
+    pub scope_defaults_to_original: bool,
+    pub rotate_on_refresh: bool,
+}
+
+impl Default for RefreshPolicySettings {
+    // Per RFC 6749 (4.4.3), `client_credentials` does not issue refresh tokens.
+    fn default() -> RefreshPolicySettings {
+        RefreshPolicySettings {
+            issue_for_grants: vec!["authorization_code".to_owned(), "password".to_owned()],
+            scope_defaults_to_original: true,
+            rotate_on_refresh: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    pub enabled: bool,
+    pub capacity: usize,
+    pub max_ttl: i64,
+    pub revocation_check_interval: i64,
+}
+
+impl Default for CacheSettings {
+    fn default() -> CacheSettings {
+        CacheSettings {
+            enabled: true,
+            capacity: 10_000,
+            max_ttl: 60,
+            revocation_check_interval: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PruningSettings {
+    pub enabled: bool,
+    pub interval: u64,
+    pub grace_period: i64,
+    pub batch_size: i64,
+}
+
+impl Default for PruningSettings {
+    fn default() -> PruningSettings {
+        PruningSettings {
+            enabled: true,
+            interval: 300,
+            grace_period: 86_400,
+            batch_size: 1_000,
+        }
+    }
+}
+
+// The admin API is only served to callers presenting one of these tokens,
+// which are kept as the hex SHA-256 digest of the token, keyed by the name
+// revocations are audited under.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AdminSettings {
+    pub tokens: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> MetricsSettings {
+        MetricsSettings { enabled: true }
+    }
+}
+
+// Spans are exported in batches, at least every `export_interval`
+// milliseconds, to `endpoint` with the `otlp` exporter or appended to `path`
+// with the `file` exporter.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TracingSettings {
+    pub enabled: bool,
+    pub exporter: TraceExporter,
+    pub endpoint: String,
+    pub path: String,
+    pub service_name: String,
+    pub sample_ratio: f64,
+    pub export_interval: u64,
+}
+
+impl Default for TracingSettings {
+    fn default() -> TracingSettings {
+        TracingSettings {
+            enabled: false,
+            exporter: TraceExporter::Otlp,
+            endpoint: "http://localhost:4318/v1/traces".to_owned(),
+            path: "traces.jsonl".to_owned(),
+            service_name: "oa2p".to_owned(),
+            sample_ratio: 1.0,
+            export_interval: 5_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceExporter {
+    Otlp,
+    File,
+}
+
+// On SIGTERM, readiness fails for `drain_delay` seconds before the listeners
+// stop. They then give the requests in flight `grace` seconds to finish, and
+// `mercy` more seconds to write their responses before closing connections.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    pub drain_delay: u64,
+    pub grace: u32,
+    pub mercy: u32,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> ShutdownSettings {
+        ShutdownSettings {
+            drain_delay: 5,
+            grace: 20,
+            mercy: 5,
+        }
+    }
 }
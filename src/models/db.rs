@@ -1,9 +1,9 @@
+use crate::persistence::*;
 use chrono::NaiveDateTime;
-use persistence::*;
 use std::fmt;
 use uuid::Uuid;
 
-#[derive(Builder, Serialize, Deserialize, Identifiable, Queryable, Associations)]
+#[derive(Builder, Clone, Serialize, Deserialize, Identifiable, Queryable, Associations)]
 #[builder(setter(into))]
 #[table_name = "clients"]
 pub struct Client {
@@ -11,19 +11,32 @@ pub struct Client {
     pub identifier: String,
     pub secret: String,
     pub response_type: String,
+    pub token_reuse_min_ttl: Option<i32>,
+    pub issue_refresh_tokens: Option<bool>,
+    pub rotate_refresh_tokens: Option<bool>,
+    /// When the client was disabled. Disabled clients cannot authenticate.
+    pub disabled_at: Option<NaiveDateTime>,
 }
 
 impl fmt::Debug for Client {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Client {{ id: {}, identifier: {}, secret: [REDACTED], response_type: {} }}",
-            self.id, self.identifier, self.response_type
+            "Client {{ id: {}, identifier: {}, secret: [REDACTED], response_type: {}, \
+             token_reuse_min_ttl: {:?}, issue_refresh_tokens: {:?}, \
+             rotate_refresh_tokens: {:?}, disabled_at: {:?} }}",
+            self.id,
+            self.identifier,
+            self.response_type,
+            self.token_reuse_min_ttl,
+            self.issue_refresh_tokens,
+            self.rotate_refresh_tokens,
+            self.disabled_at
         )
     }
 }
 
-#[derive(Builder, Debug, Serialize, Deserialize, Identifiable, Queryable, Associations)]
+#[derive(Builder, Clone, Debug, Serialize, Deserialize, Identifiable, Queryable, Associations)]
 #[builder(setter(into))]
 #[table_name = "grant_types"]
 pub struct GrantType {
@@ -31,48 +44,197 @@ pub struct GrantType {
     pub name: String,
 }
 
-#[derive(Builder, Debug, Serialize, Deserialize, Identifiable, Queryable, Associations)]
+#[derive(Builder, Clone, Debug, Serialize, Deserialize, Identifiable, Queryable, Associations)]
+#[builder(setter(into))]
+#[table_name = "client_redirect_uris"]
+pub struct ClientRedirectUri {
+    pub id: i32,
+    pub client_id: i32,
+    pub redirect_uri: String,
+}
+
+#[derive(Builder, Clone, Serialize, Deserialize, Identifiable, Queryable, Associations)]
 #[builder(setter(into))]
 #[table_name = "access_tokens"]
 pub struct AccessToken {
     pub id: i32,
-    pub token: Uuid,
+    /// The plaintext token, only kept for tokens issued before tokens were
+    /// hashed.
+    pub token: Option<Uuid>,
+    pub token_hash: Option<String>,
     pub client_id: i32,
     pub grant_id: i32,
     pub scope: String,
     pub issued_at: NaiveDateTime,
     pub expires_at: NaiveDateTime,
+    /// The refresh token this token was issued with, or minted from.
+    pub refresh_token_id: Option<i32>,
+    pub auth_code_id: Option<i32>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AccessToken {{ id: {}, token: {}, token_hash: {:?}, client_id: {}, grant_id: {}, \
+             scope: {}, issued_at: {}, expires_at: {}, refresh_token_id: {:?}, \
+             auth_code_id: {:?}, revoked_at: {:?} }}",
+            self.id,
+            redacted(&self.token),
+            self.token_hash,
+            self.client_id,
+            self.grant_id,
+            self.scope,
+            self.issued_at,
+            self.expires_at,
+            self.refresh_token_id,
+            self.auth_code_id,
+            self.revoked_at
+        )
+    }
 }
 
 #[derive(Builder, Debug, Serialize, Deserialize, Insertable)]
 #[builder(setter(into))]
 #[table_name = "access_tokens"]
 pub struct NewAccessToken {
+    pub token_hash: String,
     pub client_id: i32,
     pub grant_id: i32,
     pub scope: String,
     pub issued_at: NaiveDateTime,
     pub expires_at: NaiveDateTime,
+    pub refresh_token_id: Option<i32>,
+    pub auth_code_id: Option<i32>,
 }
 
-#[derive(Builder, Debug, Serialize, Deserialize, Identifiable, Queryable, Associations)]
+#[derive(Builder, Clone, Serialize, Deserialize, Identifiable, Queryable, Associations)]
 #[builder(setter(into))]
 #[table_name = "refresh_tokens"]
 pub struct RefreshToken {
     pub id: i32,
-    pub token: Uuid,
+    /// The plaintext token, only kept for tokens issued before tokens were
+    /// hashed and not used since.
+    pub token: Option<Uuid>,
+    pub token_hash: Option<String>,
     pub client_id: i32,
     pub scope: String,
     pub issued_at: NaiveDateTime,
     pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub absolute_expires_at: Option<NaiveDateTime>,
+    /// The refresh token this one replaced when it was rotated.
+    pub parent_id: Option<i32>,
+    pub auth_code_id: Option<i32>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl fmt::Debug for RefreshToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "RefreshToken {{ id: {}, token: {}, token_hash: {:?}, client_id: {}, scope: {}, \
+             issued_at: {}, expires_at: {:?}, last_used_at: {:?}, absolute_expires_at: {:?}, \
+             parent_id: {:?}, auth_code_id: {:?}, revoked_at: {:?} }}",
+            self.id,
+            redacted(&self.token),
+            self.token_hash,
+            self.client_id,
+            self.scope,
+            self.issued_at,
+            self.expires_at,
+            self.last_used_at,
+            self.absolute_expires_at,
+            self.parent_id,
+            self.auth_code_id,
+            self.revoked_at
+        )
+    }
+}
+
+// Legacy plaintext tokens are shown as present or not, never as themselves.
+fn redacted<T>(secret: &Option<T>) -> &'static str {
+    match *secret {
+        Some(_) => "Some([REDACTED])",
+        None => "None",
+    }
 }
 
 #[derive(Builder, Debug, Serialize, Deserialize, Insertable)]
 #[builder(setter(into))]
 #[table_name = "refresh_tokens"]
 pub struct NewRefreshToken {
+    pub token_hash: String,
     pub client_id: i32,
     pub scope: String,
     pub issued_at: NaiveDateTime,
     pub expires_at: Option<NaiveDateTime>,
+    pub absolute_expires_at: Option<NaiveDateTime>,
+    pub parent_id: Option<i32>,
+    pub auth_code_id: Option<i32>,
+}
+
+#[derive(Builder, Clone, Debug, Serialize, Deserialize, Identifiable, Queryable, Associations)]
+#[builder(setter(into))]
+#[table_name = "token_lifetimes"]
+pub struct TokenLifetime {
+    pub id: i32,
+    pub client_id: Option<i32>,
+    pub grant_id: Option<i32>,
+    pub scope: Option<String>,
+    pub access_token_ttl: Option<i32>,
+    pub refresh_token_ttl: Option<i32>,
+}
+
+#[derive(Builder, Debug, Serialize, Deserialize, Insertable)]
+#[builder(setter(into))]
+#[table_name = "token_lifetimes"]
+pub struct NewTokenLifetime {
+    pub client_id: Option<i32>,
+    pub grant_id: Option<i32>,
+    pub scope: Option<String>,
+    pub access_token_ttl: Option<i32>,
+    pub refresh_token_ttl: Option<i32>,
+}
+
+#[derive(Builder, Clone, Debug, Serialize, Deserialize, Identifiable, Queryable, Associations)]
+#[builder(setter(into))]
+#[table_name = "auth_codes"]
+pub struct AuthCode {
+    pub id: i32,
+    pub client_id: i32,
+    pub name: String,
+    pub scope: String,
+    pub expires_at: NaiveDateTime,
+    pub redirect_uri: String,
+    pub user_id: Option<i32>,
+}
+
+/// An entry in the audit trail of revocations, see `utils::revocation`.
+#[derive(Builder, Clone, Debug, Serialize, Deserialize, Identifiable, Queryable)]
+#[builder(setter(into))]
+#[table_name = "token_revocations"]
+pub struct Revocation {
+    pub id: i32,
+    pub revoked_at: NaiveDateTime,
+    /// Who revoked the tokens, e.g. `cli:alice` or `admin:oncall`.
+    pub actor: String,
+    pub reason: Option<String>,
+    /// What was revoked, e.g. `client=automation scope=admin`.
+    pub selection: String,
+    pub access_tokens: i32,
+    pub refresh_tokens: i32,
+}
+
+#[derive(Builder, Debug, Serialize, Deserialize, Insertable)]
+#[builder(setter(into))]
+#[table_name = "token_revocations"]
+pub struct NewRevocation {
+    pub revoked_at: NaiveDateTime,
+    pub actor: String,
+    pub reason: Option<String>,
+    pub selection: String,
+    pub access_tokens: i32,
+    pub refresh_tokens: i32,
 }
@@ -1,7 +1,22 @@
-#[derive(Builder, Clone, Debug, Deserialize, FromForm, Serialize)]
+use std::fmt;
+
+#[derive(Builder, Clone, Deserialize, FromForm, Serialize)]
 pub struct AccessTokenRequest {
     pub grant_type: Option<String>,
     pub scope: Option<String>,
     pub refresh_token: Option<String>,
     pub code: Option<String>,
 }
+
+impl fmt::Debug for AccessTokenRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AccessTokenRequest {{ grant_type: {:?}, scope: {:?}, refresh_token: {}, code: {} }}",
+            self.grant_type,
+            self.scope,
+            self.refresh_token.as_ref().map_or("None", |_| "[REDACTED]"),
+            self.code.as_ref().map_or("None", |_| "[REDACTED]")
+        )
+    }
+}
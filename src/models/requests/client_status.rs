@@ -0,0 +1,4 @@
+#[derive(Builder, Clone, Debug, Deserialize, FromForm)]
+pub struct ClientStatusRequest {
+    pub reason: Option<String>,
+}
@@ -1,5 +1,17 @@
-#[derive(Builder, Clone, Debug, Deserialize, FromForm)]
+use std::fmt;
+
+#[derive(Builder, Clone, Deserialize, FromForm)]
 pub struct IntrospectionRequest {
     pub token: String,
     pub token_type_hint: Option<String>,
 }
+
+impl fmt::Debug for IntrospectionRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "IntrospectionRequest {{ token: [REDACTED], token_type_hint: {:?} }}",
+            self.token_type_hint
+        )
+    }
+}
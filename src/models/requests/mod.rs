@@ -1,2 +1,4 @@
 pub mod access_token;
+pub mod client_status;
 pub mod introspect;
+pub mod revocation;
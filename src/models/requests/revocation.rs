@@ -0,0 +1,32 @@
+use std::fmt;
+
+#[derive(Builder, Clone, Deserialize, FromForm)]
+pub struct RevocationRequest {
+    pub client: Option<String>,
+    pub scope: Option<String>,
+    pub issued_after: Option<String>,
+    pub issued_before: Option<String>,
+    pub all: Option<bool>,
+    /// A single access or refresh token to revoke, instead of a selection.
+    pub token: Option<String>,
+    pub dry_run: Option<bool>,
+    pub reason: Option<String>,
+}
+
+impl fmt::Debug for RevocationRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "RevocationRequest {{ client: {:?}, scope: {:?}, issued_after: {:?}, \
+             issued_before: {:?}, all: {:?}, token: {}, dry_run: {:?}, reason: {:?} }}",
+            self.client,
+            self.scope,
+            self.issued_after,
+            self.issued_before,
+            self.all,
+            self.token.as_ref().map_or("None", |_| "[REDACTED]"),
+            self.dry_run,
+            self.reason
+        )
+    }
+}
@@ -1,30 +1,86 @@
 use rocket::Request;
 use rocket::http::Status;
-use rocket::response::{Responder, Response};
-use rocket::response::Result as RocketResult;
-use serde_json;
+use rocket::response::{self, Responder, Response};
+use std::fmt;
 use std::io::Cursor;
 
-// See: https://tools.ietf.org/html/rfc6749#section-5.1
-#[derive(Builder, Debug, Serialize, Deserialize)]
+// See: https://tools.ietf.org/html/rfc6749#section-5.1. The refresh token
+// fields are optional there, so they are left out rather than sent as null
+// when no refresh token is issued, or when it never expires.
+#[derive(Builder, Serialize, Deserialize)]
 #[builder(setter(into))]
 pub struct AccessTokenResponse {
     pub token_type: String,
     pub expires_in: i64,
     pub access_token: String,
     pub scope: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub refresh_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub refresh_expires_in: Option<i64>,
 }
 
-impl<'r> Responder<'r> for AccessTokenResponse {
-    fn respond_to(self, _req: &Request) -> RocketResult<'r> {
+impl fmt::Debug for AccessTokenResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AccessTokenResponse {{ token_type: {}, expires_in: {}, access_token: [REDACTED], \
+             scope: {}, refresh_token: {}, refresh_expires_in: {:?} }}",
+            self.token_type,
+            self.expires_in,
+            self.scope,
+            self.refresh_token.as_ref().map_or("None", |_| "[REDACTED]"),
+            self.refresh_expires_in
+        )
+    }
+}
+
+impl<'r> Responder<'r, 'static> for AccessTokenResponse {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
         Response::build()
             .raw_header("Content-Type", "application/json")
             .raw_header("Cache-Control", "no-cache, no-store")
             .raw_header("Pragma", "no-cache")
             .status(Status::Ok)
-            .sized_body(Cursor::new(serde_json::to_string(&self).unwrap()))
+            .sized_body(None, Cursor::new(serde_json::to_string(&self).unwrap()))
             .ok()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(
+        refresh_token: Option<&str>,
+        refresh_expires_in: Option<i64>,
+    ) -> AccessTokenResponse {
+        AccessTokenResponse {
+            token_type: "Bearer".to_owned(),
+            expires_in: 3600,
+            access_token: "oa2p_at_token".to_owned(),
+            scope: "read".to_owned(),
+            refresh_token: refresh_token.map(str::to_owned),
+            refresh_expires_in,
+        }
+    }
+
+    #[test]
+    fn leaves_out_the_refresh_token_when_none_is_issued() {
+        let json = serde_json::to_value(response(None, None)).unwrap();
+        assert_eq!(json.get("refresh_token"), None);
+        assert_eq!(json.get("refresh_expires_in"), None);
+        assert_eq!(json["expires_in"], 3600);
+    }
+
+    #[test]
+    fn includes_the_refresh_token_when_issued() {
+        let json = serde_json::to_value(response(Some("oa2p_rt_token"), Some(600))).unwrap();
+        assert_eq!(json["refresh_token"], "oa2p_rt_token");
+        assert_eq!(json["refresh_expires_in"], 600);
+
+        let json = serde_json::to_value(response(Some("oa2p_rt_token"), None)).unwrap();
+        assert_eq!(json["refresh_token"], "oa2p_rt_token");
+        assert_eq!(json.get("refresh_expires_in"), None);
+    }
+}
@@ -1,9 +1,6 @@
 use rocket::Request;
 use rocket::http::{ContentType, Status};
-use rocket::http::hyper::header::{CacheControl, CacheDirective, Pragma};
-use rocket::response::{Responder, Response};
-use rocket::response::Result as RocketResult;
-use serde_json;
+use rocket::response::{self, Responder, Response};
 use std::io::Cursor;
 
 #[derive(Builder, Debug, Serialize, Deserialize)]
@@ -16,17 +13,14 @@ pub struct IntrospectionOkResponse {
     pub iat: Option<i64>,
 }
 
-impl<'r> Responder<'r> for IntrospectionOkResponse {
-    fn respond_to(self, _req: &Request) -> RocketResult<'r> {
+impl<'r> Responder<'r, 'static> for IntrospectionOkResponse {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
         Response::build()
             .header(ContentType::JSON)
-            .header(CacheControl(vec![
-                CacheDirective::NoCache,
-                CacheDirective::NoStore,
-            ]))
-            .header(Pragma::NoCache)
+            .raw_header("Cache-Control", "no-cache, no-store")
+            .raw_header("Pragma", "no-cache")
             .status(Status::Ok)
-            .sized_body(Cursor::new(serde_json::to_string(&self).unwrap()))
+            .sized_body(None, Cursor::new(serde_json::to_string(&self).unwrap()))
             .ok()
     }
 }
@@ -0,0 +1,47 @@
+use rocket::Request;
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Responder, Response};
+use serde_json::Value;
+use std::io::Cursor;
+
+/// A JSON response for the endpoints outside of OAuth 2.0, i.e. the admin API
+/// and health checks.
+#[derive(Debug)]
+pub struct JsonResponse {
+    pub status: Status,
+    pub body: Value,
+}
+
+impl JsonResponse {
+    pub fn ok(body: Value) -> JsonResponse {
+        JsonResponse {
+            status: Status::Ok,
+            body,
+        }
+    }
+
+    pub fn error(status: Status, message: &str) -> JsonResponse {
+        JsonResponse {
+            status,
+            body: json!({ "error": message }),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for JsonResponse {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .header(ContentType::JSON)
+            .raw_header("Cache-Control", "no-cache, no-store")
+            .raw_header("Pragma", "no-cache")
+            .status(self.status);
+        if self.status == Status::Unauthorized {
+            response.raw_header("WWW-Authenticate", "Bearer");
+        }
+
+        response
+            .sized_body(None, Cursor::new(self.body.to_string()))
+            .ok()
+    }
+}
@@ -1,4 +1,5 @@
 pub mod access_token;
 pub mod introspection_err;
 pub mod introspection_ok;
+pub mod json;
 pub mod oauth2_error;
@@ -1,8 +1,7 @@
+use crate::metrics::METRICS;
 use rocket::Request;
 use rocket::http::{ContentType, Status};
-use rocket::http::hyper::header::{CacheControl, CacheDirective, Pragma};
-use rocket::response::{Responder, Response};
-use rocket::response::Result as RocketResult;
+use rocket::response::{self, Responder, Response};
 use std::io::Cursor;
 
 #[derive(Debug)]
@@ -13,6 +12,7 @@ pub enum OAuth2ErrorResponse {
     UnauthorizedClient,
     UnsupportedGrantType,
     InvalidScope,
+    ServerError,
 }
 
 impl OAuth2ErrorResponse {
@@ -24,20 +24,20 @@ impl OAuth2ErrorResponse {
             OAuth2ErrorResponse::UnauthorizedClient => "unauthorized_client",
             OAuth2ErrorResponse::UnsupportedGrantType => "unsupported_grant_type",
             OAuth2ErrorResponse::InvalidScope => "invalid_scope",
+            OAuth2ErrorResponse::ServerError => "server_error",
         }
     }
 }
 
-impl<'r> Responder<'r> for OAuth2ErrorResponse {
-    fn respond_to(self, _req: &Request) -> RocketResult<'r> {
+impl<'r> Responder<'r, 'static> for OAuth2ErrorResponse {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        METRICS.oauth_errors.inc(&[self.message()]);
+
         let mut response = Response::build();
         response
             .header(ContentType::JSON)
-            .header(CacheControl(vec![
-                CacheDirective::NoCache,
-                CacheDirective::NoStore,
-            ]))
-            .header(Pragma::NoCache);
+            .raw_header("Cache-Control", "no-cache, no-store")
+            .raw_header("Pragma", "no-cache");
 
         match self {
             OAuth2ErrorResponse::InvalidClient => {
@@ -45,6 +45,9 @@ impl<'r> Responder<'r> for OAuth2ErrorResponse {
                     .raw_header("WWW-Authenticate", "Basic")
                     .status(Status::Unauthorized);
             }
+            OAuth2ErrorResponse::ServerError => {
+                response.status(Status::InternalServerError);
+            }
             _ => {
                 response.status(Status::BadRequest);
             }
@@ -55,6 +58,6 @@ impl<'r> Responder<'r> for OAuth2ErrorResponse {
       "error": self.message()
     });
 
-        response.sized_body(Cursor::new(json.to_string())).ok()
+        response.sized_body(None, Cursor::new(json.to_string())).ok()
     }
 }
@@ -1,9 +1,16 @@
+#[cfg(any(feature = "sqlite", feature = "mysql"))]
+pub mod text_tokens;
+
 table! {
     clients (id) {
         id -> Integer,
         identifier -> VarChar,
         secret -> VarChar,
         response_type -> VarChar,
+        token_reuse_min_ttl -> Nullable<Integer>,
+        issue_refresh_tokens -> Nullable<Bool>,
+        rotate_refresh_tokens -> Nullable<Bool>,
+        disabled_at -> Nullable<Timestamp>,
     }
 }
 
@@ -25,23 +32,33 @@ table! {
 table! {
     access_tokens (id) {
         id -> Integer,
-        token -> Uuid,
+        token -> Nullable<Uuid>,
+        token_hash -> Nullable<VarChar>,
         client_id -> Integer,
         grant_id -> Integer,
         scope -> VarChar,
         issued_at -> Timestamp,
         expires_at -> Timestamp,
+        refresh_token_id -> Nullable<Integer>,
+        auth_code_id -> Nullable<Integer>,
+        revoked_at -> Nullable<Timestamp>,
     }
 }
 
 table! {
     refresh_tokens (id) {
         id -> Integer,
-        token -> Uuid,
+        token -> Nullable<Uuid>,
+        token_hash -> Nullable<VarChar>,
         client_id -> Integer,
         scope -> VarChar,
         issued_at -> Timestamp,
         expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        absolute_expires_at -> Nullable<Timestamp>,
+        parent_id -> Nullable<Integer>,
+        auth_code_id -> Nullable<Integer>,
+        revoked_at -> Nullable<Timestamp>,
     }
 }
 
@@ -56,3 +73,34 @@ table!{
         user_id -> Nullable<Integer>,
     }
 }
+
+table! {
+    token_lifetimes (id) {
+        id -> Integer,
+        client_id -> Nullable<Integer>,
+        grant_id -> Nullable<Integer>,
+        scope -> Nullable<VarChar>,
+        access_token_ttl -> Nullable<Integer>,
+        refresh_token_ttl -> Nullable<Integer>,
+    }
+}
+
+table! {
+    schema_migrations (version) {
+        version -> Integer,
+        name -> VarChar,
+        applied_at -> Timestamp,
+    }
+}
+
+table! {
+    token_revocations (id) {
+        id -> Integer,
+        revoked_at -> Timestamp,
+        actor -> VarChar,
+        reason -> Nullable<VarChar>,
+        selection -> VarChar,
+        access_tokens -> Integer,
+        refresh_tokens -> Integer,
+    }
+}
@@ -0,0 +1,108 @@
+//! The schema for backends without a UUID type (SQLite and MySQL). Legacy
+//! plaintext tokens are kept as their hyphenated text form, and
+//! timestamps are kept in UTC without a time zone (`TIMESTAMP` in SQLite,
+//! `DATETIME` in MySQL).
+
+table! {
+    clients (id) {
+        id -> Integer,
+        identifier -> Text,
+        secret -> Text,
+        response_type -> Text,
+        token_reuse_min_ttl -> Nullable<Integer>,
+        issue_refresh_tokens -> Nullable<Bool>,
+        rotate_refresh_tokens -> Nullable<Bool>,
+        disabled_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    grant_types (id) {
+        id -> Integer,
+        name -> Text,
+    }
+}
+
+table! {
+    client_redirect_uris (id) {
+        id -> Integer,
+        client_id -> Integer,
+        redirect_uri -> Text,
+    }
+}
+
+table! {
+    access_tokens (id) {
+        id -> Integer,
+        token -> Nullable<Text>,
+        token_hash -> Nullable<Text>,
+        client_id -> Integer,
+        grant_id -> Integer,
+        scope -> Text,
+        issued_at -> Timestamp,
+        expires_at -> Timestamp,
+        refresh_token_id -> Nullable<Integer>,
+        auth_code_id -> Nullable<Integer>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    refresh_tokens (id) {
+        id -> Integer,
+        token -> Nullable<Text>,
+        token_hash -> Nullable<Text>,
+        client_id -> Integer,
+        scope -> Text,
+        issued_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        absolute_expires_at -> Nullable<Timestamp>,
+        parent_id -> Nullable<Integer>,
+        auth_code_id -> Nullable<Integer>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    auth_codes (id) {
+        id -> Integer,
+        client_id -> Integer,
+        name -> Text,
+        scope -> Text,
+        expires_at -> Timestamp,
+        redirect_uri -> Text,
+        user_id -> Nullable<Integer>,
+    }
+}
+
+table! {
+    token_lifetimes (id) {
+        id -> Integer,
+        client_id -> Nullable<Integer>,
+        grant_id -> Nullable<Integer>,
+        scope -> Nullable<Text>,
+        access_token_ttl -> Nullable<Integer>,
+        refresh_token_ttl -> Nullable<Integer>,
+    }
+}
+
+table! {
+    schema_migrations (version) {
+        version -> Integer,
+        name -> Text,
+        applied_at -> Timestamp,
+    }
+}
+
+table! {
+    token_revocations (id) {
+        id -> Integer,
+        revoked_at -> Timestamp,
+        actor -> Text,
+        reason -> Nullable<Text>,
+        selection -> Text,
+        access_tokens -> Integer,
+        refresh_tokens -> Integer,
+    }
+}
@@ -0,0 +1,183 @@
+//! The pruning module deletes access tokens, refresh tokens and authorization
+//! codes once they have been expired (or revoked) for longer than the
+//! configured grace period, so the token tables do not grow without bound.
+//! A refresh token is kept past its grace period for as long as a token
+//! derived from it is, so that revoking the root of a grant still reaches
+//! every token in it; a grant is pruned from its newest tokens up.
+//!
+//! Pruning runs on a background thread every `pruning.interval` seconds and
+//! deletes at most `pruning.batch_size` rows per statement, so no single
+//! statement holds its locks for long. Instances sharing a database take a
+//! lock through the storage backend first, and skip the run if another
+//! instance already holds it.
+
+use crate::settings;
+use crate::storage::{self, PruneStore, StorageResult};
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::cmp;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time;
+
+lazy_static! {
+    pub static ref PRUNE_STATS: PruneStats = PruneStats::default();
+}
+
+/// Counters describing the pruning done since the process started.
+#[derive(Default)]
+pub struct PruneStats {
+    runs: AtomicUsize,
+    skipped: AtomicUsize,
+    failures: AtomicUsize,
+    access_tokens: AtomicUsize,
+    refresh_tokens: AtomicUsize,
+    auth_codes: AtomicUsize,
+}
+
+/// Point in time copy of `PruneStats`.
+#[derive(Clone, Debug, Serialize)]
+pub struct PruneCounts {
+    /// Runs that held the lock and pruned every table.
+    pub runs: usize,
+    /// Runs skipped because another instance held the lock.
+    pub skipped: usize,
+    pub failures: usize,
+    pub access_tokens: usize,
+    pub refresh_tokens: usize,
+    pub auth_codes: usize,
+}
+
+impl PruneStats {
+    pub fn counts(&self) -> PruneCounts {
+        PruneCounts {
+            runs: self.runs.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            access_tokens: self.access_tokens.load(Ordering::Relaxed),
+            refresh_tokens: self.refresh_tokens.load(Ordering::Relaxed),
+            auth_codes: self.auth_codes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Starts the background pruning thread.
+///
+/// Returns: io::Result<JoinHandle<()>> --- the thread, which never finishes.
+pub fn spawn() -> io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("oa2p-pruner".to_owned())
+        .spawn(move || loop {
+            match run_once() {
+                Ok(true) => debug!("Pruning finished: {:?}", PRUNE_STATS.counts()),
+                Ok(false) => debug!("Pruning skipped, another instance holds the lock"),
+                Err(e) => {
+                    PRUNE_STATS.failures.fetch_add(1, Ordering::Relaxed);
+                    warn!("Pruning failed: {}", e);
+                }
+            }
+            thread::sleep(time::Duration::from_secs(
+                settings::current().pruning.interval,
+            ));
+        })
+}
+
+/// Prunes every table once, if no other instance is pruning.
+///
+/// Returns: StorageResult<bool>
+/// - Ok(true)          --- every table was pruned
+/// - Ok(false)         --- another instance holds the pruning lock
+/// - Err(StorageError) --- the backend failed part way through
+pub fn run_once() -> StorageResult<bool> {
+    let store = storage::connect()?;
+    if !store.try_lock_pruning()? {
+        PRUNE_STATS.skipped.fetch_add(1, Ordering::Relaxed);
+        return Ok(false);
+    }
+
+    let pruned = prune_all(&*store);
+    let unlocked = store.unlock_pruning();
+    pruned?;
+    unlocked?;
+
+    PRUNE_STATS.runs.fetch_add(1, Ordering::Relaxed);
+    Ok(true)
+}
+
+fn prune_all<S: PruneStore + ?Sized>(store: &S) -> StorageResult<()> {
+    let settings = settings::current();
+    let cutoff = Utc::now().naive_utc() - Duration::seconds(settings.pruning.grace_period);
+    let batch_size = cmp::max(settings.pruning.batch_size, 1);
+
+    let access_count =
+        prune_in_batches(cutoff, batch_size, |c, n| store.prune_access_tokens(c, n))?;
+    PRUNE_STATS.access_tokens.fetch_add(access_count, Ordering::Relaxed);
+    // A refresh token is only pruned once nothing refers to it, so each pass
+    // frees the parents of the tokens the pass before pruned
+    let mut refresh_count = 0;
+    loop {
+        let count = prune_in_batches(cutoff, batch_size, |c, n| store.prune_refresh_tokens(c, n))?;
+        if count == 0 {
+            break;
+        }
+        refresh_count += count;
+    }
+    PRUNE_STATS.refresh_tokens.fetch_add(refresh_count, Ordering::Relaxed);
+    let code_count = prune_in_batches(cutoff, batch_size, |c, n| store.prune_auth_codes(c, n))?;
+    PRUNE_STATS.auth_codes.fetch_add(code_count, Ordering::Relaxed);
+
+    if access_count + refresh_count + code_count > 0 {
+        info!(
+            "Pruned {} access tokens, {} refresh tokens and {} authorization codes expired before {}",
+            access_count, refresh_count, code_count, cutoff
+        );
+    }
+    Ok(())
+}
+
+// Keeps deleting batches until one comes back short, which means nothing
+// older than the cutoff is left.
+fn prune_in_batches<F>(cutoff: NaiveDateTime, batch_size: i64, prune: F) -> StorageResult<usize>
+where
+    F: Fn(NaiveDateTime, i64) -> StorageResult<usize>,
+{
+    let mut total = 0;
+    loop {
+        let count = prune(cutoff, batch_size)?;
+        total += count;
+        if (count as i64) < batch_size {
+            return Ok(total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn prunes_batches_until_one_comes_back_short() {
+        let batches = RefCell::new(vec![3, 10, 10]);
+        let cutoff = Utc::now().naive_utc();
+
+        let total = prune_in_batches(cutoff, 10, |c, n| {
+            assert_eq!(c, cutoff);
+            assert_eq!(n, 10);
+            Ok(batches.borrow_mut().pop().unwrap())
+        });
+        assert_eq!(total.unwrap(), 23);
+        assert!(batches.borrow().is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_first_failure() {
+        let calls = RefCell::new(0);
+        let result = prune_in_batches(Utc::now().naive_utc(), 10, |_, _| {
+            *calls.borrow_mut() += 1;
+            Err(crate::storage::StorageError::Unavailable("down".to_owned()))
+        });
+        assert!(result.is_err());
+        assert_eq!(*calls.borrow(), 1);
+    }
+}
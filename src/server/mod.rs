@@ -0,0 +1,270 @@
+//! The server module turns the `[server]` settings into the configuration of
+//! the two Rocket instances the provider runs: the public listener serving the
+//! OAuth routes, optionally over TLS, and the internal listener serving the
+//! admin, metrics and health routes.
+//!
+//! Keeping the admin API on its own listener means it is never reachable
+//! through the public interface, however the network in front of the provider
+//! is set up. With `server.internal.enabled` turned off, the public listener
+//! serves the metrics and health routes as well, and the admin API is not
+//! served at all. `Rocket.toml` and the `ROCKET_*` variables are not read.
+//!
+//! With TLS enabled, the public Rocket instance listens on a loopback port
+//! and `tls` terminates TLS on the public address in front of it, since Rocket
+//! cannot replace its certificate once started. A renewed pair is put in place
+//! by `reload_tls` without the listener stopping.
+//!
+//! Both listeners run on one Tokio runtime with `server.workers` threads.
+//! Handlers hand storage access and bcrypt verification to the runtime's
+//! blocking thread pool, see `web::context`, so the workers only ever wait on
+//! the network.
+
+mod tls;
+
+use crate::SETTINGS;
+use crate::server::tls::{CertResolver, Clients};
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::tls::rustls::sign::{self, CertifiedKey};
+use rocket::http::tls::util::{load_certs, load_private_key};
+use rocket::tokio::net::TcpListener;
+use rocket::tokio::runtime;
+use rocket::tokio::sync::oneshot;
+use rocket::{Config, Request, Shutdown};
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+lazy_static! {
+    // The certificate chain and key of the public listener, once it is
+    // listening.
+    static ref TLS_RESOLVER: Mutex<Option<Arc<CertResolver>>> = Mutex::new(None);
+    // The shutdown handles of the running listeners.
+    static ref PUBLIC: Mutex<Option<Shutdown>> = Mutex::new(None);
+    static ref INTERNAL: Mutex<Option<Shutdown>> = Mutex::new(None);
+}
+
+// Set once the listeners are stopping for good.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// The configuration of the public listener. With TLS enabled, that is a
+/// loopback address on a port of the system's choosing, which `launch` puts
+/// the TLS listener in front of.
+///
+/// Returns: Result<Figment, String>
+/// - Ok(Figment)  --- the listener's address and port
+/// - Err(String)  --- a setting Rocket does not accept, or a TLS certificate
+///   or key that cannot be used
+pub fn public_config() -> Result<Figment, String> {
+    let server = &SETTINGS.server;
+    let figment = if server.tls.enabled {
+        read_tls()?;
+        base_config()
+            .merge(("address", Ipv4Addr::LOCALHOST))
+            .merge(("port", 0))
+    } else {
+        base_config()
+            .merge(("address", &server.address))
+            .merge(("port", server.port))
+    };
+
+    figment
+        .extract::<Config>()
+        .map(|_| figment)
+        .map_err(|e| format!("server: {}", e))
+}
+
+/// The configuration of the internal listener, which never uses TLS.
+///
+/// Returns: Result<Figment, String>
+/// - Ok(Figment)  --- the listener's address and port
+/// - Err(String)  --- a setting Rocket does not accept
+pub fn internal_config() -> Result<Figment, String> {
+    let internal = &SETTINGS.server.internal;
+    let figment = base_config()
+        .merge(("address", &internal.address))
+        .merge(("port", internal.port));
+
+    figment
+        .extract::<Config>()
+        .map(|_| figment)
+        .map_err(|e| format!("server.internal: {}", e))
+}
+
+/// Reads the files named by `[server.tls]` again, and has the public
+/// listener use the new pair once it has been checked. Every handshake from
+/// then on uses the new pair, while connections already open are left as
+/// they are, so no connection is refused or dropped. A pair that cannot be
+/// used is rejected, and the listener keeps the pair it has.
+///
+/// Returns: Result<(), String> --- why the new pair cannot be used, if it
+/// cannot.
+pub fn reload_tls() -> Result<(), String> {
+    let key = read_tls()?;
+    if let Some(resolver) = lock(&TLS_RESOLVER).as_ref() {
+        resolver.replace(key);
+    }
+    Ok(())
+}
+
+/// The address a request came from. Requests over TLS reach the public
+/// Rocket instance from the TLS listener, and are traced back to the client
+/// the TLS listener accepted them from.
+pub fn remote_addr(request: &Request) -> Option<SocketAddr> {
+    let remote = request.remote()?;
+    match request.rocket().state::<Clients>() {
+        Some(clients) => clients.get(remote),
+        None => Some(remote),
+    }
+}
+
+/// Stops both listeners gracefully, which makes `launch` return. They stop
+/// accepting connections, and give the requests in flight `shutdown.grace`
+/// seconds to finish and `shutdown.mercy` more seconds to be written out
+/// before their connections are closed.
+pub fn stop() {
+    STOPPING.store(true, Ordering::SeqCst);
+    for handle in [&*PUBLIC, &*INTERNAL].iter() {
+        if let Some(listener) = lock(handle).clone() {
+            listener.notify();
+        }
+    }
+}
+
+// Reads the certificate chain and key, and parses them the way Rocket does,
+// so that a pair that cannot be used is caught before a handshake needs it.
+fn read_tls() -> Result<Arc<CertifiedKey>, String> {
+    let tls = &SETTINGS.server.tls;
+    let certs = fs::read(&tls.certs).map_err(|e| format!("server.tls.certs: {}: {}", tls.certs, e))?;
+    let key = fs::read(&tls.key).map_err(|e| format!("server.tls.key: {}: {}", tls.key, e))?;
+
+    let chain = match load_certs(&mut &certs[..]) {
+        Ok(chain) if !chain.is_empty() => chain,
+        Ok(_) => return Err(format!("server.tls.certs: {}: no PEM certificate found", tls.certs)),
+        Err(e) => return Err(format!("server.tls.certs: {}: {}", tls.certs, e)),
+    };
+    let key = load_private_key(&mut &key[..]).map_err(|e| format!("server.tls.key: {}: {}", tls.key, e))?;
+    let key = sign::any_supported_type(&key).map_err(|e| format!("server.tls.key: {}: {}", tls.key, e))?;
+    Ok(Arc::new(CertifiedKey::new(chain, key)))
+}
+
+/// Runs the listeners until `stop` is called.
+///
+/// Returns: Result<(), String> --- why a listener could not be started, if
+/// one could not.
+pub fn launch() -> Result<(), String> {
+    let runtime = runtime::Builder::new_multi_thread()
+        .worker_threads(usize::from(SETTINGS.server.workers))
+        .thread_name("oa2p-worker")
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Unable to start the async runtime: {}", e))?;
+
+    runtime.block_on(async {
+        let public = async {
+            if SETTINGS.server.tls.enabled {
+                return launch_tls().await;
+            }
+            let rocket = crate::rocket()
+                .ignite()
+                .await
+                .map_err(|e| format!("Unable to start the public listener: {}", e))?;
+            register(&PUBLIC, rocket.shutdown());
+            rocket
+                .launch()
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("Unable to start the public listener: {}", e))
+        };
+        let internal = async {
+            if !SETTINGS.server.internal.enabled {
+                return Ok(());
+            }
+            let rocket = crate::internal_rocket()
+                .ignite()
+                .await
+                .map_err(|e| format!("Unable to start the internal listener: {}", e))?;
+            register(&INTERNAL, rocket.shutdown());
+            rocket
+                .launch()
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("Unable to start the internal listener: {}", e))
+        };
+        rocket::tokio::try_join!(public, internal).map(|_| ())
+    })
+}
+
+// Runs the public Rocket instance on a loopback port, with the TLS listener on
+// the public address in front of it.
+async fn launch_tls() -> Result<(), String> {
+    let server = &SETTINGS.server;
+    let resolver = Arc::new(CertResolver::new(read_tls()?));
+    let config = tls::server_config(resolver.clone())?;
+    let listener = TcpListener::bind((server.address.as_str(), server.port))
+        .await
+        .map_err(|e| format!("Unable to start the public listener: {}", e))?;
+    *lock(&TLS_RESOLVER) = Some(resolver);
+    info!(
+        "The public listener is serving TLS on {}:{}",
+        server.address, server.port
+    );
+
+    let (backend_tx, backend_rx) = oneshot::channel();
+    let clients = Clients::default();
+    let rocket = crate::rocket()
+        .manage(clients.clone())
+        .attach(AdHoc::on_liftoff("TLS listener", move |rocket| {
+            Box::pin(async move {
+                let config = rocket.config();
+                let _ = backend_tx.send(SocketAddr::new(config.address, config.port));
+            })
+        }))
+        .ignite()
+        .await
+        .map_err(|e| format!("Unable to start the public listener: {}", e))?;
+    let shutdown = rocket.shutdown();
+    register(&PUBLIC, shutdown.clone());
+
+    let serve = tls::serve(listener, backend_rx, config, clients, shutdown);
+    let launched = async {
+        rocket
+            .launch()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Unable to start the public listener: {}", e))
+    };
+    rocket::tokio::join!(serve, launched).1
+}
+
+// Keeps the shutdown handle of a listener about to launch. A listener that
+// starts after `stop` was called is stopped as soon as it has started.
+fn register(handle: &Mutex<Option<Shutdown>>, listener: Shutdown) {
+    *lock(handle) = Some(listener.clone());
+    if STOPPING.load(Ordering::SeqCst) {
+        listener.notify();
+    }
+}
+
+// Rocket's defaults, without reading `Rocket.toml` or the environment. Rocket
+// does not listen for signals itself: the `shutdown` module lets readiness
+// fail first, then calls `stop`.
+fn base_config() -> Figment {
+    let shutdown = &SETTINGS.shutdown;
+    Figment::from(Config::default())
+        .merge(("cli_colors", false))
+        .merge(("shutdown.ctrlc", false))
+        .merge(("shutdown.signals", Vec::<String>::new()))
+        .merge(("shutdown.grace", shutdown.grace))
+        .merge(("shutdown.mercy", shutdown.mercy))
+}
+
+// Nothing is left half-updated behind these locks, so a poisoned one is
+// recovered rather than failing every later caller.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
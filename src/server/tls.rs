@@ -0,0 +1,140 @@
+//! TLS for the public listener. Rocket reads its certificate once, as a
+//! listener starts, and has no way to replace it, so with TLS enabled the
+//! public Rocket instance listens on a loopback port, and `serve` accepts the
+//! connections to the public address in its place. Every handshake is answered
+//! with the certificate `CertResolver` holds at that moment, and the decrypted
+//! stream is passed on to Rocket. A renewed certificate is put in place with
+//! `CertResolver::replace`: new handshakes use it straight away, while open
+//! connections carry on undisturbed.
+
+use crate::server::lock;
+use rocket::Shutdown;
+use rocket::http::tls::rustls::server::{ClientHello, ResolvesServerCert, ServerSessionMemoryCache};
+use rocket::http::tls::rustls::sign::CertifiedKey;
+use rocket::http::tls::rustls::{ServerConfig, Ticketer};
+use rocket::tokio::io::copy_bidirectional;
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::sync::oneshot;
+use rocket::tokio::{self, time};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio_rustls::TlsAcceptor;
+
+// How long to wait before accepting again after failing to, e.g. when the
+// process is out of file descriptors.
+const ACCEPT_BACKOFF_MS: u64 = 100;
+
+/// Hands every handshake the certificate chain and key last put in place.
+pub struct CertResolver {
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    pub fn new(key: Arc<CertifiedKey>) -> CertResolver {
+        CertResolver {
+            key: RwLock::new(key),
+        }
+    }
+
+    /// Uses the given certificate chain and key for every later handshake.
+    pub fn replace(&self, key: Arc<CertifiedKey>) {
+        *self.key.write().unwrap() = key; // TODO: remove unwrap
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap().clone()) // TODO: remove unwrap
+    }
+}
+
+/// The clients of the connections passed on to Rocket, by the address each
+/// connection to Rocket is made from. Managed by the public Rocket instance,
+/// so that requests can be traced back to their client.
+#[derive(Clone, Default)]
+pub struct Clients(Arc<Mutex<HashMap<SocketAddr, SocketAddr>>>);
+
+impl Clients {
+    pub fn get(&self, forwarded_from: SocketAddr) -> Option<SocketAddr> {
+        lock(&self.0).get(&forwarded_from).cloned()
+    }
+}
+
+/// The TLS settings Rocket would use, but with the certificate taken from
+/// `resolver` at every handshake.
+pub fn server_config(resolver: Arc<CertResolver>) -> Result<Arc<ServerConfig>, String> {
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.ignore_client_order = true;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config.session_storage = ServerSessionMemoryCache::new(1024);
+    config.ticketer = Ticketer::new().map_err(|e| format!("server.tls: {}", e))?;
+    Ok(Arc::new(config))
+}
+
+/// Accepts connections on `listener` until `shutdown` is notified, and passes
+/// each on to the Rocket instance listening on the address `backend` sends
+/// once it has started. Connections already passed on are left to Rocket to
+/// close.
+pub async fn serve(
+    listener: TcpListener,
+    backend: oneshot::Receiver<SocketAddr>,
+    config: Arc<ServerConfig>,
+    clients: Clients,
+    shutdown: Shutdown,
+) {
+    // Without an address Rocket failed to start, which it reports itself
+    let backend = match backend.await {
+        Ok(backend) => backend,
+        Err(_) => return,
+    };
+    let acceptor = TlsAcceptor::from(config);
+
+    tokio::pin!(shutdown);
+    loop {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => return,
+            accepted = listener.accept() => accepted,
+        };
+        let (stream, client) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Unable to accept a connection: {}", e);
+                time::sleep(Duration::from_millis(ACCEPT_BACKOFF_MS)).await;
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let clients = clients.clone();
+        tokio::spawn(async move {
+            if let Err(e) = forward(acceptor, stream, client, backend, clients).await {
+                debug!("TLS connection from {} failed: {}", client, e);
+            }
+        });
+    }
+}
+
+async fn forward(
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    client: SocketAddr,
+    backend: SocketAddr,
+    clients: Clients,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut tls = acceptor.accept(stream).await?;
+    let mut plain = TcpStream::connect(backend).await?;
+    plain.set_nodelay(true)?;
+
+    let forwarded_from = plain.local_addr()?;
+    lock(&clients.0).insert(forwarded_from, client);
+    let copied = copy_bidirectional(&mut tls, &mut plain).await;
+    lock(&clients.0).remove(&forwarded_from);
+    copied.map(|_| ())
+}
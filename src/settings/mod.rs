@@ -0,0 +1,643 @@
+//! The settings module loads `AppSettings` from its layered sources, from
+//! lowest to highest precedence:
+//!
+//! 1. The config file: the path given by `--config`, or `OA2P_CONFIG`, or
+//!    `config.toml` in the working directory if that exists.
+//! 2. Environment variables named `OA2P_` followed by the path to the
+//!    setting, with sections separated by a double underscore, e.g.
+//!    `OA2P_DB__PASS` for `pass` in the `[db]` section.
+//! 3. Secret files: `<setting>_file` names a file holding the value of a
+//!    secret setting, e.g. `OA2P_DB__PASS_FILE=/run/secrets/db-pass`, for
+//!    secrets mounted from Kubernetes or Docker.
+//!
+//! The result is validated as a whole, so that every mistake is reported at
+//! once rather than surfacing later as a crash.
+//!
+//! `SETTINGS` holds the settings the process started with. The settings that
+//! can safely change at runtime are read through `current` instead, which
+//! follows every reload, see `reload`.
+
+mod reload;
+
+pub use self::reload::watch;
+
+use crate::INTROSPECTION_CACHE;
+use crate::SETTINGS;
+use crate::logging;
+use crate::models::configuration::{AppSettings, DatabaseBackend, LogDestination, TraceExporter};
+use config::{Config, Environment, File as ConfigFile};
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The environment variable naming the config file, unless `--config` does.
+pub const CONFIG_ENV: &str = "OA2P_CONFIG";
+
+const DEFAULT_CONFIG: &str = "config.toml";
+const ENV_PREFIX: &str = "OA2P";
+
+/// The settings that may be read from a file named by `<setting>_file`.
+const SECRETS: [&str; 2] = ["db.user", "db.pass"];
+
+/// The settings that take effect when reloaded, by name or section. Anything
+/// else is only read at startup, and keeps its startup value in `current`.
+const RELOADABLE: [&str; 9] = [
+    "oauth.",
+    "logging.",
+    "admin.",
+    "cache.",
+    "pruning.interval",
+    "pruning.grace_period",
+    "pruning.batch_size",
+    "tracing.sample_ratio",
+    "shutdown.drain_delay",
+];
+
+lazy_static! {
+    static ref CONFIG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+    static ref LOADED: Mutex<Option<AppSettings>> = Mutex::new(None);
+    static ref CURRENT: RwLock<Arc<AppSettings>> = RwLock::new(Arc::new(SETTINGS.clone()));
+}
+
+/// The settings in effect, including the reloadable settings changed since
+/// startup.
+pub fn current() -> Arc<AppSettings> {
+    CURRENT.read().unwrap().clone() // TODO: remove unwrap
+}
+
+/// Loads the settings again and makes the reloadable ones take effect. Every
+/// changed setting is logged, along with whether it needs a restart. Changes
+/// to settings that need one are ignored until then.
+///
+/// Returns: Result<(), String>
+/// - Ok(())       --- the new settings are in effect
+/// - Err(String)  --- why the new settings were rejected; the current ones
+///   stay in effect
+pub fn reload() -> Result<(), String> {
+    let current = current();
+    let loaded = load()?;
+    let changes = diff(&current, &loaded);
+    if changes.is_empty() {
+        info!("Reloaded the configuration, nothing changed");
+        return Ok(());
+    }
+    let new_settings = with_reloadable(&current, &loaded)?;
+
+    // The only reloadable setting that can still fail to apply, e.g. when a
+    // new log file cannot be opened, so it goes first
+    if changes.keys().any(|key| key.starts_with("logging.")) {
+        logging::reconfigure(&new_settings.logging)?;
+    }
+    if changes.keys().any(|key| key.starts_with("cache.")) {
+        INTROSPECTION_CACHE.reconfigure(&new_settings.cache);
+    }
+    *CURRENT.write().unwrap() = Arc::new(new_settings); // TODO: remove unwrap
+
+    for (key, change) in &changes {
+        if RELOADABLE.iter().any(|r| key.starts_with(r)) {
+            info!("Reloaded {}: {}", key, change);
+        } else {
+            warn!(
+                "{} changed ({}), but is ignored until the next restart",
+                key, change
+            );
+        }
+    }
+    Ok(())
+}
+
+// The current settings, with the reloadable ones taken from the loaded
+// settings instead.
+fn with_reloadable(current: &AppSettings, loaded: &AppSettings) -> Result<AppSettings, String> {
+    let mut merged = serde_json::to_value(current).map_err(|e| e.to_string())?;
+    let loaded = serde_json::to_value(loaded).map_err(|e| e.to_string())?;
+    for key in &RELOADABLE {
+        let pointer = format!("/{}", key.trim_end_matches('.').replace('.', "/"));
+        if let (Some(value), Some(new_value)) =
+            (merged.pointer_mut(&pointer), loaded.pointer(&pointer))
+        {
+            *value = new_value.clone();
+        }
+    }
+
+    // Some settings are only checked along with others, e.g.
+    // tracing.sample_ratio once tracing is enabled, so the mix is checked too
+    let merged: AppSettings = serde_json::from_value(merged).map_err(|e| e.to_string())?;
+    validate(&merged)?;
+    Ok(merged)
+}
+
+// The settings that differ, by dotted name, along with how they changed.
+// Secrets are only reported as changed.
+fn diff(old: &AppSettings, new: &AppSettings) -> BTreeMap<String, String> {
+    let mut old_values = BTreeMap::new();
+    let mut new_values = BTreeMap::new();
+    flatten(
+        "",
+        &serde_json::to_value(old).unwrap_or(Value::Null),
+        &mut old_values,
+    );
+    flatten(
+        "",
+        &serde_json::to_value(new).unwrap_or(Value::Null),
+        &mut new_values,
+    );
+
+    let mut changes = BTreeMap::new();
+    for key in old_values.keys().chain(new_values.keys()) {
+        let old_value = old_values.get(key);
+        let new_value = new_values.get(key);
+        if old_value == new_value || changes.contains_key(key) {
+            continue;
+        }
+
+        let change = if SECRETS.contains(&key.as_str()) {
+            "value hidden".to_owned()
+        } else {
+            format!(
+                "{} -> {}",
+                old_value.map_or("unset".to_owned(), |v| v.to_string()),
+                new_value.map_or("unset".to_owned(), |v| v.to_string())
+            )
+        };
+        changes.insert(key.clone(), change);
+    }
+    changes
+}
+
+fn flatten(prefix: &str, value: &Value, out: &mut BTreeMap<String, Value>) {
+    match *value {
+        Value::Object(ref map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_owned(), value.clone());
+        }
+    }
+}
+
+/// Takes a leading `--config <path>` off the command line. The path is used
+/// by every later `load`.
+///
+/// Returns: Result<Vec<String>, String>
+/// - Ok(Vec<String>)  --- the remaining arguments
+/// - Err(String)      --- `--config` is missing its path
+pub fn take_config_flag(args: Vec<String>) -> Result<Vec<String>, String> {
+    if args.first().map(|a| a.as_str()) != Some("--config") {
+        return Ok(args);
+    }
+    match args.get(1) {
+        Some(path) => {
+            *CONFIG_PATH.lock().unwrap() = Some(PathBuf::from(path)); // TODO: remove unwrap
+            Ok(args[2..].to_vec())
+        }
+        None => Err("missing a value for `--config`".to_owned()),
+    }
+}
+
+/// Loads and validates the settings, and keeps them for `SETTINGS`, so that
+/// the binary can report invalid settings before anything else happens.
+///
+/// Returns: Result<(), String> --- every problem found, one per line.
+pub fn preload() -> Result<(), String> {
+    let settings = load()?;
+    *LOADED.lock().unwrap() = Some(settings); // TODO: remove unwrap
+    Ok(())
+}
+
+/// The settings for `SETTINGS`: those kept by `preload`, or freshly loaded
+/// when the provider is hosted by another crate.
+pub fn initial() -> AppSettings {
+    let preloaded = LOADED.lock().unwrap().take(); // TODO: remove unwrap
+    if let Some(settings) = preloaded {
+        return settings;
+    }
+    // Unit tests run without a config file, and get the example one unless
+    // they name another, as the ignored backend suites do
+    #[cfg(test)]
+    {
+        let mut flag = CONFIG_PATH.lock().unwrap(); // TODO: remove unwrap
+        if flag.is_none() && env::var(CONFIG_ENV).map_or(true, |p| p.is_empty()) {
+            *flag = Some(PathBuf::from("config.example.toml"));
+        }
+    }
+    load().unwrap_or_else(|e| panic!("Invalid configuration:\n{}", e))
+}
+
+/// Reads the settings from every source and validates them.
+///
+/// Returns: Result<AppSettings, String>
+/// - Ok(AppSettings)  --- the settings
+/// - Err(String)      --- every problem found, one per line
+pub fn load() -> Result<AppSettings, String> {
+    let (path, required) = config_path();
+    load_from(&path, required, ENV_PREFIX)
+}
+
+// Reads the settings from the config file at `path`, then the environment
+// variables starting with `env_prefix`, then the secret files those name.
+fn load_from(path: &Path, required: bool, env_prefix: &str) -> Result<AppSettings, String> {
+    let mut config = Config::new();
+
+    config
+        .merge(ConfigFile::from(path).required(required))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    config
+        .merge(Environment::with_prefix(env_prefix).separator("__"))
+        .map_err(|e| format!("environment: {}", e))?;
+
+    for key in &SECRETS {
+        let file_key = format!("{}_file", key);
+        if let Ok(file) = config.get_str(&file_key) {
+            let mut value = String::new();
+            File::open(&file)
+                .and_then(|mut f| f.read_to_string(&mut value))
+                .map_err(|e| format!("{}: unable to read {}: {}", file_key, file, e))?;
+            // Files written by hand or by `echo` usually end in a newline
+            config
+                .set(key, value.trim_end_matches(['\n', '\r']))
+                .map_err(|e| format!("{}: {}", key, e))?;
+        }
+    }
+
+    let settings: AppSettings = config.try_into().map_err(|e| e.to_string())?;
+    validate(&settings)?;
+    Ok(settings)
+}
+
+// An explicitly named config file must exist, while the default one may be
+// left out when everything is set through the environment.
+fn config_path() -> (PathBuf, bool) {
+    let flag = CONFIG_PATH.lock().unwrap().clone(); // TODO: remove unwrap
+    if let Some(path) = flag {
+        return (path, true);
+    }
+    match env::var(CONFIG_ENV) {
+        Ok(ref path) if !path.is_empty() => (PathBuf::from(path), true),
+        _ => (PathBuf::from(DEFAULT_CONFIG), false),
+    }
+}
+
+/// Checks the settings for values that deserialize fine but make no sense.
+///
+/// Returns: Result<(), String> --- every problem found, one per line.
+fn validate(settings: &AppSettings) -> Result<(), String> {
+    let mut errors = Vec::new();
+    {
+        let mut check = |ok: bool, message: &str| {
+            if !ok {
+                errors.push(message.to_owned());
+            }
+        };
+
+        let server = &settings.server;
+        check(
+            server.address.parse::<IpAddr>().is_ok(),
+            "server.address: must be an IP address",
+        );
+        check(server.port > 0, "server.port: must be a port number");
+        check(server.workers > 0, "server.workers: must be at least 1");
+        if server.tls.enabled {
+            check(
+                Path::new(&server.tls.certs).is_file(),
+                "server.tls.certs: must name a PEM file",
+            );
+            check(
+                Path::new(&server.tls.key).is_file(),
+                "server.tls.key: must name a PEM file",
+            );
+        }
+        if server.internal.enabled {
+            check(
+                server.internal.address.parse::<IpAddr>().is_ok(),
+                "server.internal.address: must be an IP address",
+            );
+            check(
+                server.internal.port > 0,
+                "server.internal.port: must be a port number",
+            );
+            check(
+                server.internal.address != server.address || server.internal.port != server.port,
+                "server.internal.port: must differ from server.port on the same address",
+            );
+        }
+
+        let db = &settings.db;
+        check(db.pool_size > 0, "db.pool_size: must be at least 1");
+        match db.backend {
+            DatabaseBackend::Postgres | DatabaseBackend::Mysql => {
+                check(!db.host.is_empty(), "db.host: must be set for this backend");
+                check(
+                    db.port > 0 && db.port <= 65_535,
+                    "db.port: must be a port number",
+                );
+                check(!db.user.is_empty(), "db.user: must be set for this backend");
+                check(
+                    !db.db_name.is_empty(),
+                    "db.db_name: must be set for this backend",
+                );
+            }
+            DatabaseBackend::Sqlite => check(
+                !db.path.is_empty(),
+                "db.path: must be set for the sqlite backend",
+            ),
+            DatabaseBackend::Memory => (),
+        }
+
+        let oauth = &settings.oauth;
+        check(
+            oauth.access_token_ttl > 0,
+            "oauth.access_token_ttl: must be positive",
+        );
+        check(
+            oauth.refresh_token_ttl == -1 || oauth.refresh_token_ttl > 0,
+            "oauth.refresh_token_ttl: must be positive, or -1 for no idle timeout",
+        );
+        check(
+            oauth.refresh_token_max_lifetime == -1 || oauth.refresh_token_max_lifetime > 0,
+            "oauth.refresh_token_max_lifetime: must be positive, or -1 for no limit",
+        );
+        check(
+            oauth.online_refresh_token_ttl > 0,
+            "oauth.online_refresh_token_ttl: must be positive",
+        );
+
+        check(
+            settings.cache.max_ttl >= 0,
+            "cache.max_ttl: must not be negative",
+        );
+        check(
+            settings.cache.revocation_check_interval >= 0,
+            "cache.revocation_check_interval: must not be negative",
+        );
+
+        if settings.pruning.enabled {
+            check(
+                settings.pruning.interval > 0,
+                "pruning.interval: must be positive",
+            );
+            check(
+                settings.pruning.grace_period >= 0,
+                "pruning.grace_period: must not be negative",
+            );
+            check(
+                settings.pruning.batch_size > 0,
+                "pruning.batch_size: must be positive",
+            );
+        }
+
+        let logging = &settings.logging;
+        if logging.destination != LogDestination::Stdout {
+            check(
+                !logging.path.is_empty(),
+                "logging.path: must be set to log to a file",
+            );
+        }
+        if logging.destination == LogDestination::RollingFile {
+            check(logging.max_size > 0, "logging.max_size: must be positive");
+        }
+
+        let tracing = &settings.tracing;
+        if tracing.enabled {
+            check(
+                tracing.sample_ratio >= 0.0 && tracing.sample_ratio <= 1.0,
+                "tracing.sample_ratio: must be between 0 and 1",
+            );
+            check(
+                tracing.export_interval > 0,
+                "tracing.export_interval: must be positive",
+            );
+            match tracing.exporter {
+                TraceExporter::Otlp => check(
+                    tracing.endpoint.starts_with("http://")
+                        || tracing.endpoint.starts_with("https://"),
+                    "tracing.endpoint: must be an http:// or https:// URL",
+                ),
+                TraceExporter::File => check(
+                    !tracing.path.is_empty(),
+                    "tracing.path: must be set for the file exporter",
+                ),
+            }
+        }
+    }
+
+    // Admin tokens are listed by digest, never in plaintext
+    let mut names: Vec<&String> = settings.admin.tokens.keys().collect();
+    names.sort();
+    for name in names {
+        let digest = &settings.admin.tokens[name];
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            errors.push(format!(
+                "admin.tokens.{}: must be the hex SHA-256 digest of the token",
+                name
+            ));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // The environment is shared by every test, so each test reads variables
+    // under a prefix of its own, which `load` never looks at.
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("oa2p-settings-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn example() -> AppSettings {
+        load_from(Path::new("config.example.toml"), true, "OA2PTEST_EXAMPLE").unwrap()
+    }
+
+    #[test]
+    fn layers_the_environment_and_secret_files_over_the_config_file() {
+        let config = temp_file(
+            "layers.toml",
+            "[db]\n\
+             backend = \"memory\"\n\
+             user = \"file-user\"\n\
+             pass = \"file-pass\"\n\
+             db_name = \"file-db\"\n\
+             pool_size = 3\n\
+             [oauth]\n\
+             access_token_ttl = 3600\n\
+             refresh_token_ttl = 86400\n",
+        );
+        let secret = temp_file("layers-pass", "secret-pass\n");
+        env::set_var("OA2PTEST_LAYERS_DB__USER", "env-user");
+        env::set_var("OA2PTEST_LAYERS_DB__PASS", "env-pass");
+        env::set_var("OA2PTEST_LAYERS_DB__POOL_SIZE", "5");
+        env::set_var("OA2PTEST_LAYERS_DB__PASS_FILE", &secret);
+
+        let settings = load_from(&config, true, "OA2PTEST_LAYERS").unwrap();
+        assert_eq!(settings.db.db_name, "file-db");
+        assert_eq!(settings.db.user, "env-user");
+        assert_eq!(settings.db.pool_size, 5);
+        assert_eq!(settings.db.pass, "secret-pass");
+    }
+
+    #[test]
+    fn reports_unreadable_sources() {
+        let missing = env::temp_dir().join("oa2p-settings-missing.toml");
+        let error = load_from(&missing, true, "OA2PTEST_MISSING").err().unwrap();
+        assert!(error.starts_with(&format!("{}: ", missing.display())));
+
+        env::set_var("OA2PTEST_UNREADABLE_DB__USER_FILE", &missing);
+        let error = load_from(
+            Path::new("config.example.toml"),
+            true,
+            "OA2PTEST_UNREADABLE",
+        )
+        .err()
+        .unwrap();
+        assert!(error.starts_with(&format!(
+            "db.user_file: unable to read {}: ",
+            missing.display()
+        )));
+    }
+
+    #[test]
+    fn accepts_the_example_config() {
+        assert!(validate(&example()).is_ok());
+    }
+
+    #[test]
+    fn reports_every_invalid_setting_at_once() {
+        let mut settings = example();
+        settings.server.port = 0;
+        settings.server.workers = 0;
+        settings.db.host = String::new();
+        settings.oauth.access_token_ttl = -1;
+        settings.oauth.refresh_token_ttl = 0;
+        settings.cache.revocation_check_interval = -1;
+        settings
+            .admin
+            .tokens
+            .insert("oncall".to_owned(), "not a digest".to_owned());
+
+        assert_eq!(
+            validate(&settings).err().unwrap(),
+            "server.port: must be a port number\n\
+             server.workers: must be at least 1\n\
+             db.host: must be set for this backend\n\
+             oauth.access_token_ttl: must be positive\n\
+             oauth.refresh_token_ttl: must be positive, or -1 for no idle timeout\n\
+             cache.revocation_check_interval: must not be negative\n\
+             admin.tokens.oncall: must be the hex SHA-256 digest of the token"
+        );
+    }
+
+    #[test]
+    fn only_checks_the_settings_of_the_selected_backend_and_exporter() {
+        let mut settings = example();
+        settings.db.backend = DatabaseBackend::Sqlite;
+        settings.db.host = String::new();
+        settings.db.path = String::new();
+        assert_eq!(
+            validate(&settings).err().unwrap(),
+            "db.path: must be set for the sqlite backend"
+        );
+
+        settings.db.backend = DatabaseBackend::Memory;
+        settings.tracing.enabled = true;
+        for endpoint in &[
+            "http://localhost:4318/v1/traces",
+            "https://collector/v1/traces",
+        ] {
+            settings.tracing.endpoint = endpoint.to_string();
+            assert!(validate(&settings).is_ok());
+        }
+        settings.tracing.endpoint = "localhost:4318".to_owned();
+        assert_eq!(
+            validate(&settings).err().unwrap(),
+            "tracing.endpoint: must be an http:// or https:// URL"
+        );
+    }
+
+    #[test]
+    fn reports_changed_settings_and_hides_secrets() {
+        let old = example();
+        assert!(diff(&old, &old.clone()).is_empty());
+
+        let mut new = old.clone();
+        new.cache.max_ttl = 30;
+        new.db.pass = "rotated".to_owned();
+        new.admin
+            .tokens
+            .insert("oncall".to_owned(), "ab".repeat(32));
+        let changes = diff(&old, &new);
+        assert_eq!(
+            changes.keys().collect::<Vec<_>>(),
+            vec!["admin.tokens.oncall", "cache.max_ttl", "db.pass"]
+        );
+        assert_eq!(changes["cache.max_ttl"], "60 -> 30");
+        assert_eq!(changes["db.pass"], "value hidden");
+        assert!(changes["admin.tokens.oncall"].starts_with("unset -> "));
+    }
+
+    #[test]
+    fn takes_only_the_reloadable_settings() {
+        let current = example();
+        let mut loaded = current.clone();
+        loaded.oauth.access_token_ttl = 600;
+        loaded.cache.capacity = 10;
+        loaded.pruning.interval = 60;
+        loaded.tracing.sample_ratio = 0.5;
+        loaded.server.port = 8080;
+        loaded.db.pool_size = 1;
+        loaded.pruning.enabled = false;
+        loaded.tracing.enabled = true;
+
+        let merged = with_reloadable(&current, &loaded).unwrap();
+        assert_eq!(merged.oauth.access_token_ttl, 600);
+        assert_eq!(merged.cache.capacity, 10);
+        assert_eq!(merged.pruning.interval, 60);
+        assert_eq!(merged.tracing.sample_ratio, 0.5);
+        assert_eq!(merged.server.port, current.server.port);
+        assert_eq!(merged.db.pool_size, current.db.pool_size);
+        assert!(merged.pruning.enabled);
+        assert!(!merged.tracing.enabled);
+    }
+
+    #[test]
+    fn rejects_reloaded_settings_that_are_invalid() {
+        let current = example();
+        let mut loaded = current.clone();
+        loaded.oauth.access_token_ttl = 0;
+        assert_eq!(
+            with_reloadable(&current, &loaded).err().unwrap(),
+            "oauth.access_token_ttl: must be positive"
+        );
+
+        // Only checked along with a setting that needs a restart
+        let mut current = example();
+        current.tracing.enabled = true;
+        let mut loaded = current.clone();
+        loaded.tracing.sample_ratio = 2.0;
+        assert_eq!(
+            with_reloadable(&current, &loaded).err().unwrap(),
+            "tracing.sample_ratio: must be between 0 and 1"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::SETTINGS;
+use crate::server;
+use crate::settings;
+use crate::signals;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: u64 = 2;
+
+/// Starts the thread reloading the configuration when the process receives
+/// SIGHUP, or when the config file is modified. The thread also reloads the
+/// TLS certificate and key whenever they are modified.
+///
+/// Returns: io::Result<JoinHandle<()>> --- the thread, which never finishes.
+pub fn watch() -> io::Result<JoinHandle<()>> {
+    let (path, _) = settings::config_path();
+    let mut last_modified = modified(&path);
+    let mut last_tls_modified = tls_modified();
+
+    thread::Builder::new()
+        .name("oa2p-config-reloader".to_owned())
+        .spawn(move || loop {
+            thread::sleep(Duration::from_secs(POLL_INTERVAL));
+
+            let tls_modified = tls_modified();
+            if tls_modified != last_tls_modified {
+                last_tls_modified = tls_modified;
+                reload_tls();
+            }
+
+            let hangup = signals::take_hangup();
+            let modified = modified(&path);
+            if !hangup && modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            info!(
+                "Reloading the configuration from {} after {}",
+                path.display(),
+                if hangup {
+                    "SIGHUP"
+                } else {
+                    "a change to the file"
+                }
+            );
+            if let Err(e) = settings::reload() {
+                error!(
+                    "Rejected the new configuration, keeping the current one:\n{}",
+                    e
+                );
+            }
+        })
+}
+
+// A renewed pair that cannot be used is only logged, and the public listener
+// keeps serving the pair it has.
+fn reload_tls() {
+    info!("The TLS certificate or key changed, reloading them");
+    if let Err(e) = server::reload_tls() {
+        error!(
+            "Unable to use the new TLS certificate and key, keeping the current ones: {}",
+            e
+        );
+    }
+}
+
+fn tls_modified() -> Option<(Option<SystemTime>, Option<SystemTime>)> {
+    let tls = &SETTINGS.server.tls;
+    if tls.enabled {
+        Some((modified(Path::new(&tls.certs)), modified(Path::new(&tls.key))))
+    } else {
+        None
+    }
+}
+
+// Mounted ConfigMaps are updated by swapping a symlink, which the metadata of
+// the path follows.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
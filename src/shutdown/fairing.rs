@@ -0,0 +1,38 @@
+use crate::shutdown::{is_draining, IN_FLIGHT};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Data, Request, Response};
+use std::sync::atomic::Ordering;
+
+// Whether a request was counted lives with the request, since the worker
+// thread handling it may change at every await.
+struct Counted(bool);
+
+/// Counts the requests in flight, so that shutting down can report any it cut
+/// off, and asks clients to close their connection once the provider is
+/// draining.
+pub struct DrainRequests;
+
+#[rocket::async_trait]
+impl Fairing for DrainRequests {
+    fn info(&self) -> Info {
+        Info {
+            name: "Connection draining",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        request.local_cache(|| Counted(true));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if is_draining() {
+            response.set_header(Header::new("Connection", "close"));
+        }
+        if request.local_cache(|| Counted(false)).0 {
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! The shutdown module stops the provider gracefully when it receives SIGTERM
+//! or SIGINT, so that rolling deployments do not cut off token requests
+//! halfway through.
+//!
+//! Once a signal arrives, readiness starts failing so that load balancers stop
+//! sending new requests, and responses ask clients to close their keep-alive
+//! connections. The listeners keep serving for `shutdown.drain_delay` seconds,
+//! which gives load balancers time to notice that the instance is no longer
+//! ready, and are then stopped through Rocket's own graceful shutdown, see
+//! `server::stop`. Once they have stopped, `finish` closes the database pool,
+//! exports the remaining spans and flushes the logs.
+
+mod fairing;
+
+pub use self::fairing::DrainRequests;
+
+use crate::server;
+use crate::settings;
+use crate::signals;
+use crate::storage;
+use crate::tracing;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How often the signal flag is checked.
+const POLL_INTERVAL: u64 = 100;
+
+static DRAINING: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the provider is shutting down.
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::SeqCst)
+}
+
+fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+/// Starts the thread waiting for SIGTERM or SIGINT and stopping the
+/// listeners, see the module documentation.
+///
+/// Returns: io::Result<JoinHandle<()>> --- the thread, which finishes once the
+/// listeners have been told to stop.
+pub fn spawn() -> io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("oa2p-shutdown".to_owned())
+        .spawn(|| {
+            while !signals::take_terminate() {
+                thread::sleep(Duration::from_millis(POLL_INTERVAL));
+            }
+            drain();
+            server::stop();
+        })
+}
+
+/// Releases what the provider holds once the listeners have stopped: the
+/// database pool, the spans still queued for the trace exporter thread, when
+/// tracing is enabled, and the logs.
+pub fn finish(exporter: Option<JoinHandle<()>>) {
+    let remaining = in_flight();
+    if remaining > 0 {
+        warn!(
+            "Stopped with {} requests cut off after shutdown.grace and shutdown.mercy",
+            remaining
+        );
+    }
+    storage::close();
+
+    if let Some(exporter) = exporter {
+        tracing::close();
+        if exporter.join().is_err() {
+            warn!("The trace exporter failed while exporting the remaining spans");
+        }
+    }
+    info!("Shut down");
+    log::logger().flush();
+}
+
+fn drain() {
+    let started = Instant::now();
+    let drain_delay = Duration::from_secs(settings::current().shutdown.drain_delay);
+
+    DRAINING.store(true, Ordering::SeqCst);
+    info!(
+        "Shutting down, stopping the listeners in {} seconds with {} requests in flight",
+        drain_delay.as_secs(),
+        in_flight()
+    );
+
+    // A second signal skips the wait
+    while started.elapsed() < drain_delay && !signals::take_terminate() {
+        thread::sleep(Duration::from_millis(POLL_INTERVAL));
+    }
+    info!("Stopping the listeners with {} requests in flight", in_flight());
+}
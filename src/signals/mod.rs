@@ -0,0 +1,44 @@
+//! The signals module turns the signals the provider reacts to into flags,
+//! which the threads acting on them poll. Nothing but setting a flag is safe
+//! to do inside a signal handler.
+//!
+//! - SIGHUP asks for the configuration to be reloaded, see `settings`.
+//! - SIGTERM and SIGINT ask the provider to shut down, see `shutdown`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static HANGUP: AtomicBool = AtomicBool::new(false);
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+/// Installs the signal handlers.
+#[cfg(unix)]
+pub fn install() {
+    type Handler = extern "C" fn(libc::c_int);
+
+    extern "C" fn on_hangup(_: libc::c_int) {
+        HANGUP.store(true, Ordering::SeqCst);
+    }
+
+    extern "C" fn on_terminate(_: libc::c_int) {
+        TERMINATE.store(true, Ordering::SeqCst);
+    }
+
+    unsafe {
+        libc::signal(libc::SIGHUP, on_hangup as Handler as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_terminate as Handler as libc::sighandler_t);
+        libc::signal(libc::SIGINT, on_terminate as Handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Whether SIGHUP was received since the last call.
+pub fn take_hangup() -> bool {
+    HANGUP.swap(false, Ordering::SeqCst)
+}
+
+/// Whether SIGTERM or SIGINT was received since the last call.
+pub fn take_terminate() -> bool {
+    TERMINATE.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,644 @@
+//! The in-memory backend, for development servers and integration tests.
+//! Nothing is persisted: every table starts out empty apart from the standard
+//! grant types and whatever clients are listed in the `db.seed_file`, and is
+//! lost when the process exits. The semantics otherwise match the database
+//! backends, including generating ids on insert.
+
+use crate::SETTINGS;
+use crate::migrations::Migration;
+use crate::models::db::*;
+use crate::storage::*;
+use chrono::NaiveDateTime;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use uuid::Uuid;
+
+lazy_static! {
+    static ref TABLES: RwLock<Tables> = {
+        let mut tables = Tables::default();
+        for name in &GRANT_TYPES {
+            let id = tables.next_id();
+            tables.grant_types.push(GrantType {
+                id,
+                name: (*name).to_owned(),
+            });
+        }
+
+        RwLock::new(tables)
+    };
+}
+
+const GRANT_TYPES: [&str; 5] = [
+    "authorization_code",
+    "token",
+    "password",
+    "client_credentials",
+    "refresh_token",
+];
+
+/// The seed file lists the clients the store starts out with, e.g.
+///
+/// ```toml
+/// [[clients]]
+/// identifier = "abcd1234"
+/// secret = "$2y$05$WV4774ZgHYmyY2gWdVB2MuILGdBrG2HP1c6OvPxuSAlphNU2bQ.au"
+/// response_type = "confidential"
+/// redirect_uris = ["https://localhost:8080/callback"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+struct Seed {
+    #[serde(default)]
+    clients: Vec<SeedClient>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SeedClient {
+    identifier: String,
+    secret: String,
+    response_type: String,
+    #[serde(default)]
+    redirect_uris: Vec<String>,
+    token_reuse_min_ttl: Option<i32>,
+    issue_refresh_tokens: Option<bool>,
+    rotate_refresh_tokens: Option<bool>,
+}
+
+/// Loads the clients listed in `db.seed_file`. Only the first call loads
+/// them, so every listener built in a process shares the same clients.
+///
+/// Returns: Result<(), String>
+/// - Ok(())       --- the clients are loaded, or there is no seed file
+/// - Err(String)  --- the seed file cannot be read, or lists a client twice
+pub fn seed() -> Result<(), String> {
+    seed_from(&SETTINGS.db.seed_file)
+}
+
+/// Loads the clients listed in a seed file, unless a seed file has been
+/// loaded already. An empty path loads nothing.
+pub fn seed_from(path: &str) -> Result<(), String> {
+    let mut tables = write().map_err(|e| e.to_string())?;
+    if tables.seeded || path.is_empty() {
+        return Ok(());
+    }
+    let seed = load_seed(path)?;
+    tables
+        .apply_seed(seed)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    tables.seeded = true;
+    Ok(())
+}
+
+fn load_seed(path: &str) -> Result<Seed, String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| format!("{}: {}", path, e))?;
+    toml::from_str(&contents).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[derive(Default)]
+struct Tables {
+    seeded: bool,
+    last_id: i32,
+    clients: Vec<Client>,
+    client_redirect_uris: Vec<ClientRedirectUri>,
+    grant_types: Vec<GrantType>,
+    access_tokens: Vec<AccessToken>,
+    refresh_tokens: Vec<RefreshToken>,
+    auth_codes: Vec<AuthCode>,
+    token_lifetimes: Vec<TokenLifetime>,
+    token_revocations: Vec<Revocation>,
+}
+
+impl Tables {
+    // Ids come from a single sequence shared by every table, which keeps them
+    // unique per table just like the database sequences do.
+    fn next_id(&mut self) -> i32 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn apply_seed(&mut self, seed: Seed) -> Result<(), String> {
+        for c in seed.clients {
+            if self.clients.iter().any(|e| e.identifier == c.identifier) {
+                return Err(format!("duplicate client identifier `{}`", c.identifier));
+            }
+
+            let client_id = self.next_id();
+            for uri in c.redirect_uris {
+                let id = self.next_id();
+                self.client_redirect_uris.push(ClientRedirectUri {
+                    id,
+                    client_id,
+                    redirect_uri: uri,
+                });
+            }
+            self.clients.push(Client {
+                id: client_id,
+                identifier: c.identifier,
+                secret: c.secret,
+                response_type: c.response_type,
+                token_reuse_min_ttl: c.token_reuse_min_ttl,
+                issue_refresh_tokens: c.issue_refresh_tokens,
+                rotate_refresh_tokens: c.rotate_refresh_tokens,
+                disabled_at: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn read() -> StorageResult<RwLockReadGuard<'static, Tables>> {
+    TABLES
+        .read()
+        .map_err(|_| StorageError::Backend("the in-memory store is poisoned".to_owned()))
+}
+
+fn write() -> StorageResult<RwLockWriteGuard<'static, Tables>> {
+    TABLES
+        .write()
+        .map_err(|_| StorageError::Backend("the in-memory store is poisoned".to_owned()))
+}
+
+fn found<T: Clone>(item: Option<&T>) -> StorageResult<T> {
+    item.cloned().ok_or(StorageError::NotFound)
+}
+
+pub struct MemoryStore;
+
+impl MemoryStore {
+    pub fn connect() -> StorageResult<MemoryStore> {
+        Ok(MemoryStore)
+    }
+}
+
+impl ClientStore for MemoryStore {
+    fn find_client(&self, id: i32) -> StorageResult<Client> {
+        found(read()?.clients.iter().find(|c| c.id == id))
+    }
+
+    fn find_client_by_identifier(&self, identifier: &str) -> StorageResult<Client> {
+        found(read()?.clients.iter().find(|c| c.identifier == identifier))
+    }
+
+    fn set_client_disabled(
+        &self,
+        id: i32,
+        disabled_at: Option<NaiveDateTime>,
+    ) -> StorageResult<Client> {
+        let mut tables = write()?;
+        let client = tables
+            .clients
+            .iter_mut()
+            .find(|c| c.id == id)
+            .ok_or(StorageError::NotFound)?;
+        client.disabled_at = disabled_at;
+        Ok(client.clone())
+    }
+
+    fn find_client_redirect_uris(&self, client_id: i32) -> StorageResult<Vec<ClientRedirectUri>> {
+        Ok(read()?
+            .client_redirect_uris
+            .iter()
+            .filter(|u| u.client_id == client_id)
+            .cloned()
+            .collect())
+    }
+
+    fn find_token_lifetimes(
+        &self,
+        client_id: i32,
+        grant_id: i32,
+    ) -> StorageResult<Vec<TokenLifetime>> {
+        Ok(read()?
+            .token_lifetimes
+            .iter()
+            .filter(|o| o.client_id.map_or(true, |id| id == client_id))
+            .filter(|o| o.grant_id.map_or(true, |id| id == grant_id))
+            .cloned()
+            .collect())
+    }
+
+    fn list_token_lifetimes(&self) -> StorageResult<Vec<TokenLifetime>> {
+        Ok(read()?.token_lifetimes.clone())
+    }
+
+    fn save_token_lifetime(&self, new_override: &NewTokenLifetime) -> StorageResult<TokenLifetime> {
+        check_token_lifetime(new_override)?;
+        let mut tables = write()?;
+        if let Some(existing) = tables.token_lifetimes.iter_mut().find(|o| {
+            o.client_id == new_override.client_id && o.grant_id == new_override.grant_id
+                && o.scope == new_override.scope
+        }) {
+            existing.access_token_ttl = new_override.access_token_ttl;
+            existing.refresh_token_ttl = new_override.refresh_token_ttl;
+            return Ok(existing.clone());
+        }
+
+        let saved = TokenLifetime {
+            id: tables.next_id(),
+            client_id: new_override.client_id,
+            grant_id: new_override.grant_id,
+            scope: new_override.scope.clone(),
+            access_token_ttl: new_override.access_token_ttl,
+            refresh_token_ttl: new_override.refresh_token_ttl,
+        };
+        tables.token_lifetimes.push(saved.clone());
+        Ok(saved)
+    }
+
+    fn delete_token_lifetime(&self, id: i32) -> StorageResult<()> {
+        let mut tables = write()?;
+        let before = tables.token_lifetimes.len();
+        tables.token_lifetimes.retain(|o| o.id != id);
+        match before - tables.token_lifetimes.len() {
+            0 => Err(StorageError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl GrantTypeStore for MemoryStore {
+    fn find_grant_type(&self, id: i32) -> StorageResult<GrantType> {
+        found(read()?.grant_types.iter().find(|g| g.id == id))
+    }
+
+    fn find_grant_type_by_name(&self, name: &str) -> StorageResult<GrantType> {
+        found(read()?.grant_types.iter().find(|g| g.name == name))
+    }
+}
+
+impl TokenStore for MemoryStore {
+    fn create_access_token(&self, new_token: &NewAccessToken) -> StorageResult<AccessToken> {
+        let mut tables = write()?;
+        let token = AccessToken {
+            id: tables.next_id(),
+            token: None,
+            token_hash: Some(new_token.token_hash.clone()),
+            client_id: new_token.client_id,
+            grant_id: new_token.grant_id,
+            scope: new_token.scope.clone(),
+            issued_at: new_token.issued_at,
+            expires_at: new_token.expires_at,
+            refresh_token_id: new_token.refresh_token_id,
+            auth_code_id: new_token.auth_code_id,
+            revoked_at: None,
+        };
+        tables.access_tokens.push(token.clone());
+        Ok(token)
+    }
+
+    fn find_access_token(&self, token_hash: &str) -> StorageResult<AccessToken> {
+        found(read()?
+            .access_tokens
+            .iter()
+            .find(|t| t.token_hash.as_ref().is_some_and(|h| h == token_hash)))
+    }
+
+    // Nothing in this store predates hashing.
+    fn find_legacy_access_token(&self, _token: &Uuid) -> StorageResult<AccessToken> {
+        Err(StorageError::NotFound)
+    }
+
+    fn find_recent_access_tokens(
+        &self,
+        client_id: i32,
+        grant_id: i32,
+        expires_after: NaiveDateTime,
+        limit: i64,
+    ) -> StorageResult<Vec<AccessToken>> {
+        let mut tokens: Vec<AccessToken> = read()?
+            .access_tokens
+            .iter()
+            .filter(|t| t.client_id == client_id && t.grant_id == grant_id)
+            .filter(|t| t.expires_at > expires_after)
+            .filter(|t| t.revoked_at.is_none())
+            .cloned()
+            .collect();
+        tokens.sort_by_key(|t| Reverse(t.issued_at));
+        tokens.truncate(limit as usize);
+        Ok(tokens)
+    }
+
+    fn create_refresh_token(&self, new_token: &NewRefreshToken) -> StorageResult<RefreshToken> {
+        let mut tables = write()?;
+        let token = RefreshToken {
+            id: tables.next_id(),
+            token: None,
+            token_hash: Some(new_token.token_hash.clone()),
+            client_id: new_token.client_id,
+            scope: new_token.scope.clone(),
+            issued_at: new_token.issued_at,
+            expires_at: new_token.expires_at,
+            last_used_at: None,
+            absolute_expires_at: new_token.absolute_expires_at,
+            parent_id: new_token.parent_id,
+            auth_code_id: new_token.auth_code_id,
+            revoked_at: None,
+        };
+        tables.refresh_tokens.push(token.clone());
+        Ok(token)
+    }
+
+    fn find_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshToken> {
+        found(read()?
+            .refresh_tokens
+            .iter()
+            .find(|t| t.token_hash.as_ref().is_some_and(|h| h == token_hash)))
+    }
+
+    fn find_active_refresh_token(
+        &self,
+        client_id: i32,
+        token_hash: &str,
+        now: NaiveDateTime,
+    ) -> StorageResult<RefreshToken> {
+        found(read()?
+            .refresh_tokens
+            .iter()
+            .filter(|t| t.token_hash.as_ref().is_some_and(|h| h == token_hash))
+            .filter(|t| t.client_id == client_id && t.revoked_at.is_none())
+            .find(|t| t.expires_at.map_or(true, |e| e > now)))
+    }
+
+    fn find_active_legacy_refresh_token(
+        &self,
+        _client_id: i32,
+        _token: &Uuid,
+        _now: NaiveDateTime,
+    ) -> StorageResult<RefreshToken> {
+        Err(StorageError::NotFound)
+    }
+
+    fn hash_legacy_refresh_token(&self, id: i32, token_hash: &str) -> StorageResult<RefreshToken> {
+        let mut tables = write()?;
+        match tables.refresh_tokens.iter_mut().find(|t| t.id == id) {
+            Some(token) => {
+                token.token = None;
+                token.token_hash = Some(token_hash.to_owned());
+                Ok(token.clone())
+            }
+            None => Err(StorageError::NotFound),
+        }
+    }
+
+    fn update_refresh_token_expiry(
+        &self,
+        id: i32,
+        last_used_at: NaiveDateTime,
+        expires_at: Option<NaiveDateTime>,
+    ) -> StorageResult<RefreshToken> {
+        let mut tables = write()?;
+        match tables.refresh_tokens.iter_mut().find(|t| t.id == id) {
+            Some(token) => {
+                token.last_used_at = Some(last_used_at);
+                token.expires_at = expires_at;
+                Ok(token.clone())
+            }
+            None => Err(StorageError::NotFound),
+        }
+    }
+
+    fn find_child_refresh_token_ids(&self, parent_ids: &[i32]) -> StorageResult<Vec<i32>> {
+        Ok(read()?
+            .refresh_tokens
+            .iter()
+            .filter(|t| t.parent_id.is_some_and(|p| parent_ids.contains(&p)))
+            .filter(|t| t.revoked_at.is_none())
+            .map(|t| t.id)
+            .collect())
+    }
+
+    fn find_access_token_ids_by_refresh_tokens(
+        &self,
+        refresh_token_ids: &[i32],
+    ) -> StorageResult<Vec<i32>> {
+        Ok(read()?
+            .access_tokens
+            .iter()
+            .filter(|t| t.refresh_token_id.is_some_and(|r| refresh_token_ids.contains(&r)))
+            .filter(|t| t.revoked_at.is_none())
+            .map(|t| t.id)
+            .collect())
+    }
+
+    fn find_refresh_token_ids_by_auth_code(&self, auth_code_id: i32) -> StorageResult<Vec<i32>> {
+        Ok(read()?
+            .refresh_tokens
+            .iter()
+            .filter(|t| t.auth_code_id == Some(auth_code_id) && t.revoked_at.is_none())
+            .map(|t| t.id)
+            .collect())
+    }
+
+    fn find_access_token_ids_by_auth_code(&self, auth_code_id: i32) -> StorageResult<Vec<i32>> {
+        Ok(read()?
+            .access_tokens
+            .iter()
+            .filter(|t| t.auth_code_id == Some(auth_code_id) && t.revoked_at.is_none())
+            .map(|t| t.id)
+            .collect())
+    }
+
+    fn revoke_access_tokens(&self, ids: &[i32], revoked_at: NaiveDateTime) -> StorageResult<usize> {
+        let mut tables = write()?;
+        let mut revoked = 0;
+        for token in tables.access_tokens.iter_mut() {
+            if ids.contains(&token.id) && token.revoked_at.is_none() {
+                token.revoked_at = Some(revoked_at);
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+
+    fn revoke_refresh_tokens(
+        &self,
+        ids: &[i32],
+        revoked_at: NaiveDateTime,
+    ) -> StorageResult<usize> {
+        let mut tables = write()?;
+        let mut revoked = 0;
+        for token in tables.refresh_tokens.iter_mut() {
+            if ids.contains(&token.id) && token.revoked_at.is_none() {
+                token.revoked_at = Some(revoked_at);
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+}
+
+impl CodeStore for MemoryStore {
+    fn find_auth_code(&self, client_id: i32, code: &str) -> StorageResult<AuthCode> {
+        found(read()?
+            .auth_codes
+            .iter()
+            .find(|c| c.client_id == client_id && c.name == code))
+    }
+
+    fn delete_auth_code(&self, id: i32) -> StorageResult<()> {
+        let mut tables = write()?;
+        let before = tables.auth_codes.len();
+        tables.auth_codes.retain(|c| c.id != id);
+        match before - tables.auth_codes.len() {
+            0 => Err(StorageError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl RevocationStore for MemoryStore {
+    fn find_revocable_access_tokens(
+        &self,
+        filter: &TokenFilter,
+        after_id: i32,
+        limit: i64,
+    ) -> StorageResult<Vec<(i32, String)>> {
+        let tables = read()?;
+        let mut matching: Vec<(i32, String)> = tables
+            .access_tokens
+            .iter()
+            .filter(|t| t.id > after_id && t.revoked_at.is_none())
+            .filter(|t| t.expires_at > filter.live_at)
+            .filter(|t| matches(filter, t.client_id, t.issued_at))
+            .map(|t| (t.id, t.scope.clone()))
+            .collect();
+        matching.sort();
+        matching.truncate(limit as usize);
+        Ok(matching)
+    }
+
+    fn find_revocable_refresh_tokens(
+        &self,
+        filter: &TokenFilter,
+        after_id: i32,
+        limit: i64,
+    ) -> StorageResult<Vec<(i32, String)>> {
+        let tables = read()?;
+        let mut matching: Vec<(i32, String)> = tables
+            .refresh_tokens
+            .iter()
+            .filter(|t| t.id > after_id && t.revoked_at.is_none())
+            .filter(|t| t.expires_at.map_or(true, |e| e > filter.live_at))
+            .filter(|t| matches(filter, t.client_id, t.issued_at))
+            .map(|t| (t.id, t.scope.clone()))
+            .collect();
+        matching.sort();
+        matching.truncate(limit as usize);
+        Ok(matching)
+    }
+
+    fn record_revocation(&self, entry: &NewRevocation) -> StorageResult<()> {
+        let mut tables = write()?;
+        let id = tables.next_id();
+        tables.token_revocations.push(Revocation {
+            id,
+            revoked_at: entry.revoked_at,
+            actor: entry.actor.clone(),
+            reason: entry.reason.clone(),
+            selection: entry.selection.clone(),
+            access_tokens: entry.access_tokens,
+            refresh_tokens: entry.refresh_tokens,
+        });
+        Ok(())
+    }
+
+    fn list_revocations(&self, limit: i64) -> StorageResult<Vec<Revocation>> {
+        Ok(read()?
+            .token_revocations
+            .iter()
+            .rev()
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    fn latest_revocation_id(&self) -> StorageResult<Option<i32>> {
+        Ok(read()?.token_revocations.last().map(|r| r.id))
+    }
+}
+
+fn matches(filter: &TokenFilter, client_id: i32, issued_at: NaiveDateTime) -> bool {
+    filter.client_id.map_or(true, |c| c == client_id)
+        && filter.issued_after.map_or(true, |t| issued_at >= t)
+        && filter.issued_before.map_or(true, |t| issued_at < t)
+}
+
+// There is no schema to migrate, and `migrations::for_backend` has nothing
+// for this backend, so the store is always current.
+impl MigrationStore for MemoryStore {
+    fn applied_migrations(&self) -> StorageResult<Vec<i32>> {
+        Ok(Vec::new())
+    }
+
+    fn apply_migration(&self, _migration: &Migration) -> StorageResult<()> {
+        Ok(())
+    }
+
+    fn revert_migration(&self, _migration: &Migration) -> StorageResult<()> {
+        Ok(())
+    }
+
+    fn mark_migration(&self, _migration: &Migration) -> StorageResult<()> {
+        Ok(())
+    }
+}
+
+// Only one process ever sees these tables, so the lock is always free.
+impl PruneStore for MemoryStore {
+    fn try_lock_pruning(&self) -> StorageResult<bool> {
+        Ok(true)
+    }
+
+    fn unlock_pruning(&self) -> StorageResult<()> {
+        Ok(())
+    }
+
+    fn prune_access_tokens(&self, expired_before: NaiveDateTime, limit: i64) -> StorageResult<usize> {
+        let mut tables = write()?;
+        Ok(prune(&mut tables.access_tokens, limit, |t| {
+            t.expires_at < expired_before || t.revoked_at.is_some_and(|r| r < expired_before)
+        }))
+    }
+
+    fn prune_refresh_tokens(
+        &self,
+        expired_before: NaiveDateTime,
+        limit: i64,
+    ) -> StorageResult<usize> {
+        let mut tables = write()?;
+        let referenced: HashSet<i32> = tables
+            .refresh_tokens
+            .iter()
+            .filter_map(|t| t.parent_id)
+            .chain(tables.access_tokens.iter().filter_map(|t| t.refresh_token_id))
+            .collect();
+        Ok(prune(&mut tables.refresh_tokens, limit, |t| {
+            (t.expires_at.is_some_and(|e| e < expired_before)
+                || t.revoked_at.is_some_and(|r| r < expired_before))
+                && !referenced.contains(&t.id)
+        }))
+    }
+
+    fn prune_auth_codes(&self, expired_before: NaiveDateTime, limit: i64) -> StorageResult<usize> {
+        let mut tables = write()?;
+        Ok(prune(&mut tables.auth_codes, limit, |c| c.expires_at < expired_before))
+    }
+}
+
+fn prune<T, F: Fn(&T) -> bool>(rows: &mut Vec<T>, limit: i64, expired: F) -> usize {
+    let mut pruned = 0;
+    rows.retain(|row| {
+        if (pruned as i64) < limit && expired(row) {
+            pruned += 1;
+            false
+        } else {
+            true
+        }
+    });
+    pruned
+}
@@ -0,0 +1,463 @@
+//! The storage module defines how the rest of the provider talks to its
+//! datastore. Grant handling and the web handlers only ever see the traits in
+//! this module, so they can be exercised against any backend (or a fake one)
+//! without knowing how the data is actually kept. Each backend lives in its own
+//! submodule and implements every store trait.
+
+#[cfg(any(feature = "sqlite", feature = "mysql"))]
+#[macro_use]
+mod text_tokens;
+
+pub mod memory;
+#[cfg(feature = "mysql")]
+pub mod mysql;
+pub mod pool;
+pub mod postgres;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(test)]
+mod tests;
+mod traced;
+
+use crate::DB_POOL;
+use crate::SETTINGS;
+use crate::migrations::Migration;
+use crate::models::configuration::DatabaseBackend;
+use crate::models::db::*;
+use crate::tracing::Span;
+use chrono::NaiveDateTime;
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// The requested record does not exist.
+    NotFound,
+    /// The backend could not be reached, e.g. the connection pool is exhausted.
+    Unavailable(String),
+    /// The backend failed while processing the request.
+    Backend(String),
+    /// The record was refused before reaching the backend.
+    Invalid(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StorageError::NotFound => write!(f, "record not found"),
+            StorageError::Unavailable(ref e) => write!(f, "storage unavailable: {}", e),
+            StorageError::Backend(ref e) => write!(f, "storage error: {}", e),
+            StorageError::Invalid(ref e) => write!(f, "invalid record: {}", e),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for StorageError {
+    fn from(e: diesel::result::Error) -> StorageError {
+        match e {
+            diesel::result::Error::NotFound => StorageError::NotFound,
+            e => StorageError::Backend(e.to_string()),
+        }
+    }
+}
+
+impl From<r2d2::Error> for StorageError {
+    fn from(e: r2d2::Error) -> StorageError {
+        StorageError::Unavailable(e.to_string())
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// Clients, and the per-client policy attached to them.
+pub trait ClientStore {
+    fn find_client(&self, id: i32) -> StorageResult<Client>;
+
+    fn find_client_by_identifier(&self, identifier: &str) -> StorageResult<Client>;
+
+    /// Disables the client as of the given time, or enables it again.
+    fn set_client_disabled(&self, id: i32, disabled_at: Option<NaiveDateTime>)
+        -> StorageResult<Client>;
+
+    // Nothing serves the authorization code grant yet, see `CodeStore`.
+    #[allow(dead_code)]
+    fn find_client_redirect_uris(&self, client_id: i32) -> StorageResult<Vec<ClientRedirectUri>>;
+
+    /// Every token lifetime override that names either the given client and
+    /// grant type, or no client and/or no grant type at all.
+    fn find_token_lifetimes(&self, client_id: i32, grant_id: i32)
+        -> StorageResult<Vec<TokenLifetime>>;
+
+    fn list_token_lifetimes(&self) -> StorageResult<Vec<TokenLifetime>>;
+
+    /// Saves an override, replacing any existing one for the same client,
+    /// grant type and scope. Lifetimes `check_token_lifetime` refuses are
+    /// never saved.
+    fn save_token_lifetime(&self, new_override: &NewTokenLifetime) -> StorageResult<TokenLifetime>;
+
+    fn delete_token_lifetime(&self, id: i32) -> StorageResult<()>;
+}
+
+/// The grant types the provider knows about.
+pub trait GrantTypeStore {
+    fn find_grant_type(&self, id: i32) -> StorageResult<GrantType>;
+
+    fn find_grant_type_by_name(&self, name: &str) -> StorageResult<GrantType>;
+}
+
+/// Access and refresh tokens. Tokens are looked up by the digest of their
+/// plaintext, see `utils::secrets`. The `legacy` lookups find tokens issued
+/// before tokens were hashed, which are still stored in plaintext.
+pub trait TokenStore {
+    fn create_access_token(&self, new_token: &NewAccessToken) -> StorageResult<AccessToken>;
+
+    fn find_access_token(&self, token_hash: &str) -> StorageResult<AccessToken>;
+
+    fn find_legacy_access_token(&self, token: &Uuid) -> StorageResult<AccessToken>;
+
+    /// The most recently issued access tokens for a client and grant type
+    /// that expire after the given time and have not been revoked, newest
+    /// first.
+    fn find_recent_access_tokens(
+        &self,
+        client_id: i32,
+        grant_id: i32,
+        expires_after: NaiveDateTime,
+        limit: i64,
+    ) -> StorageResult<Vec<AccessToken>>;
+
+    fn create_refresh_token(&self, new_token: &NewRefreshToken) -> StorageResult<RefreshToken>;
+
+    /// A refresh token in any state, for revoking it.
+    fn find_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshToken>;
+
+    /// A refresh token owned by the given client that has neither expired as
+    /// of `now` nor been revoked.
+    fn find_active_refresh_token(
+        &self,
+        client_id: i32,
+        token_hash: &str,
+        now: NaiveDateTime,
+    ) -> StorageResult<RefreshToken>;
+
+    fn find_active_legacy_refresh_token(
+        &self,
+        client_id: i32,
+        token: &Uuid,
+        now: NaiveDateTime,
+    ) -> StorageResult<RefreshToken>;
+
+    /// Replaces the plaintext of a legacy refresh token with its digest.
+    fn hash_legacy_refresh_token(&self, id: i32, token_hash: &str) -> StorageResult<RefreshToken>;
+
+    fn update_refresh_token_expiry(
+        &self,
+        id: i32,
+        last_used_at: NaiveDateTime,
+        expires_at: Option<NaiveDateTime>,
+    ) -> StorageResult<RefreshToken>;
+
+    /// The ids of unrevoked refresh tokens whose parent is one of the given
+    /// tokens. Revoking a token revokes its descendants, so nothing below a
+    /// revoked token is left to find.
+    fn find_child_refresh_token_ids(&self, parent_ids: &[i32]) -> StorageResult<Vec<i32>>;
+
+    /// The ids of unrevoked access tokens issued with, or minted from, one of
+    /// the given refresh tokens.
+    fn find_access_token_ids_by_refresh_tokens(
+        &self,
+        refresh_token_ids: &[i32],
+    ) -> StorageResult<Vec<i32>>;
+
+    /// The ids of unrevoked refresh tokens issued for an authorization code.
+    fn find_refresh_token_ids_by_auth_code(&self, auth_code_id: i32) -> StorageResult<Vec<i32>>;
+
+    fn find_access_token_ids_by_auth_code(&self, auth_code_id: i32) -> StorageResult<Vec<i32>>;
+
+    /// Marks the given access tokens as revoked, leaving tokens that already
+    /// are alone.
+    ///
+    /// Returns: StorageResult<usize> --- the number of tokens revoked.
+    fn revoke_access_tokens(&self, ids: &[i32], revoked_at: NaiveDateTime) -> StorageResult<usize>;
+
+    fn revoke_refresh_tokens(&self, ids: &[i32], revoked_at: NaiveDateTime)
+        -> StorageResult<usize>;
+}
+
+/// Authorization codes. The token endpoint does not exchange them yet (see
+/// Known Deviations in the README), but every backend keeps them so that the
+/// grant can be added without touching the storage layer again.
+pub trait CodeStore {
+    #[allow(dead_code)]
+    fn find_auth_code(&self, client_id: i32, code: &str) -> StorageResult<AuthCode>;
+
+    fn delete_auth_code(&self, id: i32) -> StorageResult<()>;
+}
+
+/// The record of schema migrations applied to the backend.
+pub trait MigrationStore {
+    /// The versions of every applied migration, in ascending order. Creates
+    /// the `schema_migrations` table if it does not exist yet.
+    fn applied_migrations(&self) -> StorageResult<Vec<i32>>;
+
+    /// Runs the migration's `up` script and records it, in one transaction
+    /// where the backend supports transactional DDL.
+    fn apply_migration(&self, migration: &Migration) -> StorageResult<()>;
+
+    /// Runs the migration's `down` script and removes its record.
+    fn revert_migration(&self, migration: &Migration) -> StorageResult<()>;
+
+    /// Records the migration as applied without running it, for databases
+    /// whose schema was created by hand.
+    fn mark_migration(&self, migration: &Migration) -> StorageResult<()>;
+}
+
+/// Which tokens a bulk revocation considers. Every criterion that is set must
+/// match, so a filter without any matches every live token.
+#[derive(Clone, Debug)]
+pub struct TokenFilter {
+    pub client_id: Option<i32>,
+    /// Only tokens issued at or after this time match.
+    pub issued_after: Option<NaiveDateTime>,
+    /// Only tokens issued before this time match.
+    pub issued_before: Option<NaiveDateTime>,
+    /// Only tokens that have not expired by this time match.
+    pub live_at: NaiveDateTime,
+}
+
+/// Bulk revocation, and the audit trail of every revocation, see
+/// `utils::revocation`.
+pub trait RevocationStore {
+    /// Up to `limit` unrevoked access tokens matching the filter with an id
+    /// above `after_id`, in id order, as `(id, scope)` pairs. Scopes are
+    /// matched by the caller.
+    fn find_revocable_access_tokens(
+        &self,
+        filter: &TokenFilter,
+        after_id: i32,
+        limit: i64,
+    ) -> StorageResult<Vec<(i32, String)>>;
+
+    fn find_revocable_refresh_tokens(
+        &self,
+        filter: &TokenFilter,
+        after_id: i32,
+        limit: i64,
+    ) -> StorageResult<Vec<(i32, String)>>;
+
+    fn record_revocation(&self, entry: &NewRevocation) -> StorageResult<()>;
+
+    /// The most recent revocations, newest first.
+    fn list_revocations(&self, limit: i64) -> StorageResult<Vec<Revocation>>;
+
+    /// The id of the most recent revocation. It changes whenever any instance
+    /// sharing the backend revokes tokens.
+    fn latest_revocation_id(&self) -> StorageResult<Option<i32>>;
+}
+
+/// Removal of expired tokens and codes, see the `pruning` module.
+pub trait PruneStore {
+    /// Takes the lock that keeps instances sharing a database from pruning at
+    /// the same time, without waiting for it. The lock belongs to this store's
+    /// connection, so it must be released through the same store.
+    ///
+    /// Returns: StorageResult<bool> --- whether the lock was taken.
+    fn try_lock_pruning(&self) -> StorageResult<bool>;
+
+    fn unlock_pruning(&self) -> StorageResult<()>;
+
+    /// Deletes up to `limit` access tokens that expired, or were revoked,
+    /// before the given time.
+    ///
+    /// Returns: StorageResult<usize> --- the number of rows deleted.
+    fn prune_access_tokens(&self, expired_before: NaiveDateTime, limit: i64) -> StorageResult<usize>;
+
+    /// Deletes up to `limit` refresh tokens that expired, or were revoked,
+    /// before the given time. Refresh tokens without an expiry are only
+    /// pruned once revoked, and refresh tokens that another refresh token or
+    /// an access token refers to are only pruned once those are gone, so
+    /// that revoking the root of a grant still reaches every token in it.
+    fn prune_refresh_tokens(&self, expired_before: NaiveDateTime, limit: i64)
+        -> StorageResult<usize>;
+
+    fn prune_auth_codes(&self, expired_before: NaiveDateTime, limit: i64) -> StorageResult<usize>;
+}
+
+/// Everything the provider needs from a backend.
+pub trait Storage:
+    ClientStore
+    + GrantTypeStore
+    + TokenStore
+    + CodeStore
+    + MigrationStore
+    + PruneStore
+    + RevocationStore
+{
+}
+
+impl<T> Storage for T
+where
+    T: ClientStore
+        + GrantTypeStore
+        + TokenStore
+        + CodeStore
+        + MigrationStore
+        + PruneStore
+        + RevocationStore,
+{
+}
+
+/// Prepares the backend named by `db.backend` ahead of the first connection,
+/// so that a broken setup stops the provider on startup rather than failing
+/// requests. The in-memory backend loads its seed file.
+///
+/// Returns: Result<(), String> --- why the backend cannot be used, if it
+/// cannot.
+pub fn init() -> Result<(), String> {
+    match SETTINGS.db.backend {
+        DatabaseBackend::Memory => memory::seed().map_err(|e| format!("db.seed_file: {}", e)),
+        _ => Ok(()),
+    }
+}
+
+/// Checks a connection out of the backend selected by `db.backend`. With
+/// `tracing.enabled`, every call made through the store is traced.
+///
+/// Returns: StorageResult<Box<dyn Storage>>
+/// - Ok(Box<dyn Storage>)  --- a store, valid until dropped
+/// - Err(StorageError)     --- no connection could be made
+pub fn connect() -> StorageResult<Box<dyn Storage>> {
+    let mut span = Span::start("db.connect");
+    let store: StorageResult<Box<dyn Storage>> = match SETTINGS.db.backend {
+        DatabaseBackend::Postgres => {
+            postgres::PgStore::connect().map(|s| Box::new(s) as Box<dyn Storage>)
+        }
+        DatabaseBackend::Sqlite => connect_sqlite(),
+        DatabaseBackend::Mysql => connect_mysql(),
+        DatabaseBackend::Memory => {
+            memory::MemoryStore::connect().map(|s| Box::new(s) as Box<dyn Storage>)
+        }
+    };
+    if let Err(ref e) = store {
+        span.set_error(&e.to_string());
+    }
+
+    if SETTINGS.tracing.enabled {
+        let system = match SETTINGS.db.backend {
+            DatabaseBackend::Postgres => "postgresql",
+            DatabaseBackend::Sqlite => "sqlite",
+            DatabaseBackend::Mysql => "mysql",
+            DatabaseBackend::Memory => "memory",
+        };
+        store.map(|s| Box::new(traced::TracedStore::new(s, system)) as Box<dyn Storage>)
+    } else {
+        store
+    }
+}
+
+/// Checks the lifetimes of an override before it is saved. Access tokens
+/// must expire, so only a refresh token lifetime may be -1 (no idle timeout).
+///
+/// Returns: StorageResult<()>
+/// - Ok(())                         --- the override can be saved
+/// - Err(StorageError::Invalid(..)) --- a lifetime is out of range
+pub fn check_token_lifetime(new_override: &NewTokenLifetime) -> StorageResult<()> {
+    match new_override.access_token_ttl {
+        Some(ttl) if ttl <= 0 => {
+            return Err(StorageError::Invalid(format!(
+                "access token lifetime must be positive, got {}",
+                ttl
+            )))
+        }
+        _ => (),
+    }
+    match new_override.refresh_token_ttl {
+        Some(ttl) if ttl <= 0 && ttl != -1 => Err(StorageError::Invalid(format!(
+            "refresh token lifetime must be positive or -1, got {}",
+            ttl
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The state of the connection pool behind `db.backend`, for the backends
+/// that have one.
+pub fn pool_state() -> Option<r2d2::State> {
+    match SETTINGS.db.backend {
+        DatabaseBackend::Postgres => DB_POOL.state(),
+        DatabaseBackend::Sqlite => sqlite_pool_state(),
+        DatabaseBackend::Mysql => mysql_pool_state(),
+        DatabaseBackend::Memory => None,
+    }
+}
+
+/// Closes the connection pool behind `db.backend`, once nothing is left to
+/// use it. Connections still checked out are closed as they are returned.
+pub fn close() {
+    match SETTINGS.db.backend {
+        DatabaseBackend::Postgres => DB_POOL.close(),
+        DatabaseBackend::Sqlite => close_sqlite(),
+        DatabaseBackend::Mysql => close_mysql(),
+        DatabaseBackend::Memory => (),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn connect_sqlite() -> StorageResult<Box<dyn Storage>> {
+    Ok(Box::new(sqlite::SqliteStore::connect()?))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn connect_sqlite() -> StorageResult<Box<dyn Storage>> {
+    Err(StorageError::Unavailable(
+        "oa2p was built without the `sqlite` feature".to_owned(),
+    ))
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_pool_state() -> Option<r2d2::State> {
+    sqlite::SQLITE_POOL.state()
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_pool_state() -> Option<r2d2::State> {
+    None
+}
+
+#[cfg(feature = "sqlite")]
+fn close_sqlite() {
+    sqlite::SQLITE_POOL.close()
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn close_sqlite() {}
+
+#[cfg(feature = "mysql")]
+fn connect_mysql() -> StorageResult<Box<dyn Storage>> {
+    Ok(Box::new(mysql::MysqlStore::connect()?))
+}
+
+#[cfg(not(feature = "mysql"))]
+fn connect_mysql() -> StorageResult<Box<dyn Storage>> {
+    Err(StorageError::Unavailable(
+        "oa2p was built without the `mysql` feature".to_owned(),
+    ))
+}
+
+#[cfg(feature = "mysql")]
+fn mysql_pool_state() -> Option<r2d2::State> {
+    mysql::MYSQL_POOL.state()
+}
+
+#[cfg(not(feature = "mysql"))]
+fn mysql_pool_state() -> Option<r2d2::State> {
+    None
+}
+
+#[cfg(feature = "mysql")]
+fn close_mysql() {
+    mysql::MYSQL_POOL.close()
+}
+
+#[cfg(not(feature = "mysql"))]
+fn close_mysql() {}
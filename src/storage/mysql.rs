@@ -0,0 +1,89 @@
+//! The MySQL and MariaDB backend.
+
+use crate::SETTINGS;
+use crate::migrations::Migration;
+use crate::models::db::*;
+use crate::persistence::text_tokens::*;
+use crate::storage::*;
+use crate::storage::pool::ClosablePool;
+use crate::storage::text_tokens::{AccessTokenRow, RefreshTokenRow};
+use chrono::{NaiveDateTime, Utc};
+use diesel::connection::SimpleConnection;
+use diesel::mysql::MysqlConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Nullable, Text, Unsigned};
+use r2d2::{Pool, PooledConnection};
+use r2d2_diesel::ConnectionManager;
+use uuid::Uuid;
+
+lazy_static! {
+    pub static ref MYSQL_POOL: ClosablePool<ConnectionManager<MysqlConnection>> = {
+        let db_url = format!(
+            "mysql://{}:{}@{}:{}/{}",
+            &SETTINGS.db.user,
+            &SETTINGS.db.pass,
+            &SETTINGS.db.host,
+            SETTINGS.db.port,
+            &SETTINGS.db.db_name
+        );
+        let manager = ConnectionManager::<MysqlConnection>::new(db_url);
+
+        let pool = Pool::builder()
+            .max_size(SETTINGS.db.pool_size)
+            .build(manager)
+            .expect("Failed to initialize the MySQL connection pool");
+        ClosablePool::new(pool)
+    };
+}
+
+const PRUNE_LOCK_NAME: &str = "oa2p_prune";
+
+sql_function!(fn get_lock(name: Text, timeout: Integer) -> Nullable<Integer>);
+sql_function!(fn release_lock(name: Text) -> Nullable<Integer>);
+no_arg_sql_function!(last_insert_id, Unsigned<BigInt>);
+
+pub struct MysqlStore {
+    conn: PooledConnection<ConnectionManager<MysqlConnection>>,
+}
+
+impl MysqlStore {
+    pub fn connect() -> StorageResult<MysqlStore> {
+        Ok(MysqlStore {
+            conn: MYSQL_POOL.get()?,
+        })
+    }
+
+    fn conn(&self) -> &MysqlConnection {
+        &self.conn
+    }
+
+    // Named locks belong to the connection, like PostgreSQL's session-level
+    // advisory locks. GET_LOCK returns 1 when the lock was taken.
+    fn try_lock_pruning_in(&self) -> StorageResult<bool> {
+        let taken = diesel::select(get_lock(PRUNE_LOCK_NAME, 0))
+            .get_result::<Option<i32>>(self.conn())?;
+        Ok(taken == Some(1))
+    }
+
+    fn unlock_pruning_in(&self) -> StorageResult<()> {
+        diesel::select(release_lock(PRUNE_LOCK_NAME)).get_result::<Option<i32>>(self.conn())?;
+        Ok(())
+    }
+
+    fn write_transaction_in<T, F>(&self, f: F) -> QueryResult<T>
+    where
+        F: FnOnce() -> QueryResult<T>,
+    {
+        self.conn().transaction(f)
+    }
+
+    // LAST_INSERT_ID() is kept per connection, so other writers cannot change
+    // what it returns between the insert and this query.
+    fn last_insert_id_in(&self) -> QueryResult<i32> {
+        diesel::select(last_insert_id)
+            .get_result::<u64>(self.conn())
+            .map(|id| id as i32)
+    }
+}
+
+impl_text_token_store!(MysqlStore);
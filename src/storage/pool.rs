@@ -0,0 +1,43 @@
+//! A connection pool that can be closed. The pools live in statics, which are
+//! never dropped, so shutting down needs a way to let go of their connections.
+
+use crate::storage::{StorageError, StorageResult};
+use r2d2::{ManageConnection, Pool, PooledConnection, State};
+use std::sync::RwLock;
+
+pub struct ClosablePool<M: ManageConnection> {
+    pool: RwLock<Option<Pool<M>>>,
+}
+
+impl<M: ManageConnection> ClosablePool<M> {
+    pub fn new(pool: Pool<M>) -> ClosablePool<M> {
+        ClosablePool {
+            pool: RwLock::new(Some(pool)),
+        }
+    }
+
+    /// Checks a connection out of the pool.
+    ///
+    /// Returns: StorageResult<PooledConnection<M>>
+    /// - Ok(PooledConnection<M>)  --- a connection, returned to the pool when dropped
+    /// - Err(StorageError)        --- the pool is exhausted or closed
+    pub fn get(&self) -> StorageResult<PooledConnection<M>> {
+        match *self.pool.read().unwrap() { // TODO: remove unwrap
+            Some(ref pool) => Ok(pool.get()?),
+            None => Err(StorageError::Unavailable(
+                "the connection pool is closed".to_owned(),
+            )),
+        }
+    }
+
+    /// The connections held by the pool, until it is closed.
+    pub fn state(&self) -> Option<State> {
+        self.pool.read().unwrap().as_ref().map(Pool::state) // TODO: remove unwrap
+    }
+
+    /// Closes the idle connections, and every other one as it is returned.
+    /// Checking a connection out fails from then on.
+    pub fn close(&self) {
+        self.pool.write().unwrap().take(); // TODO: remove unwrap
+    }
+}
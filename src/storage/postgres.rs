@@ -0,0 +1,520 @@
+//! The PostgreSQL backend, built on Diesel and the `DB_POOL` connection pool.
+
+use crate::DB_POOL;
+use crate::migrations::Migration;
+use crate::models::db::*;
+use crate::persistence::*;
+use crate::storage::*;
+use chrono::{NaiveDateTime, Utc};
+use diesel::connection::SimpleConnection;
+use diesel::dsl::sql;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Bool};
+use r2d2::PooledConnection;
+use r2d2_diesel::ConnectionManager;
+use uuid::Uuid;
+
+// An arbitrary key for the session-level advisory lock held while pruning.
+const PRUNE_LOCK_KEY: i64 = 0x6f61_3270_7275_6e65;
+
+// Refresh tokens other tokens still refer to are kept, so that revoking the
+// root of a grant still reaches every live token derived from it.
+const UNREFERENCED: &str = "NOT EXISTS (SELECT 1 FROM refresh_tokens AS c \
+     WHERE c.parent_id = refresh_tokens.id) \
+     AND NOT EXISTS (SELECT 1 FROM access_tokens AS a \
+     WHERE a.refresh_token_id = refresh_tokens.id)";
+
+sql_function!(fn pg_try_advisory_lock(key: BigInt) -> Bool);
+sql_function!(fn pg_advisory_unlock(key: BigInt) -> Bool);
+
+pub struct PgStore {
+    conn: PooledConnection<ConnectionManager<PgConnection>>,
+}
+
+impl PgStore {
+    pub fn connect() -> StorageResult<PgStore> {
+        Ok(PgStore {
+            conn: DB_POOL.get()?,
+        })
+    }
+
+    fn conn(&self) -> &PgConnection {
+        &self.conn
+    }
+}
+
+impl ClientStore for PgStore {
+    fn find_client(&self, id: i32) -> StorageResult<Client> {
+        Ok(clients::table.find(id).first(self.conn())?)
+    }
+
+    fn find_client_by_identifier(&self, identifier: &str) -> StorageResult<Client> {
+        Ok(clients::table
+            .filter(clients::identifier.eq(identifier))
+            .first(self.conn())?)
+    }
+
+    fn set_client_disabled(
+        &self,
+        id: i32,
+        disabled_at: Option<NaiveDateTime>,
+    ) -> StorageResult<Client> {
+        Ok(diesel::update(clients::table.find(id))
+            .set(clients::disabled_at.eq(disabled_at))
+            .get_result(self.conn())?)
+    }
+
+    fn find_client_redirect_uris(&self, client_id: i32) -> StorageResult<Vec<ClientRedirectUri>> {
+        Ok(client_redirect_uris::table
+            .filter(client_redirect_uris::client_id.eq(client_id))
+            .order(client_redirect_uris::id)
+            .load(self.conn())?)
+    }
+
+    fn find_token_lifetimes(
+        &self,
+        client_id: i32,
+        grant_id: i32,
+    ) -> StorageResult<Vec<TokenLifetime>> {
+        Ok(token_lifetimes::table
+            .filter(
+                token_lifetimes::client_id
+                    .is_null()
+                    .or(token_lifetimes::client_id.eq(client_id)),
+            )
+            .filter(
+                token_lifetimes::grant_id
+                    .is_null()
+                    .or(token_lifetimes::grant_id.eq(grant_id)),
+            )
+            .load(self.conn())?)
+    }
+
+    fn list_token_lifetimes(&self) -> StorageResult<Vec<TokenLifetime>> {
+        Ok(token_lifetimes::table
+            .order(token_lifetimes::id)
+            .load(self.conn())?)
+    }
+
+    fn save_token_lifetime(&self, new_override: &NewTokenLifetime) -> StorageResult<TokenLifetime> {
+        check_token_lifetime(new_override)?;
+        let mut existing = token_lifetimes::table.into_boxed();
+        existing = match new_override.client_id {
+            Some(id) => existing.filter(token_lifetimes::client_id.eq(id)),
+            None => existing.filter(token_lifetimes::client_id.is_null()),
+        };
+        existing = match new_override.grant_id {
+            Some(id) => existing.filter(token_lifetimes::grant_id.eq(id)),
+            None => existing.filter(token_lifetimes::grant_id.is_null()),
+        };
+        existing = match new_override.scope {
+            Some(ref s) => existing.filter(token_lifetimes::scope.eq(s.clone())),
+            None => existing.filter(token_lifetimes::scope.is_null()),
+        };
+        let existing = existing
+            .first::<TokenLifetime>(self.conn())
+            .optional()?;
+
+        let saved = match existing {
+            Some(o) => diesel::update(token_lifetimes::table.find(o.id))
+                .set((
+                    token_lifetimes::access_token_ttl.eq(new_override.access_token_ttl),
+                    token_lifetimes::refresh_token_ttl.eq(new_override.refresh_token_ttl),
+                ))
+                .get_result(self.conn())?,
+            None => diesel::insert_into(token_lifetimes::table)
+                .values(new_override)
+                .get_result(self.conn())?,
+        };
+        Ok(saved)
+    }
+
+    fn delete_token_lifetime(&self, id: i32) -> StorageResult<()> {
+        match diesel::delete(token_lifetimes::table.find(id)).execute(self.conn())? {
+            0 => Err(StorageError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl GrantTypeStore for PgStore {
+    fn find_grant_type(&self, id: i32) -> StorageResult<GrantType> {
+        Ok(grant_types::table.find(id).first(self.conn())?)
+    }
+
+    fn find_grant_type_by_name(&self, name: &str) -> StorageResult<GrantType> {
+        Ok(grant_types::table
+            .filter(grant_types::name.eq(name))
+            .first(self.conn())?)
+    }
+}
+
+impl TokenStore for PgStore {
+    fn create_access_token(&self, new_token: &NewAccessToken) -> StorageResult<AccessToken> {
+        Ok(diesel::insert_into(access_tokens::table)
+            .values(new_token)
+            .get_result(self.conn())?)
+    }
+
+    fn find_access_token(&self, token_hash: &str) -> StorageResult<AccessToken> {
+        Ok(access_tokens::table
+            .filter(access_tokens::token_hash.eq(token_hash))
+            .first(self.conn())?)
+    }
+
+    fn find_legacy_access_token(&self, token: &Uuid) -> StorageResult<AccessToken> {
+        Ok(access_tokens::table
+            .filter(access_tokens::token.eq(token))
+            .first(self.conn())?)
+    }
+
+    fn find_recent_access_tokens(
+        &self,
+        client_id: i32,
+        grant_id: i32,
+        expires_after: NaiveDateTime,
+        limit: i64,
+    ) -> StorageResult<Vec<AccessToken>> {
+        Ok(access_tokens::table
+            .filter(access_tokens::client_id.eq(client_id))
+            .filter(access_tokens::grant_id.eq(grant_id))
+            .filter(access_tokens::expires_at.gt(expires_after))
+            .filter(access_tokens::revoked_at.is_null())
+            .order(access_tokens::issued_at.desc())
+            .limit(limit)
+            .load(self.conn())?)
+    }
+
+    fn create_refresh_token(&self, new_token: &NewRefreshToken) -> StorageResult<RefreshToken> {
+        Ok(diesel::insert_into(refresh_tokens::table)
+            .values(new_token)
+            .get_result(self.conn())?)
+    }
+
+    fn find_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshToken> {
+        Ok(refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(token_hash))
+            .first(self.conn())?)
+    }
+
+    fn find_active_refresh_token(
+        &self,
+        client_id: i32,
+        token_hash: &str,
+        now: NaiveDateTime,
+    ) -> StorageResult<RefreshToken> {
+        Ok(refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(token_hash))
+            .filter(refresh_tokens::client_id.eq(client_id))
+            .filter(
+                refresh_tokens::expires_at
+                    .is_null()
+                    .or(refresh_tokens::expires_at.gt(now)),
+            )
+            .filter(refresh_tokens::revoked_at.is_null())
+            .first(self.conn())?)
+    }
+
+    fn find_active_legacy_refresh_token(
+        &self,
+        client_id: i32,
+        token: &Uuid,
+        now: NaiveDateTime,
+    ) -> StorageResult<RefreshToken> {
+        Ok(refresh_tokens::table
+            .filter(refresh_tokens::token.eq(token))
+            .filter(refresh_tokens::client_id.eq(client_id))
+            .filter(
+                refresh_tokens::expires_at
+                    .is_null()
+                    .or(refresh_tokens::expires_at.gt(now)),
+            )
+            .filter(refresh_tokens::revoked_at.is_null())
+            .order(refresh_tokens::issued_at.desc())
+            .first(self.conn())?)
+    }
+
+    fn hash_legacy_refresh_token(&self, id: i32, token_hash: &str) -> StorageResult<RefreshToken> {
+        Ok(diesel::update(refresh_tokens::table.find(id))
+            .set((
+                refresh_tokens::token.eq(None::<Uuid>),
+                refresh_tokens::token_hash.eq(Some(token_hash)),
+            ))
+            .get_result(self.conn())?)
+    }
+
+    fn update_refresh_token_expiry(
+        &self,
+        id: i32,
+        last_used_at: NaiveDateTime,
+        expires_at: Option<NaiveDateTime>,
+    ) -> StorageResult<RefreshToken> {
+        Ok(diesel::update(refresh_tokens::table.find(id))
+            .set((
+                refresh_tokens::last_used_at.eq(Some(last_used_at)),
+                refresh_tokens::expires_at.eq(expires_at),
+            ))
+            .get_result(self.conn())?)
+    }
+
+    fn find_child_refresh_token_ids(&self, parent_ids: &[i32]) -> StorageResult<Vec<i32>> {
+        Ok(refresh_tokens::table
+            .select(refresh_tokens::id)
+            .filter(refresh_tokens::parent_id.eq_any(parent_ids))
+            .filter(refresh_tokens::revoked_at.is_null())
+            .load(self.conn())?)
+    }
+
+    fn find_access_token_ids_by_refresh_tokens(
+        &self,
+        refresh_token_ids: &[i32],
+    ) -> StorageResult<Vec<i32>> {
+        Ok(access_tokens::table
+            .select(access_tokens::id)
+            .filter(access_tokens::refresh_token_id.eq_any(refresh_token_ids))
+            .filter(access_tokens::revoked_at.is_null())
+            .load(self.conn())?)
+    }
+
+    fn find_refresh_token_ids_by_auth_code(&self, auth_code_id: i32) -> StorageResult<Vec<i32>> {
+        Ok(refresh_tokens::table
+            .select(refresh_tokens::id)
+            .filter(refresh_tokens::auth_code_id.eq(auth_code_id))
+            .filter(refresh_tokens::revoked_at.is_null())
+            .load(self.conn())?)
+    }
+
+    fn find_access_token_ids_by_auth_code(&self, auth_code_id: i32) -> StorageResult<Vec<i32>> {
+        Ok(access_tokens::table
+            .select(access_tokens::id)
+            .filter(access_tokens::auth_code_id.eq(auth_code_id))
+            .filter(access_tokens::revoked_at.is_null())
+            .load(self.conn())?)
+    }
+
+    fn revoke_access_tokens(&self, ids: &[i32], revoked_at: NaiveDateTime) -> StorageResult<usize> {
+        Ok(diesel::update(
+            access_tokens::table
+                .filter(access_tokens::id.eq_any(ids))
+                .filter(access_tokens::revoked_at.is_null()),
+        ).set(access_tokens::revoked_at.eq(Some(revoked_at)))
+            .execute(self.conn())?)
+    }
+
+    fn revoke_refresh_tokens(
+        &self,
+        ids: &[i32],
+        revoked_at: NaiveDateTime,
+    ) -> StorageResult<usize> {
+        Ok(diesel::update(
+            refresh_tokens::table
+                .filter(refresh_tokens::id.eq_any(ids))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        ).set(refresh_tokens::revoked_at.eq(Some(revoked_at)))
+            .execute(self.conn())?)
+    }
+}
+
+impl CodeStore for PgStore {
+    fn find_auth_code(&self, client_id: i32, code: &str) -> StorageResult<AuthCode> {
+        Ok(auth_codes::table
+            .filter(auth_codes::client_id.eq(client_id))
+            .filter(auth_codes::name.eq(code))
+            .first(self.conn())?)
+    }
+
+    fn delete_auth_code(&self, id: i32) -> StorageResult<()> {
+        match diesel::delete(auth_codes::table.find(id)).execute(self.conn())? {
+            0 => Err(StorageError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl MigrationStore for PgStore {
+    fn applied_migrations(&self) -> StorageResult<Vec<i32>> {
+        self.conn().batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+               version INTEGER PRIMARY KEY,
+               name VARCHAR(255) NOT NULL,
+               applied_at TIMESTAMP WITH TIME ZONE NOT NULL
+             )",
+        )?;
+        Ok(schema_migrations::table
+            .select(schema_migrations::version)
+            .order(schema_migrations::version)
+            .load(self.conn())?)
+    }
+
+    fn apply_migration(&self, migration: &Migration) -> StorageResult<()> {
+        Ok(self.conn().transaction(|| {
+            self.conn().batch_execute(migration.up)?;
+            self.mark_migration_in(migration)
+        })?)
+    }
+
+    fn revert_migration(&self, migration: &Migration) -> StorageResult<()> {
+        Ok(self.conn().transaction(|| {
+            self.conn().batch_execute(migration.down)?;
+            diesel::delete(schema_migrations::table.find(migration.version))
+                .execute(self.conn())
+                .map(|_| ())
+        })?)
+    }
+
+    fn mark_migration(&self, migration: &Migration) -> StorageResult<()> {
+        Ok(self.mark_migration_in(migration)?)
+    }
+}
+
+impl RevocationStore for PgStore {
+    fn find_revocable_access_tokens(
+        &self,
+        filter: &TokenFilter,
+        after_id: i32,
+        limit: i64,
+    ) -> StorageResult<Vec<(i32, String)>> {
+        let mut query = access_tokens::table
+            .select((access_tokens::id, access_tokens::scope))
+            .filter(access_tokens::id.gt(after_id))
+            .filter(access_tokens::revoked_at.is_null())
+            .filter(access_tokens::expires_at.gt(filter.live_at))
+            .into_boxed();
+        if let Some(client_id) = filter.client_id {
+            query = query.filter(access_tokens::client_id.eq(client_id));
+        }
+        if let Some(issued_after) = filter.issued_after {
+            query = query.filter(access_tokens::issued_at.ge(issued_after));
+        }
+        if let Some(issued_before) = filter.issued_before {
+            query = query.filter(access_tokens::issued_at.lt(issued_before));
+        }
+        Ok(query
+            .order(access_tokens::id)
+            .limit(limit)
+            .load(self.conn())?)
+    }
+
+    fn find_revocable_refresh_tokens(
+        &self,
+        filter: &TokenFilter,
+        after_id: i32,
+        limit: i64,
+    ) -> StorageResult<Vec<(i32, String)>> {
+        let mut query = refresh_tokens::table
+            .select((refresh_tokens::id, refresh_tokens::scope))
+            .filter(refresh_tokens::id.gt(after_id))
+            .filter(refresh_tokens::revoked_at.is_null())
+            .filter(
+                refresh_tokens::expires_at
+                    .is_null()
+                    .or(refresh_tokens::expires_at.gt(filter.live_at)),
+            )
+            .into_boxed();
+        if let Some(client_id) = filter.client_id {
+            query = query.filter(refresh_tokens::client_id.eq(client_id));
+        }
+        if let Some(issued_after) = filter.issued_after {
+            query = query.filter(refresh_tokens::issued_at.ge(issued_after));
+        }
+        if let Some(issued_before) = filter.issued_before {
+            query = query.filter(refresh_tokens::issued_at.lt(issued_before));
+        }
+        Ok(query
+            .order(refresh_tokens::id)
+            .limit(limit)
+            .load(self.conn())?)
+    }
+
+    fn record_revocation(&self, entry: &NewRevocation) -> StorageResult<()> {
+        diesel::insert_into(token_revocations::table)
+            .values(entry)
+            .execute(self.conn())?;
+        Ok(())
+    }
+
+    fn list_revocations(&self, limit: i64) -> StorageResult<Vec<Revocation>> {
+        Ok(token_revocations::table
+            .order(token_revocations::id.desc())
+            .limit(limit)
+            .load(self.conn())?)
+    }
+
+    fn latest_revocation_id(&self) -> StorageResult<Option<i32>> {
+        Ok(token_revocations::table
+            .select(token_revocations::id)
+            .order(token_revocations::id.desc())
+            .first(self.conn())
+            .optional()?)
+    }
+}
+
+// Diesel cannot delete from a table it selects the batch from in a subquery,
+// so each batch is selected before it is deleted.
+impl PruneStore for PgStore {
+    fn try_lock_pruning(&self) -> StorageResult<bool> {
+        Ok(diesel::select(pg_try_advisory_lock(PRUNE_LOCK_KEY)).get_result(self.conn())?)
+    }
+
+    fn unlock_pruning(&self) -> StorageResult<()> {
+        diesel::select(pg_advisory_unlock(PRUNE_LOCK_KEY)).get_result::<bool>(self.conn())?;
+        Ok(())
+    }
+
+    fn prune_access_tokens(&self, expired_before: NaiveDateTime, limit: i64) -> StorageResult<usize> {
+        let batch = access_tokens::table
+            .select(access_tokens::id)
+            .filter(
+                access_tokens::expires_at
+                    .lt(expired_before)
+                    .or(access_tokens::revoked_at.lt(expired_before)),
+            )
+            .limit(limit)
+            .load::<i32>(self.conn())?;
+        Ok(diesel::delete(access_tokens::table.filter(access_tokens::id.eq_any(batch)))
+            .execute(self.conn())?)
+    }
+
+    fn prune_refresh_tokens(
+        &self,
+        expired_before: NaiveDateTime,
+        limit: i64,
+    ) -> StorageResult<usize> {
+        let batch = refresh_tokens::table
+            .select(refresh_tokens::id)
+            .filter(
+                refresh_tokens::expires_at
+                    .lt(expired_before)
+                    .or(refresh_tokens::revoked_at.lt(expired_before)),
+            )
+            .filter(sql::<Bool>(UNREFERENCED))
+            .limit(limit)
+            .load::<i32>(self.conn())?;
+        Ok(diesel::delete(refresh_tokens::table.filter(refresh_tokens::id.eq_any(batch)))
+            .execute(self.conn())?)
+    }
+
+    fn prune_auth_codes(&self, expired_before: NaiveDateTime, limit: i64) -> StorageResult<usize> {
+        let batch = auth_codes::table
+            .select(auth_codes::id)
+            .filter(auth_codes::expires_at.lt(expired_before))
+            .limit(limit)
+            .load::<i32>(self.conn())?;
+        Ok(diesel::delete(auth_codes::table.filter(auth_codes::id.eq_any(batch)))
+            .execute(self.conn())?)
+    }
+}
+
+impl PgStore {
+    fn mark_migration_in(&self, migration: &Migration) -> QueryResult<()> {
+        diesel::insert_into(schema_migrations::table)
+            .values((
+                schema_migrations::version.eq(migration.version),
+                schema_migrations::name.eq(migration.name),
+                schema_migrations::applied_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(self.conn())
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,90 @@
+//! The SQLite backend, for deployments that cannot run a database server.
+
+use crate::SETTINGS;
+use crate::migrations::Migration;
+use crate::models::db::*;
+use crate::persistence::text_tokens::*;
+use crate::storage::*;
+use crate::storage::pool::ClosablePool;
+use crate::storage::text_tokens::{AccessTokenRow, RefreshTokenRow};
+use chrono::{NaiveDateTime, Utc};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use diesel::sqlite::SqliteConnection;
+use r2d2::{CustomizeConnection, Pool, PooledConnection};
+use r2d2_diesel::{ConnectionManager, Error as ConnectionManagerError};
+use uuid::Uuid;
+
+no_arg_sql_function!(last_insert_rowid, BigInt);
+
+lazy_static! {
+    pub static ref SQLITE_POOL: ClosablePool<ConnectionManager<SqliteConnection>> = {
+        let manager = ConnectionManager::<SqliteConnection>::new(SETTINGS.db.path.clone());
+
+        let pool = Pool::builder()
+            .max_size(SETTINGS.db.pool_size)
+            .connection_customizer(Box::new(SqliteCustomizer))
+            .build(manager)
+            .expect("Failed to initialize the SQLite connection pool");
+        ClosablePool::new(pool)
+    };
+}
+
+// SQLite settings are per connection, so every connection handed out by the
+// pool needs foreign keys switched on, and should wait on a locked database
+// rather than failing straight away.
+#[derive(Debug)]
+struct SqliteCustomizer;
+
+impl CustomizeConnection<SqliteConnection, ConnectionManagerError> for SqliteCustomizer {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), ConnectionManagerError> {
+        conn.batch_execute("PRAGMA foreign_keys = ON; PRAGMA busy_timeout = 5000;")
+            .map_err(ConnectionManagerError::QueryError)
+    }
+}
+
+pub struct SqliteStore {
+    conn: PooledConnection<ConnectionManager<SqliteConnection>>,
+}
+
+impl SqliteStore {
+    pub fn connect() -> StorageResult<SqliteStore> {
+        Ok(SqliteStore {
+            conn: SQLITE_POOL.get()?,
+        })
+    }
+
+    fn conn(&self) -> &SqliteConnection {
+        &self.conn
+    }
+
+    // A SQLite database is only ever used by a single instance, so there is
+    // nobody to coordinate with.
+    fn try_lock_pruning_in(&self) -> StorageResult<bool> {
+        Ok(true)
+    }
+
+    fn unlock_pruning_in(&self) -> StorageResult<()> {
+        Ok(())
+    }
+
+    // A deferred transaction that reads before it writes fails straight away
+    // when another connection is writing, instead of waiting for busy_timeout,
+    // so transactions that write take the write lock up front.
+    fn write_transaction_in<T, F>(&self, f: F) -> QueryResult<T>
+    where
+        F: FnOnce() -> QueryResult<T>,
+    {
+        self.conn().immediate_transaction(f)
+    }
+
+    // last_insert_rowid() is kept per connection, like MySQL's LAST_INSERT_ID().
+    fn last_insert_id_in(&self) -> QueryResult<i32> {
+        diesel::select(last_insert_rowid)
+            .get_result::<i64>(self.conn())
+            .map(|id| id as i32)
+    }
+}
+
+impl_text_token_store!(SqliteStore);
@@ -0,0 +1,548 @@
+//! Integration tests for the store traits. Every test runs against the
+//! in-memory backend, loaded with the example seed file, and again against
+//! whichever backend the config file named by `OA2P_CONFIG` selects. The
+//! latter need a migrated database holding `extras/test-clients.sql`, so they
+//! are ignored by default:
+//!
+//! ```text
+//! OA2P_CONFIG=mysql.toml cargo test --features mysql -- --ignored
+//! ```
+//!
+//! Every test works on rows of its own, so the suite can be run repeatedly
+//! against the same database, and in parallel against the in-memory one.
+
+use crate::storage::*;
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::thread;
+
+/// Opens a store for a test, once per thread that needs one.
+type Connect = fn() -> Box<dyn Storage>;
+
+macro_rules! storage_tests {
+    ($($name:ident),* $(,)*) => {
+        mod in_memory {
+            $(
+                #[test]
+                fn $name() {
+                    super::$name(super::memory_store)
+                }
+            )*
+        }
+
+        mod configured {
+            $(
+                #[test]
+                #[ignore]
+                fn $name() {
+                    super::$name(super::configured_store)
+                }
+            )*
+        }
+    };
+}
+
+storage_tests!(
+    finds_clients_and_grant_types,
+    disables_and_enables_clients,
+    finds_redirect_uris_and_auth_codes,
+    saves_replaces_and_deletes_token_lifetimes,
+    refuses_token_lifetimes_out_of_range,
+    concurrent_token_lifetime_saves_return_their_own_rows,
+    creates_finds_and_revokes_tokens,
+    finds_tokens_by_auth_code,
+    records_revocations,
+    prunes_expired_and_revoked_tokens,
+    keeps_refresh_tokens_other_tokens_refer_to,
+    lists_applied_migrations,
+);
+
+fn memory_store() -> Box<dyn Storage> {
+    memory::seed_from("extras/seed.example.toml").expect("Unable to load the example seed file");
+    Box::new(memory::MemoryStore::connect().expect("Unable to connect to storage"))
+}
+
+fn configured_store() -> Box<dyn Storage> {
+    init().expect("Invalid [db] settings");
+    connect().expect("Unable to connect to storage")
+}
+
+fn unique(prefix: &str) -> String {
+    format!("{}-{}", prefix, Uuid::new_v4().simple())
+}
+
+// Token digests are stored in fixed-width columns, so they need the length of
+// a real one.
+fn unique_digest() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+fn test_client(store: &dyn Storage) -> Client {
+    store
+        .find_client_by_identifier("abcd1234")
+        .expect("The test clients have not been loaded")
+}
+
+fn new_access_token(
+    client: &Client,
+    grant_id: i32,
+    refresh_token_id: Option<i32>,
+) -> NewAccessToken {
+    let now = Utc::now().naive_utc();
+    NewAccessToken {
+        token_hash: unique_digest(),
+        client_id: client.id,
+        grant_id,
+        scope: "read".to_owned(),
+        issued_at: now,
+        expires_at: now + Duration::hours(1),
+        refresh_token_id,
+        auth_code_id: None,
+    }
+}
+
+fn new_refresh_token(client: &Client, parent_id: Option<i32>) -> NewRefreshToken {
+    let now = Utc::now().naive_utc();
+    NewRefreshToken {
+        token_hash: unique_digest(),
+        client_id: client.id,
+        scope: "read".to_owned(),
+        issued_at: now,
+        expires_at: Some(now + Duration::hours(1)),
+        absolute_expires_at: None,
+        parent_id,
+        auth_code_id: None,
+    }
+}
+
+fn finds_clients_and_grant_types(store: Connect) {
+    let store = store();
+
+    let client = test_client(&*store);
+    assert_eq!(store.find_client(client.id).unwrap().identifier, "abcd1234");
+    assert!(matches!(
+        store.find_client_by_identifier(&unique("client")),
+        Err(StorageError::NotFound)
+    ));
+
+    let grant = store.find_grant_type_by_name("client_credentials").unwrap();
+    assert_eq!(
+        store.find_grant_type(grant.id).unwrap().name,
+        "client_credentials"
+    );
+    assert!(matches!(
+        store.find_grant_type_by_name("no_such_grant"),
+        Err(StorageError::NotFound)
+    ));
+}
+
+// Only the second test client is disabled, so the other tests can keep using
+// the first while this one runs.
+fn disables_and_enables_clients(store: Connect) {
+    let store = store();
+    let client = store.find_client_by_identifier("abcd4321").unwrap();
+
+    let now = Utc::now().naive_utc();
+    let disabled = store.set_client_disabled(client.id, Some(now)).unwrap();
+    assert!(disabled.disabled_at.is_some());
+    assert!(store.find_client(client.id).unwrap().disabled_at.is_some());
+
+    let enabled = store.set_client_disabled(client.id, None).unwrap();
+    assert_eq!(enabled.disabled_at, None);
+    assert!(matches!(
+        store.set_client_disabled(-1, None),
+        Err(StorageError::NotFound)
+    ));
+}
+
+fn finds_redirect_uris_and_auth_codes(store: Connect) {
+    let store = store();
+    let client = test_client(&*store);
+
+    // The test clients have neither, which must not be an error.
+    assert!(store
+        .find_client_redirect_uris(client.id)
+        .unwrap()
+        .iter()
+        .all(|u| u.client_id == client.id));
+    assert!(matches!(
+        store.find_auth_code(client.id, &unique("code")),
+        Err(StorageError::NotFound)
+    ));
+    assert!(matches!(
+        store.delete_auth_code(-1),
+        Err(StorageError::NotFound)
+    ));
+}
+
+fn saves_replaces_and_deletes_token_lifetimes(store: Connect) {
+    let store = store();
+    let client = test_client(&*store);
+    let grant = store.find_grant_type_by_name("client_credentials").unwrap();
+    let scope = unique("scope");
+
+    let saved = store
+        .save_token_lifetime(&NewTokenLifetime {
+            client_id: Some(client.id),
+            grant_id: Some(grant.id),
+            scope: Some(scope.clone()),
+            access_token_ttl: Some(60),
+            refresh_token_ttl: None,
+        })
+        .unwrap();
+    assert_eq!(saved.scope.as_deref(), Some(scope.as_str()));
+    assert_eq!(saved.access_token_ttl, Some(60));
+
+    let replaced = store
+        .save_token_lifetime(&NewTokenLifetime {
+            client_id: Some(client.id),
+            grant_id: Some(grant.id),
+            scope: Some(scope.clone()),
+            access_token_ttl: Some(120),
+            refresh_token_ttl: Some(600),
+        })
+        .unwrap();
+    assert_eq!(replaced.id, saved.id);
+    assert_eq!(replaced.access_token_ttl, Some(120));
+    assert_eq!(replaced.refresh_token_ttl, Some(600));
+
+    let found = store.find_token_lifetimes(client.id, grant.id).unwrap();
+    assert!(found.iter().any(|o| o.id == saved.id));
+    assert!(store
+        .list_token_lifetimes()
+        .unwrap()
+        .iter()
+        .any(|o| o.id == saved.id));
+
+    store.delete_token_lifetime(saved.id).unwrap();
+    assert!(matches!(
+        store.delete_token_lifetime(saved.id),
+        Err(StorageError::NotFound)
+    ));
+}
+
+fn refuses_token_lifetimes_out_of_range(store: Connect) {
+    let store = store();
+    let client = test_client(&*store);
+    let scope = unique("scope");
+    let lifetime = |access_token_ttl, refresh_token_ttl| NewTokenLifetime {
+        client_id: Some(client.id),
+        grant_id: None,
+        scope: Some(scope.clone()),
+        access_token_ttl,
+        refresh_token_ttl,
+    };
+
+    for &(access, refresh) in &[
+        (Some(-1), None),
+        (Some(0), None),
+        (None, Some(0)),
+        (None, Some(-2)),
+    ] {
+        assert!(matches!(
+            store.save_token_lifetime(&lifetime(access, refresh)),
+            Err(StorageError::Invalid(_))
+        ));
+    }
+    assert!(!store
+        .list_token_lifetimes()
+        .unwrap()
+        .iter()
+        .any(|o| o.scope.as_deref() == Some(scope.as_str())));
+
+    let never = store
+        .save_token_lifetime(&lifetime(None, Some(-1)))
+        .unwrap();
+    assert_eq!(never.refresh_token_ttl, Some(-1));
+    store.delete_token_lifetime(never.id).unwrap();
+}
+
+// Backends without `RETURNING` read a new override back by the id of the
+// insert, which must be the one made on the same connection.
+fn concurrent_token_lifetime_saves_return_their_own_rows(store: Connect) {
+    let workers: Vec<_> = (0..8)
+        .map(|_| {
+            thread::spawn(move || {
+                let store = store();
+                let client = test_client(&*store);
+                for _ in 0..10 {
+                    let scope = unique("scope");
+                    let saved = store
+                        .save_token_lifetime(&NewTokenLifetime {
+                            client_id: Some(client.id),
+                            grant_id: None,
+                            scope: Some(scope.clone()),
+                            access_token_ttl: Some(60),
+                            refresh_token_ttl: None,
+                        })
+                        .unwrap();
+                    assert_eq!(saved.scope, Some(scope));
+                    store.delete_token_lifetime(saved.id).unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+fn creates_finds_and_revokes_tokens(store: Connect) {
+    let store = store();
+    let client = test_client(&*store);
+    let grant = store.find_grant_type_by_name("password").unwrap();
+    let now = Utc::now().naive_utc();
+
+    let parent = store
+        .create_refresh_token(&new_refresh_token(&client, None))
+        .unwrap();
+    let child = store
+        .create_refresh_token(&new_refresh_token(&client, Some(parent.id)))
+        .unwrap();
+    let new_token = new_access_token(&client, grant.id, Some(child.id));
+    let access = store.create_access_token(&new_token).unwrap();
+
+    assert_eq!(
+        store.find_access_token(&new_token.token_hash).unwrap().id,
+        access.id
+    );
+    assert!(matches!(
+        store.find_access_token(&unique_digest()),
+        Err(StorageError::NotFound)
+    ));
+    let recent = store
+        .find_recent_access_tokens(client.id, grant.id, now, 100)
+        .unwrap();
+    assert!(recent.iter().any(|t| t.id == access.id));
+
+    let found = store
+        .find_active_refresh_token(client.id, parent.token_hash.as_ref().unwrap(), now)
+        .unwrap();
+    assert_eq!(found.id, parent.id);
+    assert_eq!(
+        store.find_child_refresh_token_ids(&[parent.id]).unwrap(),
+        vec![child.id]
+    );
+    assert_eq!(
+        store
+            .find_access_token_ids_by_refresh_tokens(&[child.id])
+            .unwrap(),
+        vec![access.id]
+    );
+
+    assert_eq!(store.revoke_access_tokens(&[access.id], now).unwrap(), 1);
+    assert_eq!(store.revoke_access_tokens(&[access.id], now).unwrap(), 0);
+    assert_eq!(
+        store
+            .revoke_refresh_tokens(&[parent.id, child.id], now)
+            .unwrap(),
+        2
+    );
+    assert!(matches!(
+        store.find_active_refresh_token(client.id, parent.token_hash.as_ref().unwrap(), now),
+        Err(StorageError::NotFound)
+    ));
+    assert!(store
+        .find_child_refresh_token_ids(&[parent.id])
+        .unwrap()
+        .is_empty());
+}
+
+// Authorization codes are not foreign keys, and the test clients have none,
+// so any id no real code has will do.
+fn unique_auth_code_id() -> i32 {
+    let bytes = Uuid::new_v4();
+    let bytes = bytes.as_bytes();
+    -i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3] & 0x7f]) - 1
+}
+
+fn finds_tokens_by_auth_code(store: Connect) {
+    let store = store();
+    let client = test_client(&*store);
+    let grant = store.find_grant_type_by_name("password").unwrap();
+    let code_id = unique_auth_code_id();
+
+    let mut new_refresh = new_refresh_token(&client, None);
+    new_refresh.auth_code_id = Some(code_id);
+    let refresh = store.create_refresh_token(&new_refresh).unwrap();
+    assert_eq!(refresh.auth_code_id, Some(code_id));
+    let mut new_access = new_access_token(&client, grant.id, Some(refresh.id));
+    new_access.auth_code_id = Some(code_id);
+    let access = store.create_access_token(&new_access).unwrap();
+    assert_eq!(access.auth_code_id, Some(code_id));
+
+    assert_eq!(
+        store.find_refresh_token_ids_by_auth_code(code_id).unwrap(),
+        vec![refresh.id]
+    );
+    assert_eq!(
+        store.find_access_token_ids_by_auth_code(code_id).unwrap(),
+        vec![access.id]
+    );
+
+    let now = Utc::now().naive_utc();
+    store.revoke_refresh_tokens(&[refresh.id], now).unwrap();
+    store.revoke_access_tokens(&[access.id], now).unwrap();
+    assert!(store
+        .find_refresh_token_ids_by_auth_code(code_id)
+        .unwrap()
+        .is_empty());
+    assert!(store
+        .find_access_token_ids_by_auth_code(code_id)
+        .unwrap()
+        .is_empty());
+}
+
+fn refresh_token_exists(store: &dyn Storage, token: &RefreshToken) -> bool {
+    match store.find_refresh_token(token.token_hash.as_ref().unwrap()) {
+        Ok(_) => true,
+        Err(StorageError::NotFound) => false,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+fn prunes_expired_and_revoked_tokens(store: Connect) {
+    let store = store();
+    let client = test_client(&*store);
+    let grant = store.find_grant_type_by_name("password").unwrap();
+    let now = Utc::now().naive_utc();
+    let cutoff = now - Duration::hours(1);
+    let long_ago = now - Duration::hours(2);
+    let lately = now - Duration::minutes(1);
+
+    let access = |expires_at, revoked_at: Option<NaiveDateTime>| {
+        let mut new_token = new_access_token(&client, grant.id, None);
+        new_token.expires_at = expires_at;
+        let token = store.create_access_token(&new_token).unwrap();
+        if let Some(at) = revoked_at {
+            store.revoke_access_tokens(&[token.id], at).unwrap();
+        }
+        new_token.token_hash
+    };
+    let expired_access = access(long_ago, None);
+    let revoked_access = access(now + Duration::hours(1), Some(long_ago));
+    let recently_expired_access = access(lately, None);
+    let recently_revoked_access = access(now + Duration::hours(1), Some(lately));
+    let live_access = access(now + Duration::hours(1), None);
+
+    let refresh = |expires_at, revoked_at: Option<NaiveDateTime>| {
+        let mut new_token = new_refresh_token(&client, None);
+        new_token.expires_at = expires_at;
+        let token = store.create_refresh_token(&new_token).unwrap();
+        if let Some(at) = revoked_at {
+            store.revoke_refresh_tokens(&[token.id], at).unwrap();
+        }
+        token
+    };
+    let expired_refresh = refresh(Some(long_ago), None);
+    let revoked_refresh = refresh(None, Some(long_ago));
+    let recently_expired_refresh = refresh(Some(lately), None);
+    let recently_revoked_refresh = refresh(None, Some(lately));
+    let live_refresh = refresh(Some(now + Duration::hours(1)), None);
+    let everlasting_refresh = refresh(None, None);
+
+    while store.prune_access_tokens(cutoff, 100).unwrap() > 0 {}
+    while store.prune_refresh_tokens(cutoff, 100).unwrap() > 0 {}
+
+    for gone in &[expired_access, revoked_access] {
+        assert!(matches!(
+            store.find_access_token(gone),
+            Err(StorageError::NotFound)
+        ));
+    }
+    for kept in &[
+        recently_expired_access,
+        recently_revoked_access,
+        live_access,
+    ] {
+        assert!(store.find_access_token(kept).is_ok());
+    }
+    for gone in &[expired_refresh, revoked_refresh] {
+        assert!(!refresh_token_exists(&*store, gone));
+    }
+    for kept in &[
+        recently_expired_refresh,
+        recently_revoked_refresh,
+        live_refresh,
+        everlasting_refresh,
+    ] {
+        assert!(refresh_token_exists(&*store, kept));
+    }
+}
+
+// A grant whose older refresh tokens were revoked when they were rotated,
+// while the newest one and the access token issued with it are still live.
+fn keeps_refresh_tokens_other_tokens_refer_to(store: Connect) {
+    let store = store();
+    let client = test_client(&*store);
+    let grant = store.find_grant_type_by_name("password").unwrap();
+    let now = Utc::now().naive_utc();
+    let cutoff = now - Duration::hours(1);
+    let long_ago = now - Duration::hours(2);
+
+    let root = store
+        .create_refresh_token(&new_refresh_token(&client, None))
+        .unwrap();
+    let rotated = store
+        .create_refresh_token(&new_refresh_token(&client, Some(root.id)))
+        .unwrap();
+    let live = store
+        .create_refresh_token(&new_refresh_token(&client, Some(rotated.id)))
+        .unwrap();
+    let new_token = new_access_token(&client, grant.id, Some(live.id));
+    let access = store.create_access_token(&new_token).unwrap();
+    store
+        .revoke_refresh_tokens(&[root.id, rotated.id], long_ago)
+        .unwrap();
+
+    let prune = || {
+        while store.prune_access_tokens(cutoff, 100).unwrap() > 0 {}
+        while store.prune_refresh_tokens(cutoff, 100).unwrap() > 0 {}
+    };
+    prune();
+    for kept in &[&root, &rotated, &live] {
+        assert!(refresh_token_exists(&*store, kept));
+    }
+
+    // Once the whole grant is revoked, it is pruned from the newest token up.
+    store.revoke_access_tokens(&[access.id], long_ago).unwrap();
+    store.revoke_refresh_tokens(&[live.id], long_ago).unwrap();
+    prune();
+    assert!(matches!(
+        store.find_access_token(&new_token.token_hash),
+        Err(StorageError::NotFound)
+    ));
+    for gone in &[&root, &rotated, &live] {
+        assert!(!refresh_token_exists(&*store, gone));
+    }
+}
+
+fn records_revocations(store: Connect) {
+    let store = store();
+    let actor = unique("actor");
+
+    store
+        .record_revocation(&NewRevocation {
+            revoked_at: Utc::now().naive_utc(),
+            actor: actor.clone(),
+            reason: Some("storage tests".to_owned()),
+            selection: "{}".to_owned(),
+            access_tokens: 0,
+            refresh_tokens: 0,
+        })
+        .unwrap();
+
+    // Other tests may record revocations of their own at the same time.
+    let latest = store.list_revocations(100).unwrap();
+    let recorded = latest.iter().find(|r| r.actor == actor).unwrap();
+    assert!(latest.windows(2).all(|w| w[0].id > w[1].id));
+    assert!(store.latest_revocation_id().unwrap() >= Some(recorded.id));
+}
+
+fn lists_applied_migrations(store: Connect) {
+    let store = store();
+    let applied = store.applied_migrations().unwrap();
+    let mut sorted = applied.clone();
+    sorted.sort_unstable();
+    assert_eq!(applied, sorted);
+}
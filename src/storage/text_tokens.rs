@@ -0,0 +1,672 @@
+//! Shared pieces of the backends that use the `persistence::text_tokens`
+//! schema. Those backends have neither a UUID type nor `RETURNING`, so legacy
+//! tokens are stored as text, and inserted rows are read back by their token
+//! digest or by the connection's last insert id. Diesel queries are tied to a concrete connection
+//! type, so the store implementation is written once as a macro and expanded
+//! for each backend.
+
+use crate::models::db::*;
+use crate::storage::{StorageError, StorageResult};
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+/// Limits pruning to refresh tokens no other token refers to, so that
+/// revoking the root of a grant still reaches every live token derived from
+/// it.
+pub const UNREFERENCED_REFRESH_TOKEN: &str = "NOT EXISTS (SELECT 1 FROM refresh_tokens AS c \
+     WHERE c.parent_id = refresh_tokens.id) \
+     AND NOT EXISTS (SELECT 1 FROM access_tokens AS a \
+     WHERE a.refresh_token_id = refresh_tokens.id)";
+
+#[derive(Queryable)]
+pub struct AccessTokenRow {
+    pub id: i32,
+    pub token: Option<String>,
+    pub token_hash: Option<String>,
+    pub client_id: i32,
+    pub grant_id: i32,
+    pub scope: String,
+    pub issued_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub refresh_token_id: Option<i32>,
+    pub auth_code_id: Option<i32>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl AccessTokenRow {
+    pub fn into_model(self) -> StorageResult<AccessToken> {
+        Ok(AccessToken {
+            id: self.id,
+            token: parse_token(self.token)?,
+            token_hash: self.token_hash,
+            client_id: self.client_id,
+            grant_id: self.grant_id,
+            scope: self.scope,
+            issued_at: self.issued_at,
+            expires_at: self.expires_at,
+            refresh_token_id: self.refresh_token_id,
+            auth_code_id: self.auth_code_id,
+            revoked_at: self.revoked_at,
+        })
+    }
+}
+
+#[derive(Queryable)]
+pub struct RefreshTokenRow {
+    pub id: i32,
+    pub token: Option<String>,
+    pub token_hash: Option<String>,
+    pub client_id: i32,
+    pub scope: String,
+    pub issued_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub absolute_expires_at: Option<NaiveDateTime>,
+    pub parent_id: Option<i32>,
+    pub auth_code_id: Option<i32>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl RefreshTokenRow {
+    pub fn into_model(self) -> StorageResult<RefreshToken> {
+        Ok(RefreshToken {
+            id: self.id,
+            token: parse_token(self.token)?,
+            token_hash: self.token_hash,
+            client_id: self.client_id,
+            scope: self.scope,
+            issued_at: self.issued_at,
+            expires_at: self.expires_at,
+            last_used_at: self.last_used_at,
+            absolute_expires_at: self.absolute_expires_at,
+            parent_id: self.parent_id,
+            auth_code_id: self.auth_code_id,
+            revoked_at: self.revoked_at,
+        })
+    }
+}
+
+pub fn parse_token(token: Option<String>) -> StorageResult<Option<Uuid>> {
+    match token {
+        Some(t) => Uuid::parse_str(&t)
+            .map(Some)
+            .map_err(|e| StorageError::Backend(e.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Implements every store trait for a backend whose `conn()` method returns a
+/// connection to a database using the `persistence::text_tokens` schema. The
+/// backend provides the pruning lock itself, through `try_lock_pruning_in` and
+/// `unlock_pruning_in` methods.
+macro_rules! impl_text_token_store {
+    ($store:ident) => {
+        impl ClientStore for $store {
+            fn find_client(&self, id: i32) -> StorageResult<Client> {
+                Ok(clients::table.find(id).first(self.conn())?)
+            }
+
+            fn find_client_by_identifier(&self, identifier: &str) -> StorageResult<Client> {
+                Ok(clients::table
+                    .filter(clients::identifier.eq(identifier))
+                    .first(self.conn())?)
+            }
+
+            fn set_client_disabled(
+                &self,
+                id: i32,
+                disabled_at: Option<NaiveDateTime>,
+            ) -> StorageResult<Client> {
+                match diesel::update(clients::table.find(id))
+                    .set(clients::disabled_at.eq(disabled_at))
+                    .execute(self.conn())?
+                {
+                    0 => Err(StorageError::NotFound),
+                    _ => Ok(clients::table.find(id).first(self.conn())?),
+                }
+            }
+
+            fn find_client_redirect_uris(&self, client_id: i32) -> StorageResult<Vec<ClientRedirectUri>> {
+                Ok(client_redirect_uris::table
+                    .filter(client_redirect_uris::client_id.eq(client_id))
+                    .order(client_redirect_uris::id)
+                    .load(self.conn())?)
+            }
+
+            fn find_token_lifetimes(
+                &self,
+                client_id: i32,
+                grant_id: i32,
+            ) -> StorageResult<Vec<TokenLifetime>> {
+                Ok(token_lifetimes::table
+                    .filter(
+                        token_lifetimes::client_id
+                            .is_null()
+                            .or(token_lifetimes::client_id.eq(client_id)),
+                    )
+                    .filter(
+                        token_lifetimes::grant_id
+                            .is_null()
+                            .or(token_lifetimes::grant_id.eq(grant_id)),
+                    )
+                    .load(self.conn())?)
+            }
+
+            fn list_token_lifetimes(&self) -> StorageResult<Vec<TokenLifetime>> {
+                Ok(token_lifetimes::table
+                    .order(token_lifetimes::id)
+                    .load(self.conn())?)
+            }
+
+            fn save_token_lifetime(&self, new_override: &NewTokenLifetime) -> StorageResult<TokenLifetime> {
+                check_token_lifetime(new_override)?;
+                let conn = self.conn();
+                self.write_transaction_in(|| {
+                    let mut existing = token_lifetimes::table.into_boxed();
+                    existing = match new_override.client_id {
+                        Some(id) => existing.filter(token_lifetimes::client_id.eq(id)),
+                        None => existing.filter(token_lifetimes::client_id.is_null()),
+                    };
+                    existing = match new_override.grant_id {
+                        Some(id) => existing.filter(token_lifetimes::grant_id.eq(id)),
+                        None => existing.filter(token_lifetimes::grant_id.is_null()),
+                    };
+                    existing = match new_override.scope {
+                        Some(ref s) => existing.filter(token_lifetimes::scope.eq(s.clone())),
+                        None => existing.filter(token_lifetimes::scope.is_null()),
+                    };
+                    let existing = existing.first::<TokenLifetime>(conn).optional()?;
+
+                    let id = match existing {
+                        Some(o) => {
+                            diesel::update(token_lifetimes::table.find(o.id))
+                                .set((
+                                    token_lifetimes::access_token_ttl.eq(new_override.access_token_ttl),
+                                    token_lifetimes::refresh_token_ttl.eq(new_override.refresh_token_ttl),
+                                ))
+                                .execute(conn)?;
+                            o.id
+                        }
+                        None => {
+                            diesel::insert_into(token_lifetimes::table)
+                                .values((
+                                    token_lifetimes::client_id.eq(new_override.client_id),
+                                    token_lifetimes::grant_id.eq(new_override.grant_id),
+                                    token_lifetimes::scope.eq(new_override.scope.clone()),
+                                    token_lifetimes::access_token_ttl.eq(new_override.access_token_ttl),
+                                    token_lifetimes::refresh_token_ttl.eq(new_override.refresh_token_ttl),
+                                ))
+                                .execute(conn)?;
+                            self.last_insert_id_in()?
+                        }
+                    };
+
+                    token_lifetimes::table.find(id).first(conn)
+                }).map_err(StorageError::from)
+            }
+
+            fn delete_token_lifetime(&self, id: i32) -> StorageResult<()> {
+                match diesel::delete(token_lifetimes::table.find(id)).execute(self.conn())? {
+                    0 => Err(StorageError::NotFound),
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        impl GrantTypeStore for $store {
+            fn find_grant_type(&self, id: i32) -> StorageResult<GrantType> {
+                Ok(grant_types::table.find(id).first(self.conn())?)
+            }
+
+            fn find_grant_type_by_name(&self, name: &str) -> StorageResult<GrantType> {
+                Ok(grant_types::table
+                    .filter(grant_types::name.eq(name))
+                    .first(self.conn())?)
+            }
+        }
+
+        impl TokenStore for $store {
+            fn create_access_token(&self, new_token: &NewAccessToken) -> StorageResult<AccessToken> {
+                diesel::insert_into(access_tokens::table)
+                    .values((
+                        access_tokens::token_hash.eq(&new_token.token_hash),
+                        access_tokens::client_id.eq(new_token.client_id),
+                        access_tokens::grant_id.eq(new_token.grant_id),
+                        access_tokens::scope.eq(&new_token.scope),
+                        access_tokens::issued_at.eq(new_token.issued_at),
+                        access_tokens::expires_at.eq(new_token.expires_at),
+                        access_tokens::refresh_token_id.eq(new_token.refresh_token_id),
+                        access_tokens::auth_code_id.eq(new_token.auth_code_id),
+                    ))
+                    .execute(self.conn())?;
+
+                self.find_access_token(&new_token.token_hash)
+            }
+
+            fn find_access_token(&self, token_hash: &str) -> StorageResult<AccessToken> {
+                access_tokens::table
+                    .filter(access_tokens::token_hash.eq(token_hash))
+                    .first::<AccessTokenRow>(self.conn())?
+                    .into_model()
+            }
+
+            fn find_legacy_access_token(&self, token: &Uuid) -> StorageResult<AccessToken> {
+                access_tokens::table
+                    .filter(access_tokens::token.eq(token.hyphenated().to_string()))
+                    .first::<AccessTokenRow>(self.conn())?
+                    .into_model()
+            }
+
+            fn find_recent_access_tokens(
+                &self,
+                client_id: i32,
+                grant_id: i32,
+                expires_after: NaiveDateTime,
+                limit: i64,
+            ) -> StorageResult<Vec<AccessToken>> {
+                access_tokens::table
+                    .filter(access_tokens::client_id.eq(client_id))
+                    .filter(access_tokens::grant_id.eq(grant_id))
+                    .filter(access_tokens::expires_at.gt(expires_after))
+                    .filter(access_tokens::revoked_at.is_null())
+                    .order(access_tokens::issued_at.desc())
+                    .limit(limit)
+                    .load::<AccessTokenRow>(self.conn())?
+                    .into_iter()
+                    .map(AccessTokenRow::into_model)
+                    .collect()
+            }
+
+            fn create_refresh_token(&self, new_token: &NewRefreshToken) -> StorageResult<RefreshToken> {
+                diesel::insert_into(refresh_tokens::table)
+                    .values((
+                        refresh_tokens::token_hash.eq(&new_token.token_hash),
+                        refresh_tokens::client_id.eq(new_token.client_id),
+                        refresh_tokens::scope.eq(&new_token.scope),
+                        refresh_tokens::issued_at.eq(new_token.issued_at),
+                        refresh_tokens::expires_at.eq(new_token.expires_at),
+                        refresh_tokens::absolute_expires_at.eq(new_token.absolute_expires_at),
+                        refresh_tokens::parent_id.eq(new_token.parent_id),
+                        refresh_tokens::auth_code_id.eq(new_token.auth_code_id),
+                    ))
+                    .execute(self.conn())?;
+
+                refresh_tokens::table
+                    .filter(refresh_tokens::token_hash.eq(&new_token.token_hash))
+                    .first::<RefreshTokenRow>(self.conn())?
+                    .into_model()
+            }
+
+            fn find_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshToken> {
+                refresh_tokens::table
+                    .filter(refresh_tokens::token_hash.eq(token_hash))
+                    .first::<RefreshTokenRow>(self.conn())?
+                    .into_model()
+            }
+
+            fn find_active_refresh_token(
+                &self,
+                client_id: i32,
+                token_hash: &str,
+                now: NaiveDateTime,
+            ) -> StorageResult<RefreshToken> {
+                refresh_tokens::table
+                    .filter(refresh_tokens::token_hash.eq(token_hash))
+                    .filter(refresh_tokens::client_id.eq(client_id))
+                    .filter(
+                        refresh_tokens::expires_at
+                            .is_null()
+                            .or(refresh_tokens::expires_at.gt(now)),
+                    )
+                    .filter(refresh_tokens::revoked_at.is_null())
+                    .first::<RefreshTokenRow>(self.conn())?
+                    .into_model()
+            }
+
+            fn find_active_legacy_refresh_token(
+                &self,
+                client_id: i32,
+                token: &Uuid,
+                now: NaiveDateTime,
+            ) -> StorageResult<RefreshToken> {
+                refresh_tokens::table
+                    .filter(refresh_tokens::token.eq(token.hyphenated().to_string()))
+                    .filter(refresh_tokens::client_id.eq(client_id))
+                    .filter(
+                        refresh_tokens::expires_at
+                            .is_null()
+                            .or(refresh_tokens::expires_at.gt(now)),
+                    )
+                    .filter(refresh_tokens::revoked_at.is_null())
+                    .order(refresh_tokens::issued_at.desc())
+                    .first::<RefreshTokenRow>(self.conn())?
+                    .into_model()
+            }
+
+            fn hash_legacy_refresh_token(
+                &self,
+                id: i32,
+                token_hash: &str,
+            ) -> StorageResult<RefreshToken> {
+                diesel::update(refresh_tokens::table.find(id))
+                    .set((
+                        refresh_tokens::token.eq(None::<String>),
+                        refresh_tokens::token_hash.eq(Some(token_hash)),
+                    ))
+                    .execute(self.conn())?;
+
+                refresh_tokens::table
+                    .find(id)
+                    .first::<RefreshTokenRow>(self.conn())?
+                    .into_model()
+            }
+
+            fn update_refresh_token_expiry(
+                &self,
+                id: i32,
+                last_used_at: NaiveDateTime,
+                expires_at: Option<NaiveDateTime>,
+            ) -> StorageResult<RefreshToken> {
+                diesel::update(refresh_tokens::table.find(id))
+                    .set((
+                        refresh_tokens::last_used_at.eq(Some(last_used_at)),
+                        refresh_tokens::expires_at.eq(expires_at),
+                    ))
+                    .execute(self.conn())?;
+
+                refresh_tokens::table
+                    .find(id)
+                    .first::<RefreshTokenRow>(self.conn())?
+                    .into_model()
+            }
+
+            fn find_child_refresh_token_ids(&self, parent_ids: &[i32]) -> StorageResult<Vec<i32>> {
+                Ok(refresh_tokens::table
+                    .select(refresh_tokens::id)
+                    .filter(refresh_tokens::parent_id.eq_any(parent_ids))
+                    .filter(refresh_tokens::revoked_at.is_null())
+                    .load(self.conn())?)
+            }
+
+            fn find_access_token_ids_by_refresh_tokens(
+                &self,
+                refresh_token_ids: &[i32],
+            ) -> StorageResult<Vec<i32>> {
+                Ok(access_tokens::table
+                    .select(access_tokens::id)
+                    .filter(access_tokens::refresh_token_id.eq_any(refresh_token_ids))
+                    .filter(access_tokens::revoked_at.is_null())
+                    .load(self.conn())?)
+            }
+
+            fn find_refresh_token_ids_by_auth_code(
+                &self,
+                auth_code_id: i32,
+            ) -> StorageResult<Vec<i32>> {
+                Ok(refresh_tokens::table
+                    .select(refresh_tokens::id)
+                    .filter(refresh_tokens::auth_code_id.eq(auth_code_id))
+                    .filter(refresh_tokens::revoked_at.is_null())
+                    .load(self.conn())?)
+            }
+
+            fn find_access_token_ids_by_auth_code(
+                &self,
+                auth_code_id: i32,
+            ) -> StorageResult<Vec<i32>> {
+                Ok(access_tokens::table
+                    .select(access_tokens::id)
+                    .filter(access_tokens::auth_code_id.eq(auth_code_id))
+                    .filter(access_tokens::revoked_at.is_null())
+                    .load(self.conn())?)
+            }
+
+            fn revoke_access_tokens(
+                &self,
+                ids: &[i32],
+                revoked_at: NaiveDateTime,
+            ) -> StorageResult<usize> {
+                Ok(diesel::update(
+                    access_tokens::table
+                        .filter(access_tokens::id.eq_any(ids))
+                        .filter(access_tokens::revoked_at.is_null()),
+                ).set(access_tokens::revoked_at.eq(Some(revoked_at)))
+                    .execute(self.conn())?)
+            }
+
+            fn revoke_refresh_tokens(
+                &self,
+                ids: &[i32],
+                revoked_at: NaiveDateTime,
+            ) -> StorageResult<usize> {
+                Ok(diesel::update(
+                    refresh_tokens::table
+                        .filter(refresh_tokens::id.eq_any(ids))
+                        .filter(refresh_tokens::revoked_at.is_null()),
+                ).set(refresh_tokens::revoked_at.eq(Some(revoked_at)))
+                    .execute(self.conn())?)
+            }
+        }
+
+        impl CodeStore for $store {
+            fn find_auth_code(&self, client_id: i32, code: &str) -> StorageResult<AuthCode> {
+                Ok(auth_codes::table
+                    .filter(auth_codes::client_id.eq(client_id))
+                    .filter(auth_codes::name.eq(code))
+                    .first(self.conn())?)
+            }
+
+            fn delete_auth_code(&self, id: i32) -> StorageResult<()> {
+                match diesel::delete(auth_codes::table.find(id)).execute(self.conn())? {
+                    0 => Err(StorageError::NotFound),
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        impl MigrationStore for $store {
+            fn applied_migrations(&self) -> StorageResult<Vec<i32>> {
+                self.conn().batch_execute(
+                    "CREATE TABLE IF NOT EXISTS schema_migrations (
+                       version INTEGER NOT NULL PRIMARY KEY,
+                       name VARCHAR(255) NOT NULL,
+                       applied_at DATETIME NOT NULL
+                     )",
+                )?;
+                Ok(schema_migrations::table
+                    .select(schema_migrations::version)
+                    .order(schema_migrations::version)
+                    .load(self.conn())?)
+            }
+
+            // MySQL commits implicitly around DDL statements, so there a
+            // failing script can leave its earlier statements applied.
+            fn apply_migration(&self, migration: &Migration) -> StorageResult<()> {
+                Ok(self.conn().transaction(|| {
+                    self.conn().batch_execute(migration.up)?;
+                    self.mark_migration_in(migration)
+                })?)
+            }
+
+            fn revert_migration(&self, migration: &Migration) -> StorageResult<()> {
+                Ok(self.conn().transaction(|| {
+                    self.conn().batch_execute(migration.down)?;
+                    diesel::delete(schema_migrations::table.find(migration.version))
+                        .execute(self.conn())
+                        .map(|_| ())
+                })?)
+            }
+
+            fn mark_migration(&self, migration: &Migration) -> StorageResult<()> {
+                Ok(self.mark_migration_in(migration)?)
+            }
+        }
+
+        impl RevocationStore for $store {
+            fn find_revocable_access_tokens(
+                &self,
+                filter: &TokenFilter,
+                after_id: i32,
+                limit: i64,
+            ) -> StorageResult<Vec<(i32, String)>> {
+                let mut query = access_tokens::table
+                    .select((access_tokens::id, access_tokens::scope))
+                    .filter(access_tokens::id.gt(after_id))
+                    .filter(access_tokens::revoked_at.is_null())
+                    .filter(access_tokens::expires_at.gt(filter.live_at))
+                    .into_boxed();
+                if let Some(client_id) = filter.client_id {
+                    query = query.filter(access_tokens::client_id.eq(client_id));
+                }
+                if let Some(issued_after) = filter.issued_after {
+                    query = query.filter(access_tokens::issued_at.ge(issued_after));
+                }
+                if let Some(issued_before) = filter.issued_before {
+                    query = query.filter(access_tokens::issued_at.lt(issued_before));
+                }
+                Ok(query
+                    .order(access_tokens::id)
+                    .limit(limit)
+                    .load(self.conn())?)
+            }
+
+            fn find_revocable_refresh_tokens(
+                &self,
+                filter: &TokenFilter,
+                after_id: i32,
+                limit: i64,
+            ) -> StorageResult<Vec<(i32, String)>> {
+                let mut query = refresh_tokens::table
+                    .select((refresh_tokens::id, refresh_tokens::scope))
+                    .filter(refresh_tokens::id.gt(after_id))
+                    .filter(refresh_tokens::revoked_at.is_null())
+                    .filter(
+                        refresh_tokens::expires_at
+                            .is_null()
+                            .or(refresh_tokens::expires_at.gt(filter.live_at)),
+                    )
+                    .into_boxed();
+                if let Some(client_id) = filter.client_id {
+                    query = query.filter(refresh_tokens::client_id.eq(client_id));
+                }
+                if let Some(issued_after) = filter.issued_after {
+                    query = query.filter(refresh_tokens::issued_at.ge(issued_after));
+                }
+                if let Some(issued_before) = filter.issued_before {
+                    query = query.filter(refresh_tokens::issued_at.lt(issued_before));
+                }
+                Ok(query
+                    .order(refresh_tokens::id)
+                    .limit(limit)
+                    .load(self.conn())?)
+            }
+
+            fn record_revocation(&self, entry: &NewRevocation) -> StorageResult<()> {
+                diesel::insert_into(token_revocations::table)
+                    .values((
+                        token_revocations::revoked_at.eq(entry.revoked_at),
+                        token_revocations::actor.eq(&entry.actor),
+                        token_revocations::reason.eq(&entry.reason),
+                        token_revocations::selection.eq(&entry.selection),
+                        token_revocations::access_tokens.eq(entry.access_tokens),
+                        token_revocations::refresh_tokens.eq(entry.refresh_tokens),
+                    ))
+                    .execute(self.conn())?;
+                Ok(())
+            }
+
+            fn list_revocations(&self, limit: i64) -> StorageResult<Vec<Revocation>> {
+                Ok(token_revocations::table
+                    .order(token_revocations::id.desc())
+                    .limit(limit)
+                    .load(self.conn())?)
+            }
+
+            fn latest_revocation_id(&self) -> StorageResult<Option<i32>> {
+                Ok(token_revocations::table
+                    .select(token_revocations::id)
+                    .order(token_revocations::id.desc())
+                    .first(self.conn())
+                    .optional()?)
+            }
+        }
+
+        // MySQL cannot use LIMIT in an IN subquery, so each batch is selected
+        // before it is deleted.
+        impl PruneStore for $store {
+            fn try_lock_pruning(&self) -> StorageResult<bool> {
+                self.try_lock_pruning_in()
+            }
+
+            fn unlock_pruning(&self) -> StorageResult<()> {
+                self.unlock_pruning_in()
+            }
+
+            fn prune_access_tokens(
+                &self,
+                expired_before: NaiveDateTime,
+                limit: i64,
+            ) -> StorageResult<usize> {
+                let batch = access_tokens::table
+                    .select(access_tokens::id)
+                    .filter(
+                        access_tokens::expires_at
+                            .lt(expired_before)
+                            .or(access_tokens::revoked_at.lt(expired_before)),
+                    )
+                    .limit(limit)
+                    .load::<i32>(self.conn())?;
+                Ok(diesel::delete(access_tokens::table.filter(access_tokens::id.eq_any(batch)))
+                    .execute(self.conn())?)
+            }
+
+            fn prune_refresh_tokens(
+                &self,
+                expired_before: NaiveDateTime,
+                limit: i64,
+            ) -> StorageResult<usize> {
+                let batch = refresh_tokens::table
+                    .select(refresh_tokens::id)
+                    .filter(
+                        refresh_tokens::expires_at
+                            .lt(expired_before)
+                            .or(refresh_tokens::revoked_at.lt(expired_before)),
+                    )
+                    .filter(diesel::dsl::sql::<diesel::sql_types::Bool>(
+                        crate::storage::text_tokens::UNREFERENCED_REFRESH_TOKEN,
+                    ))
+                    .limit(limit)
+                    .load::<i32>(self.conn())?;
+                Ok(diesel::delete(refresh_tokens::table.filter(refresh_tokens::id.eq_any(batch)))
+                    .execute(self.conn())?)
+            }
+
+            fn prune_auth_codes(
+                &self,
+                expired_before: NaiveDateTime,
+                limit: i64,
+            ) -> StorageResult<usize> {
+                let batch = auth_codes::table
+                    .select(auth_codes::id)
+                    .filter(auth_codes::expires_at.lt(expired_before))
+                    .limit(limit)
+                    .load::<i32>(self.conn())?;
+                Ok(diesel::delete(auth_codes::table.filter(auth_codes::id.eq_any(batch)))
+                    .execute(self.conn())?)
+            }
+        }
+
+        impl $store {
+            fn mark_migration_in(&self, migration: &Migration) -> QueryResult<()> {
+                diesel::insert_into(schema_migrations::table)
+                    .values((
+                        schema_migrations::version.eq(migration.version),
+                        schema_migrations::name.eq(migration.name),
+                        schema_migrations::applied_at.eq(Utc::now().naive_utc()),
+                    ))
+                    .execute(self.conn())
+                    .map(|_| ())
+            }
+        }
+    };
+}
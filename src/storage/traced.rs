@@ -0,0 +1,157 @@
+use crate::migrations::Migration;
+use crate::models::db::*;
+use crate::storage::{
+    ClientStore, CodeStore, GrantTypeStore, MigrationStore, PruneStore, RevocationStore, Storage,
+    StorageError, StorageResult, TokenFilter, TokenStore,
+};
+use crate::tracing::Span;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+/// Wraps a store so that every call made through it is recorded as a span,
+/// named after the method and tagged with the backend, see `tracing`.
+pub struct TracedStore {
+    inner: Box<dyn Storage>,
+    system: &'static str,
+}
+
+impl TracedStore {
+    pub fn new(inner: Box<dyn Storage>, system: &'static str) -> TracedStore {
+        TracedStore {
+            inner,
+            system,
+        }
+    }
+}
+
+// Implements each listed trait method by forwarding it to the inner store
+// within a span. Failures other than a missing record mark the span as failed.
+macro_rules! traced {
+    ($(fn $name:ident(&self $(, $arg:ident: $ty:ty)*) -> $ret:ty;)*) => {
+        $(
+            fn $name(&self $(, $arg: $ty)*) -> $ret {
+                let mut span = Span::start(concat!("db.", stringify!($name)));
+                span.set_attribute("db.system", self.system);
+                span.set_attribute("db.operation", stringify!($name));
+
+                let result = self.inner.$name($($arg),*);
+                match result {
+                    Err(StorageError::NotFound) | Ok(_) => (),
+                    Err(ref e) => span.set_error(&e.to_string()),
+                }
+                result
+            }
+        )*
+    };
+}
+
+impl ClientStore for TracedStore {
+    traced! {
+        fn find_client(&self, id: i32) -> StorageResult<Client>;
+        fn find_client_by_identifier(&self, identifier: &str) -> StorageResult<Client>;
+        fn set_client_disabled(&self, id: i32, disabled_at: Option<NaiveDateTime>) -> StorageResult<Client>;
+        fn find_client_redirect_uris(&self, client_id: i32) -> StorageResult<Vec<ClientRedirectUri>>;
+        fn find_token_lifetimes(&self, client_id: i32, grant_id: i32) -> StorageResult<Vec<TokenLifetime>>;
+        fn list_token_lifetimes(&self) -> StorageResult<Vec<TokenLifetime>>;
+        fn save_token_lifetime(&self, new_override: &NewTokenLifetime) -> StorageResult<TokenLifetime>;
+        fn delete_token_lifetime(&self, id: i32) -> StorageResult<()>;
+    }
+}
+
+impl GrantTypeStore for TracedStore {
+    traced! {
+        fn find_grant_type(&self, id: i32) -> StorageResult<GrantType>;
+        fn find_grant_type_by_name(&self, name: &str) -> StorageResult<GrantType>;
+    }
+}
+
+impl TokenStore for TracedStore {
+    traced! {
+        fn create_access_token(&self, new_token: &NewAccessToken) -> StorageResult<AccessToken>;
+        fn find_access_token(&self, token_hash: &str) -> StorageResult<AccessToken>;
+        fn find_legacy_access_token(&self, token: &Uuid) -> StorageResult<AccessToken>;
+        fn find_recent_access_tokens(
+            &self,
+            client_id: i32,
+            grant_id: i32,
+            expires_after: NaiveDateTime,
+            limit: i64
+        ) -> StorageResult<Vec<AccessToken>>;
+        fn create_refresh_token(&self, new_token: &NewRefreshToken) -> StorageResult<RefreshToken>;
+        fn find_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshToken>;
+        fn find_active_refresh_token(
+            &self,
+            client_id: i32,
+            token_hash: &str,
+            now: NaiveDateTime
+        ) -> StorageResult<RefreshToken>;
+        fn find_active_legacy_refresh_token(
+            &self,
+            client_id: i32,
+            token: &Uuid,
+            now: NaiveDateTime
+        ) -> StorageResult<RefreshToken>;
+        fn hash_legacy_refresh_token(&self, id: i32, token_hash: &str) -> StorageResult<RefreshToken>;
+        fn update_refresh_token_expiry(
+            &self,
+            id: i32,
+            last_used_at: NaiveDateTime,
+            expires_at: Option<NaiveDateTime>
+        ) -> StorageResult<RefreshToken>;
+        fn find_child_refresh_token_ids(&self, parent_ids: &[i32]) -> StorageResult<Vec<i32>>;
+        fn find_access_token_ids_by_refresh_tokens(
+            &self,
+            refresh_token_ids: &[i32]
+        ) -> StorageResult<Vec<i32>>;
+        fn find_refresh_token_ids_by_auth_code(&self, auth_code_id: i32) -> StorageResult<Vec<i32>>;
+        fn find_access_token_ids_by_auth_code(&self, auth_code_id: i32) -> StorageResult<Vec<i32>>;
+        fn revoke_access_tokens(&self, ids: &[i32], revoked_at: NaiveDateTime) -> StorageResult<usize>;
+        fn revoke_refresh_tokens(&self, ids: &[i32], revoked_at: NaiveDateTime) -> StorageResult<usize>;
+    }
+}
+
+impl CodeStore for TracedStore {
+    traced! {
+        fn find_auth_code(&self, client_id: i32, code: &str) -> StorageResult<AuthCode>;
+        fn delete_auth_code(&self, id: i32) -> StorageResult<()>;
+    }
+}
+
+impl MigrationStore for TracedStore {
+    traced! {
+        fn applied_migrations(&self) -> StorageResult<Vec<i32>>;
+        fn apply_migration(&self, migration: &Migration) -> StorageResult<()>;
+        fn revert_migration(&self, migration: &Migration) -> StorageResult<()>;
+        fn mark_migration(&self, migration: &Migration) -> StorageResult<()>;
+    }
+}
+
+impl RevocationStore for TracedStore {
+    traced! {
+        fn find_revocable_access_tokens(
+            &self,
+            filter: &TokenFilter,
+            after_id: i32,
+            limit: i64
+        ) -> StorageResult<Vec<(i32, String)>>;
+        fn find_revocable_refresh_tokens(
+            &self,
+            filter: &TokenFilter,
+            after_id: i32,
+            limit: i64
+        ) -> StorageResult<Vec<(i32, String)>>;
+        fn record_revocation(&self, entry: &NewRevocation) -> StorageResult<()>;
+        fn list_revocations(&self, limit: i64) -> StorageResult<Vec<Revocation>>;
+        fn latest_revocation_id(&self) -> StorageResult<Option<i32>>;
+    }
+}
+
+impl PruneStore for TracedStore {
+    traced! {
+        fn try_lock_pruning(&self) -> StorageResult<bool>;
+        fn unlock_pruning(&self) -> StorageResult<()>;
+        fn prune_access_tokens(&self, expired_before: NaiveDateTime, limit: i64) -> StorageResult<usize>;
+        fn prune_refresh_tokens(&self, expired_before: NaiveDateTime, limit: i64) -> StorageResult<usize>;
+        fn prune_auth_codes(&self, expired_before: NaiveDateTime, limit: i64) -> StorageResult<usize>;
+    }
+}
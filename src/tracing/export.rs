@@ -0,0 +1,181 @@
+use crate::SETTINGS;
+use crate::models::configuration::TraceExporter;
+use crate::tracing::{encode_hex, AttributeValue, FinishedSpan, SpanKind};
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Spans waiting to be exported. When the exporter falls this far behind, e.g.
+// because the collector is down, new spans are dropped rather than slowing
+// down requests or growing without bound.
+const QUEUE_SIZE: usize = 4_096;
+const BATCH_SIZE: usize = 512;
+const TIMEOUT: u64 = 10;
+
+lazy_static! {
+    static ref QUEUE: Mutex<Option<SyncSender<FinishedSpan>>> = Mutex::new(None);
+}
+
+/// Queues a finished span for export. Spans are dropped while no exporter
+/// runs, e.g. when the provider is hosted in-process by another crate.
+pub fn send(span: FinishedSpan) {
+    let queue = QUEUE.lock().unwrap(); // TODO: remove unwrap
+    if let Some(ref sender) = *queue {
+        if let Err(TrySendError::Full(_)) = sender.try_send(span) {
+            debug!("The trace export queue is full, dropping a span");
+        }
+    }
+}
+
+/// Starts the background thread exporting spans to `tracing.exporter`.
+///
+/// Returns: io::Result<JoinHandle<()>> --- the thread, which finishes once
+/// `close` was called and every queued span was exported.
+pub fn spawn() -> io::Result<JoinHandle<()>> {
+    let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+    *QUEUE.lock().unwrap() = Some(sender); // TODO: remove unwrap
+
+    thread::Builder::new()
+        .name("oa2p-trace-exporter".to_owned())
+        .spawn(move || run(&receiver))
+}
+
+/// Stops queueing spans. The exporter still exports the spans already queued,
+/// then finishes.
+pub fn close() {
+    QUEUE.lock().unwrap().take(); // TODO: remove unwrap
+}
+
+// Spans are sent once a full batch is waiting, or at least every
+// `tracing.export_interval` milliseconds.
+fn run(receiver: &Receiver<FinishedSpan>) {
+    let interval = Duration::from_millis(SETTINGS.tracing.export_interval);
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut deadline = Instant::now() + interval;
+
+    loop {
+        let now = Instant::now();
+        let timeout = if deadline > now {
+            deadline - now
+        } else {
+            Duration::from_millis(0)
+        };
+
+        let closed = match receiver.recv_timeout(timeout) {
+            Ok(span) => {
+                batch.push(span);
+                if batch.len() < BATCH_SIZE {
+                    continue;
+                }
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        if !batch.is_empty() {
+            if let Err(e) = export(&batch) {
+                warn!("Exporting {} spans failed: {}", batch.len(), e);
+            }
+            batch.clear();
+        }
+        if closed {
+            return;
+        }
+        deadline = Instant::now() + interval;
+    }
+}
+
+fn export(batch: &[FinishedSpan]) -> Result<(), String> {
+    let body = to_otlp(batch).to_string();
+
+    match SETTINGS.tracing.exporter {
+        TraceExporter::Otlp => {
+            let response = ureq::post(&SETTINGS.tracing.endpoint)
+                .timeout(Duration::from_secs(TIMEOUT))
+                .set("Content-Type", "application/json")
+                .send_string(&body);
+            match response {
+                Ok(_) => Ok(()),
+                Err(ureq::Error::Status(status, _)) => {
+                    Err(format!("the collector answered {}", status))
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        TraceExporter::File => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&SETTINGS.tracing.path)
+            .and_then(|mut file| writeln!(file, "{}", body))
+            .map_err(|e| format!("{}: {}", SETTINGS.tracing.path, e)),
+    }
+}
+
+// The OTLP/JSON encoding of an ExportTraceServiceRequest, see
+// https://github.com/open-telemetry/opentelemetry-proto. IDs are hex encoded
+// and 64 bit integers are strings.
+fn to_otlp(batch: &[FinishedSpan]) -> Value {
+    let spans: Vec<Value> = batch
+        .iter()
+        .map(|span| {
+            let attributes: Vec<Value> = span
+                .attributes
+                .iter()
+                .map(|&(key, ref value)| attribute(key, value))
+                .collect();
+            let status = match span.error {
+                Some(ref message) => json!({ "code": 2, "message": message }),
+                None => json!({ "code": 0 }),
+            };
+
+            json!({
+                "traceId": encode_hex(&span.context.trace_id),
+                "spanId": encode_hex(&span.context.span_id),
+                "parentSpanId": span.parent_span_id.map_or_else(String::new, |id| encode_hex(&id)),
+                "name": span.name,
+                "kind": match span.kind {
+                    SpanKind::Internal => 1,
+                    SpanKind::Server => 2,
+                },
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": attributes,
+                "status": status,
+            })
+        })
+        .collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", &AttributeValue::Str(SETTINGS.tracing.service_name.clone())),
+                    attribute("service.version", &AttributeValue::from(env!("CARGO_PKG_VERSION"))),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "oa2p" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn attribute(key: &str, value: &AttributeValue) -> Value {
+    let value = match *value {
+        AttributeValue::Str(ref s) => json!({ "stringValue": s }),
+        AttributeValue::Int(i) => json!({ "intValue": i.to_string() }),
+        AttributeValue::Bool(b) => json!({ "boolValue": b }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    (since_epoch.as_secs() * 1_000_000_000 + u64::from(since_epoch.subsec_nanos())).to_string()
+}
@@ -0,0 +1,79 @@
+use crate::tracing::{Span, SpanContext, TRACEPARENT_HEADER};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::sync::Mutex;
+
+// The request span lives with the request, since the worker thread handling
+// it may change at every await.
+struct RequestSpan {
+    context: Option<SpanContext>,
+    span: Mutex<Option<Span>>,
+}
+
+/// Records a server span for every request, continuing the trace named in
+/// the `traceparent` header. Every span started while the request is handled
+/// is a descendant of it.
+pub struct TraceRequest;
+
+/// What identifies the span recorded for a request, if it is recorded.
+pub fn span_context_of(request: &Request<'_>) -> Option<SpanContext> {
+    request
+        .local_cache(|| RequestSpan {
+            context: None,
+            span: Mutex::new(None),
+        })
+        .context
+}
+
+#[rocket::async_trait]
+impl Fairing for TraceRequest {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request tracing",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        let remote = request
+            .headers()
+            .get_one(TRACEPARENT_HEADER)
+            .and_then(SpanContext::from_traceparent);
+
+        // The route is not known until the request has been routed, so the
+        // span is renamed once the response is ready.
+        let mut span = Span::server(request.method().as_str(), remote);
+        span.set_attribute("http.request.method", request.method().as_str());
+        span.set_attribute("url.path", request.uri().path().as_str());
+        request.local_cache(|| RequestSpan {
+            context: span.context(),
+            span: Mutex::new(Some(span)),
+        });
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let cached = request.local_cache(|| RequestSpan {
+            context: None,
+            span: Mutex::new(None),
+        });
+        let taken = cached.span.lock().unwrap().take(); // TODO: remove unwrap
+        let mut span = match taken {
+            Some(span) => span,
+            None => return,
+        };
+
+        if let Some(route) = request.route() {
+            span.set_name(&format!(
+                "{} {}",
+                request.method().as_str(),
+                route.uri.path()
+            ));
+            span.set_attribute("http.route", route.uri.path());
+        }
+        let status = response.status();
+        span.set_attribute("http.response.status_code", i64::from(status.code));
+        if status.code >= 500 {
+            span.set_error(status.reason().unwrap_or("server error"));
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! The tracing module records spans for requests, client authentication and
+//! storage calls, and exports them to an OpenTelemetry collector so that the
+//! provider shows up in the traces of the services calling it.
+//!
+//! Trace context arrives in the W3C `traceparent` header, see
+//! https://www.w3.org/TR/trace-context/. Requests without one start a new
+//! trace, sampled at `tracing.sample_ratio`. Like metrics, spans are kept and
+//! exported in process rather than through a client library: finished spans
+//! are queued for a background thread, which sends them in batches as OTLP
+//! JSON over HTTP, or appends them to a file.
+//!
+//! The span a new span is a child of is tracked per thread. Requests are
+//! handled on async workers, so the request span is kept with the request, see
+//! `TraceRequest`, and entered on the blocking thread doing the request's work,
+//! see `web::context`. Spans are only recorded within a sampled trace; outside
+//! of one, e.g. in the CLI or the pruning thread, starting a span does nothing.
+
+mod export;
+mod fairing;
+
+pub use self::export::{close, spawn};
+pub use self::fairing::{span_context_of, TraceRequest};
+
+use crate::settings;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+/// The header trace context is propagated in.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+thread_local! {
+    static CURRENT: RefCell<Vec<SpanContext>> = const { RefCell::new(Vec::new()) };
+}
+
+/// What identifies a span across process boundaries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpanContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl SpanContext {
+    /// Parses a `traceparent` header value, e.g.
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    ///
+    /// Returns: Option<SpanContext> --- None if the value is malformed, in
+    /// which case the request starts a new trace.
+    pub fn from_traceparent(value: &str) -> Option<SpanContext> {
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        // Later versions may append fields, but keep the first four
+        if parts.len() < 4
+            || parts[0].len() != 2
+            || parts[0] == "ff"
+            || (parts[0] == "00" && parts.len() != 4)
+        {
+            return None;
+        }
+
+        let mut version = [0u8; 1];
+        let mut trace_id = [0u8; 16];
+        let mut span_id = [0u8; 8];
+        let mut flags = [0u8; 1];
+        if !decode_hex(parts[0], &mut version)
+            || !decode_hex(parts[1], &mut trace_id)
+            || !decode_hex(parts[2], &mut span_id)
+            || !decode_hex(parts[3], &mut flags)
+        {
+            return None;
+        }
+        if trace_id.iter().all(|&b| b == 0) || span_id.iter().all(|&b| b == 0) {
+            return None;
+        }
+
+        Some(SpanContext {
+            trace_id,
+            span_id,
+            sampled: flags[0] & 1 == 1,
+        })
+    }
+}
+
+/// Makes a span the parent of the spans started on this thread, until the
+/// returned guard is dropped.
+pub fn enter(context: Option<SpanContext>) -> Entered {
+    if let Some(context) = context {
+        CURRENT.with(|current| current.borrow_mut().push(context));
+    }
+    Entered { context }
+}
+
+/// Keeps a span entered, see `enter`.
+pub struct Entered {
+    context: Option<SpanContext>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        if let Some(context) = self.context {
+            CURRENT.with(|current| {
+                let mut current = current.borrow_mut();
+                if current.last() == Some(&context) {
+                    current.pop();
+                }
+            });
+        }
+    }
+}
+
+/// The role of a span, as defined by OpenTelemetry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpanKind {
+    Internal,
+    Server,
+}
+
+/// The value of a span attribute.
+#[derive(Clone, Debug)]
+pub enum AttributeValue {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl<'a> From<&'a str> for AttributeValue {
+    fn from(value: &'a str) -> AttributeValue {
+        AttributeValue::Str(value.to_owned())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> AttributeValue {
+        AttributeValue::Str(value)
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> AttributeValue {
+        AttributeValue::Int(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> AttributeValue {
+        AttributeValue::Bool(value)
+    }
+}
+
+/// A span that has ended, waiting to be exported.
+#[derive(Debug)]
+pub struct FinishedSpan {
+    pub context: SpanContext,
+    pub parent_span_id: Option<[u8; 8]>,
+    pub name: String,
+    pub kind: SpanKind,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, AttributeValue)>,
+    pub error: Option<String>,
+}
+
+/// A span being recorded, which ends when dropped. A span started by `start`
+/// becomes the parent of spans started on the same thread until it ends.
+pub struct Span {
+    recording: Option<FinishedSpan>,
+}
+
+impl Span {
+    /// Starts a span as a child of the current one. Outside of a sampled
+    /// trace the span records nothing.
+    pub fn start(name: &str) -> Span {
+        match CURRENT.with(|current| current.borrow().last().cloned()) {
+            Some(parent) => {
+                let span = Span::begin(name, SpanKind::Internal, parent.trace_id, Some(parent));
+                if let Some(context) = span.context() {
+                    CURRENT.with(|current| current.borrow_mut().push(context));
+                }
+                span
+            }
+            None => Span { recording: None },
+        }
+    }
+
+    /// Starts the span for a request received by the provider, continuing the
+    /// caller's trace if there is one and starting a new trace otherwise. The
+    /// span is not entered on any thread, see `enter`.
+    pub fn server(name: &str, remote: Option<SpanContext>) -> Span {
+        match remote {
+            Some(remote) if remote.sampled => {
+                Span::begin(name, SpanKind::Server, remote.trace_id, Some(remote))
+            }
+            Some(_) => Span { recording: None },
+            None => {
+                let mut trace_id = [0u8; 16];
+                random_id(&mut trace_id);
+                if is_sampled(&trace_id) {
+                    Span::begin(name, SpanKind::Server, trace_id, None)
+                } else {
+                    Span { recording: None }
+                }
+            }
+        }
+    }
+
+    fn begin(name: &str, kind: SpanKind, trace_id: [u8; 16], parent: Option<SpanContext>) -> Span {
+        let mut span_id = [0u8; 8];
+        random_id(&mut span_id);
+        let context = SpanContext {
+            trace_id,
+            span_id,
+            sampled: true,
+        };
+
+        Span {
+            recording: Some(FinishedSpan {
+                context,
+                parent_span_id: parent.map(|p| p.span_id),
+                name: name.to_owned(),
+                kind,
+                start: SystemTime::now(),
+                end: SystemTime::now(),
+                attributes: Vec::new(),
+                error: None,
+            }),
+        }
+    }
+
+    /// What identifies the span, if it is recorded.
+    pub fn context(&self) -> Option<SpanContext> {
+        self.recording.as_ref().map(|span| span.context)
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        if let Some(ref mut span) = self.recording {
+            span.name = name.to_owned();
+        }
+    }
+
+    pub fn set_attribute<V: Into<AttributeValue>>(&mut self, key: &'static str, value: V) {
+        if let Some(ref mut span) = self.recording {
+            span.attributes.push((key, value.into()));
+        }
+    }
+
+    /// Marks the span as failed. The message must not contain secrets.
+    pub fn set_error(&mut self, message: &str) {
+        if let Some(ref mut span) = self.recording {
+            span.error = Some(message.to_owned());
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(mut span) = self.recording.take() {
+            CURRENT.with(|current| {
+                let mut current = current.borrow_mut();
+                if current.last() == Some(&span.context) {
+                    current.pop();
+                }
+            });
+            span.end = SystemTime::now();
+            export::send(span);
+        }
+    }
+}
+
+// Sampling by the trace ID rather than a separate random number means every
+// instance seeing the same new trace makes the same decision.
+fn is_sampled(trace_id: &[u8; 16]) -> bool {
+    let ratio = settings::current().tracing.sample_ratio;
+    if ratio >= 1.0 {
+        return true;
+    }
+    let value = trace_id[8..]
+        .iter()
+        .fold(0u64, |value, &b| (value << 8) | u64::from(b));
+    (value as f64) < ratio * (u64::MAX as f64)
+}
+
+fn random_id(id: &mut [u8]) {
+    SystemRandom::new()
+        .fill(id)
+        .expect("The system random number generator failed");
+}
+
+fn decode_hex(hex: &str, out: &mut [u8]) -> bool {
+    // Upper case is invalid in trace context
+    if hex.len() != out.len() * 2
+        || !hex
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    {
+        return false;
+    }
+    for (i, byte) in out.iter_mut().enumerate() {
+        match u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16) {
+            Ok(b) => *byte = b,
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const SPAN_ID: &str = "00f067aa0ba902b7";
+
+    fn traceparent(version: &str, trace_id: &str, span_id: &str, flags: &str) -> String {
+        format!("{}-{}-{}-{}", version, trace_id, span_id, flags)
+    }
+
+    #[test]
+    fn parses_a_traceparent() {
+        let context =
+            SpanContext::from_traceparent(&traceparent("00", TRACE_ID, SPAN_ID, "01")).unwrap();
+        assert_eq!(encode_hex(&context.trace_id), TRACE_ID);
+        assert_eq!(encode_hex(&context.span_id), SPAN_ID);
+        assert!(context.sampled);
+
+        let context =
+            SpanContext::from_traceparent(&traceparent("00", TRACE_ID, SPAN_ID, "00")).unwrap();
+        assert!(!context.sampled);
+    }
+
+    #[test]
+    fn reads_only_the_sampled_flag() {
+        let context =
+            SpanContext::from_traceparent(&traceparent("00", TRACE_ID, SPAN_ID, "03")).unwrap();
+        assert!(context.sampled);
+        let context =
+            SpanContext::from_traceparent(&traceparent("00", TRACE_ID, SPAN_ID, "02")).unwrap();
+        assert!(!context.sampled);
+    }
+
+    #[test]
+    fn accepts_fields_appended_by_later_versions() {
+        let value = format!(
+            "{}-what-comes-next",
+            traceparent("01", TRACE_ID, SPAN_ID, "01")
+        );
+        assert!(SpanContext::from_traceparent(&value).is_some());
+        let value = format!(" {} ", traceparent("00", TRACE_ID, SPAN_ID, "01"));
+        assert!(SpanContext::from_traceparent(&value).is_some());
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        for version in &["ff", "zz", "0", "000"] {
+            let value = traceparent(version, TRACE_ID, SPAN_ID, "01");
+            assert_eq!(SpanContext::from_traceparent(&value), None, "{}", value);
+        }
+        let value = format!("{}-extra", traceparent("00", TRACE_ID, SPAN_ID, "01"));
+        assert_eq!(SpanContext::from_traceparent(&value), None);
+    }
+
+    #[test]
+    fn rejects_malformed_ids_and_flags() {
+        let zero_trace = "0".repeat(32);
+        let zero_span = "0".repeat(16);
+        let upper_trace = TRACE_ID.to_uppercase();
+        for value in &[
+            traceparent("00", &zero_trace, SPAN_ID, "01"),
+            traceparent("00", TRACE_ID, &zero_span, "01"),
+            traceparent("00", &upper_trace, SPAN_ID, "01"),
+            traceparent("00", &TRACE_ID[1..], SPAN_ID, "01"),
+            traceparent("00", TRACE_ID, &SPAN_ID[1..], "01"),
+            traceparent("00", TRACE_ID, SPAN_ID, "1"),
+            traceparent("00", TRACE_ID, SPAN_ID, "0g"),
+            format!("00-{}-{}", TRACE_ID, SPAN_ID),
+            String::new(),
+        ] {
+            assert_eq!(SpanContext::from_traceparent(value), None, "{}", value);
+        }
+    }
+}
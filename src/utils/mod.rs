@@ -1,21 +1,31 @@
+pub mod policy;
+pub mod revocation;
+pub mod secrets;
 pub mod token;
 
-use SETTINGS;
-use bcrypt;
+use crate::REUSABLE_TOKENS;
+use crate::metrics::METRICS;
+use crate::models::db::*;
+use crate::models::responses::access_token::{AccessTokenResponse, AccessTokenResponseBuilder};
+use crate::models::responses::introspection_err::{IntrospectionErrResponse,
+                                           IntrospectionErrResponseBuilder};
+use crate::models::responses::oauth2_error::OAuth2ErrorResponse;
+use crate::storage::{ClientStore, GrantTypeStore, StorageError, StorageResult, TokenStore};
+use crate::tracing::Span;
+use crate::utils::secrets::TokenKind;
 use chrono::Duration;
 use chrono::offset::Utc;
-use diesel;
-use diesel::pg::PgConnection;
-use diesel::prelude::*;
-use models::db::*;
-use models::responses::access_token::{AccessTokenResponse, AccessTokenResponseBuilder};
-use models::responses::introspection_err::{IntrospectionErrResponse,
-                                           IntrospectionErrResponseBuilder};
-use models::responses::oauth2_error::OAuth2ErrorResponse;
-use persistence::*;
 use std::ops::Add;
+use std::time::Instant;
 use uuid::Uuid;
 
+/// A newly issued token along with its plaintext. Only the digest of the
+/// plaintext is stored, so this is the one chance to send it to the client.
+pub struct Issued<T> {
+    pub token: String,
+    pub record: T,
+}
+
 /// Generates an IntrospectionErrResponse struct.
 ///
 /// Returns: IntrospectionErrResponse --- A standard error response struct when
@@ -32,30 +42,46 @@ pub fn introspection_error() -> IntrospectionErrResponse {
 ///
 /// Returns: Result<Client, OAuth2Error>
 /// - Ok(Client)       --- The client credentials are valid, and map to the
-/// resulting Client object. - Err(OAuth2Error) --- The Error value
-pub fn check_client_credentials<'a>(
-    conn: &PgConnection,
+///   resulting Client object.
+/// - Err(OAuth2Error) --- The Error value
+pub fn check_client_credentials<'a, S: ClientStore + ?Sized>(
+    store: &S,
     client_id: &'a str,
     client_secret: &'a str,
 ) -> Result<Client, OAuth2ErrorResponse> {
     trace!("Checking client credentials...");
+    let mut span = Span::start("client.authenticate");
+    span.set_attribute("oauth.client_id", client_id);
 
-    let opt_client: QueryResult<Client> = clients::table
-        .filter(clients::identifier.eq(client_id))
-        .first(conn);
+    let opt_client: StorageResult<Client> = store.find_client_by_identifier(client_id);
 
     trace!("Client result: {:?}", &opt_client);
 
-    let unverified_client = opt_client.map_err(|_| OAuth2ErrorResponse::InvalidClient)?;
+    let unverified_client = opt_client.map_err(|_| {
+        span.set_error("unknown client");
+        OAuth2ErrorResponse::InvalidClient
+    })?;
+    if unverified_client.disabled_at.is_some() {
+        span.set_error("disabled client");
+        return Err(OAuth2ErrorResponse::InvalidClient);
+    }
 
     // Check the hashed client_secret against the user provided secret + the
     // clients marked salt
-    let result_verified_client = bcrypt::verify(&client_secret, &unverified_client.secret);
+    let started = Instant::now();
+    let bcrypt_span = Span::start("bcrypt.verify");
+    let result_verified_client = bcrypt::verify(client_secret, &unverified_client.secret);
+    drop(bcrypt_span);
+    METRICS.bcrypt_seconds.observe(&[], started.elapsed());
     trace!(
         "Attempted to verify client. Underlying result is: {:?}",
         &result_verified_client
     );
-    result_verified_client.map_err(|_| OAuth2ErrorResponse::InvalidClient)?;
+    // A secret that does not match is not an error to bcrypt
+    if !result_verified_client.unwrap_or(false) {
+        span.set_error("invalid client secret");
+        return Err(OAuth2ErrorResponse::InvalidClient);
+    }
 
     Ok(unverified_client)
 }
@@ -65,46 +91,107 @@ pub fn check_client_credentials<'a>(
 /// Returns: Result<GrantType, OAuth2Error>
 /// - Ok(GrantType)    --- the grant type is valid, and supported.
 /// - Err(OAuth2Error) --- The Error value
-fn check_grant_type<'r>(
-    conn: &PgConnection,
-    grant_type: &'r str,
+fn check_grant_type<S: GrantTypeStore + ?Sized>(
+    store: &S,
+    grant_type: &str,
 ) -> Result<GrantType, OAuth2ErrorResponse> {
-    let opt: QueryResult<GrantType> = grant_types::table
-        .filter(grant_types::name.eq(grant_type))
-        .first(conn);
+    let opt: StorageResult<GrantType> = store.find_grant_type_by_name(grant_type);
 
     opt.map_err(|_| OAuth2ErrorResponse::InvalidGrant)
 }
 
-/// Validates a Refresh Token, ensuring the client owns the token.
+/// Looks up an Access Token by its plaintext. Malformed tokens are rejected
+/// without a lookup. Tokens issued before tokens were hashed are UUIDs, and
+/// are still found by their plaintext until they expire.
+///
+/// Returns: StorageResult<AccessToken>
+/// - Ok(AccessToken)   --- the token, which may have expired
+/// - Err(StorageError) --- the token does not exist, or the lookup failed
+pub fn find_access_token<S: TokenStore + ?Sized>(
+    store: &S,
+    token: &str,
+) -> StorageResult<AccessToken> {
+    if secrets::is_well_formed(token, TokenKind::Access) {
+        return store.find_access_token(&secrets::hash_token(token));
+    }
+
+    match Uuid::parse_str(token) {
+        Ok(legacy) => store.find_legacy_access_token(&legacy),
+        Err(_) => Err(StorageError::NotFound),
+    }
+}
+
+/// Validates a Refresh Token, ensuring the client owns the token and that it
+/// has not expired. Malformed tokens are rejected without a lookup. A token
+/// issued before tokens were hashed has its plaintext replaced by its digest
+/// on first use.
 ///
 /// Returns: Result<RefreshToken, OAuth2Error>
 /// - Ok(RefreshToken) --- the token itself, if valid
 /// - Err(OAuth2Error) --- The Error value
-fn check_refresh_token<'a>(
-    conn: &PgConnection,
+fn check_refresh_token<S: TokenStore + ?Sized>(
+    store: &S,
     client: &Client,
-    token: &'a str,
+    token: &str,
 ) -> Result<RefreshToken, OAuth2ErrorResponse> {
-    let refresh_token = Uuid::parse_str(&token).map_err(|_| OAuth2ErrorResponse::InvalidRequest)?;
+    let now = Utc::now().naive_utc();
+    let token_hash = secrets::hash_token(token);
+
+    if secrets::is_well_formed(token, TokenKind::Refresh) {
+        return store
+            .find_active_refresh_token(client.id, &token_hash, now)
+            .map_err(|_| OAuth2ErrorResponse::InvalidRequest);
+    }
 
-    let token = refresh_tokens::table
-        .filter(refresh_tokens::token.eq(refresh_token))
-        .filter(refresh_tokens::client_id.eq(client.id))
-        .order(refresh_tokens::issued_at.desc())
-        .first(conn);
+    let legacy = Uuid::parse_str(token).map_err(|_| OAuth2ErrorResponse::InvalidRequest)?;
+    let refresh_token = store
+        .find_active_legacy_refresh_token(client.id, &legacy, now)
+        .map_err(|_| OAuth2ErrorResponse::InvalidRequest)?;
 
-    token.map_err(|_| OAuth2ErrorResponse::InvalidRequest)
+    match store.hash_legacy_refresh_token(refresh_token.id, &token_hash) {
+        Ok(hashed) => Ok(hashed),
+        Err(e) => {
+            warn!("Unable to hash legacy refresh token {}: {:?}", refresh_token.id, e);
+            Ok(refresh_token)
+        }
+    }
+}
+
+/// Records a use of a Refresh Token, sliding its idle timeout forward. The
+/// idle timeout is the one that applies to the grant type being used now.
+///
+/// Returns: RefreshToken --- the token with its new expiry.
+pub fn touch_refresh_token<S: ClientStore + TokenStore + ?Sized>(
+    store: &S,
+    c: &Client,
+    g: &GrantType,
+    rt: &RefreshToken,
+) -> RefreshToken {
+    let overrides = find_token_lifetimes(store, c, g);
+    let idle_ttl = policy::refresh_token_ttl(&overrides, c, g, &rt.scope);
+    let expiry = policy::refresh_token_expiry(idle_ttl, &rt.scope, rt.absolute_expires_at);
+
+    store
+        .update_refresh_token_expiry(rt.id, Utc::now().naive_utc(), expiry)
+        .unwrap() // TODO: remove unwrap
+}
+
+/// Expires a Refresh Token immediately, so that it can no longer be used.
+/// This is how a token is retired once it has been rotated.
+pub fn expire_refresh_token<S: TokenStore + ?Sized>(store: &S, rt: &RefreshToken) {
+    let now = Utc::now().naive_utc();
+    let res = store.update_refresh_token_expiry(rt.id, now, Some(now));
+
+    res.unwrap(); // TODO: remove unwrap
 }
 
 /// Validates a Scope list.
 ///
 /// Returns: Result<String, OAuth2Error>
 /// - Ok(String)       --- The valid subset of scopes (i.e the scopes that
-/// appear in both the original request, and the
-/// existing token) - Err(OAuth2Error) --- The Error value
+///   appear in both the original request, and the existing token)
+/// - Err(OAuth2Error) --- The Error value
 fn check_scope<'a>(
-    _conn: &PgConnection,
     req: &'a str,
     prev: &'a str,
 ) -> Result<String, OAuth2ErrorResponse> {
@@ -112,7 +199,7 @@ fn check_scope<'a>(
     let request_scopes: Vec<&str> = req.split(' ').collect();
 
     for s in &request_scopes {
-        if !old_scopes.contains(&s) {
+        if !old_scopes.contains(s) {
             return Err(OAuth2ErrorResponse::InvalidScope);
         }
     }
@@ -120,66 +207,184 @@ fn check_scope<'a>(
     Ok(request_scopes.join(" "))
 }
 
-/// Generates an AccessToken.
+/// Compares two space delimited scope lists, ignoring ordering and repeats.
+///
+/// Returns: bool --- true if both lists grant exactly the same scopes.
+fn scopes_match(a: &str, b: &str) -> bool {
+    let mut a_scopes: Vec<&str> = a.split(' ').filter(|s| !s.is_empty()).collect();
+    let mut b_scopes: Vec<&str> = b.split(' ').filter(|s| !s.is_empty()).collect();
+    a_scopes.sort();
+    a_scopes.dedup();
+    b_scopes.sort();
+    b_scopes.dedup();
+    a_scopes == b_scopes
+}
+
+/// Looks for an AccessToken that can be handed out again instead of minting a
+/// new one. Only clients with a `token_reuse_min_ttl` are eligible, and only
+/// tokens issued for the same grant type and scope set that will live for at
+/// least that many more seconds are considered. As only digests are stored,
+/// a token can only be reused by the instance that issued it, and only until
+/// that instance restarts; see `cache::ReusableTokens`.
+///
+/// Returns: Option<Issued<AccessToken>>
+/// - Some(Issued) --- the most recently issued token matching the request
+/// - None         --- the client does not opt in, or nothing matched
+pub fn find_reusable_access_token<S: TokenStore + ?Sized>(
+    store: &S,
+    c: &Client,
+    g: &GrantType,
+    scope: &str,
+) -> Option<Issued<AccessToken>> {
+    let min_ttl = c.token_reuse_min_ttl?;
+    let cutoff = Utc::now()
+        .naive_utc()
+        .add(Duration::seconds(i64::from(min_ttl)));
+
+    // A client normally asks for the same handful of scope sets, so the most
+    // recent tokens are the only ones worth looking at.
+    let candidates = store.find_recent_access_tokens(c.id, g.id, cutoff, 20);
+
+    match candidates {
+        Ok(tokens) => tokens
+            .into_iter()
+            .filter(|t| scopes_match(&t.scope, scope))
+            .filter_map(|t| {
+                let plaintext = match t.token {
+                    Some(legacy) => Some(legacy.hyphenated().to_string()),
+                    None => REUSABLE_TOKENS.plaintext(t.id),
+                };
+                plaintext.map(|token| Issued {
+                    token,
+                    record: t,
+                })
+            })
+            .next(),
+        Err(e) => {
+            warn!("Unable to look up reusable access tokens: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Fetches every token lifetime override that could apply to a client and
+/// grant type.
+///
+/// Returns: Vec<TokenLifetime> --- the overrides, which may be empty.
+pub fn find_token_lifetimes<S: ClientStore + ?Sized>(
+    store: &S,
+    c: &Client,
+    g: &GrantType,
+) -> Vec<TokenLifetime> {
+    let res = store.find_token_lifetimes(c.id, g.id);
+
+    res.unwrap_or_else(|e| {
+        warn!("Unable to load token lifetime overrides: {:?}", e);
+        Vec::new()
+    })
+}
+
+/// Generates an AccessToken. `parent` is the refresh token it is issued
+/// with, or minted from, which it is revoked along with.
 ///
-/// Returns: AccessToken --- the AccessToken to send back to the caller
-pub fn generate_access_token(
-    conn: &PgConnection,
+/// Returns: Issued<AccessToken> --- the AccessToken to send back to the caller
+pub fn generate_access_token<S: ClientStore + TokenStore + ?Sized>(
+    store: &S,
     c: &Client,
     g: &GrantType,
     scope: &str,
-) -> AccessToken {
-    let token_ttl = SETTINGS.oauth.access_token_ttl;
+    parent: Option<&RefreshToken>,
+) -> Issued<AccessToken> {
+    let overrides = find_token_lifetimes(store, c, g);
+    let token_ttl = policy::access_token_ttl(&overrides, c, g, scope);
     let expiry = Utc::now().naive_utc().add(Duration::seconds(token_ttl));
 
+    let token = secrets::generate_token(TokenKind::Access);
     let new_token = NewAccessTokenBuilder::default()
+        .token_hash(secrets::hash_token(&token))
         .client_id(c.id)
         .grant_id(g.id)
-        .scope(scope.clone())
+        .scope(scope)
         .issued_at(Utc::now().naive_utc())
         .expires_at(expiry)
+        .refresh_token_id(parent.map(|p| p.id))
+        .auth_code_id(parent.and_then(|p| p.auth_code_id))
         .build()
         .unwrap(); // TODO: remove unwrap
 
-    let res = diesel::insert_into(access_tokens::table)
-        .values(&new_token)
-        .get_result::<AccessToken>(conn);
+    let res = store.create_access_token(&new_token);
+    let access_token = res.unwrap(); // TODO: remove unwrap
 
-    res.unwrap() // TODO: remove unwrap
+    if c.token_reuse_min_ttl.is_some() {
+        REUSABLE_TOKENS.remember(access_token.id, &token, access_token.expires_at);
+    }
+    METRICS
+        .tokens_issued
+        .inc(&["access", &g.name, &c.identifier]);
+    Issued {
+        token,
+        record: access_token,
+    }
 }
 
-/// Generates a Refresh Token.
+/// Generates a Refresh Token. When `parent` is given, the new token continues
+/// the parent's grant, so it inherits its absolute expiry and is revoked
+/// along with it.
 ///
-/// Returns: RefreshToken --- A refresh Token for the given client, allowing
-/// callers to generate a new access token using the
+/// Returns: Issued<RefreshToken> --- A refresh Token for the given client,
+/// allowing callers to generate a new access token using the
 /// stored scope.
-pub fn generate_refresh_token(conn: &PgConnection, c: &Client, s: &str) -> RefreshToken {
-    let token_ttl = SETTINGS.oauth.refresh_token_ttl;
-    let expiry = match token_ttl {
-        -1 => None,
-        val => Some(Utc::now().naive_utc().add(Duration::seconds(val))),
+pub fn generate_refresh_token<S: ClientStore + TokenStore + ?Sized>(
+    store: &S,
+    c: &Client,
+    g: &GrantType,
+    s: &str,
+    parent: Option<&RefreshToken>,
+) -> Issued<RefreshToken> {
+    let absolute_expiry = match parent {
+        Some(p) => p.absolute_expires_at,
+        None => policy::refresh_token_absolute_expiry(),
     };
+    let overrides = find_token_lifetimes(store, c, g);
+    let idle_ttl = policy::refresh_token_ttl(&overrides, c, g, s);
+    let expiry = policy::refresh_token_expiry(idle_ttl, s, absolute_expiry);
 
+    let token = secrets::generate_token(TokenKind::Refresh);
     let new_token = NewRefreshTokenBuilder::default()
+        .token_hash(secrets::hash_token(&token))
         .client_id(c.id)
-        .scope(s.clone())
+        .scope(s)
         .issued_at(Utc::now().naive_utc())
         .expires_at(expiry)
+        .absolute_expires_at(absolute_expiry)
+        .parent_id(parent.map(|p| p.id))
+        .auth_code_id(parent.and_then(|p| p.auth_code_id))
         .build()
         .unwrap(); // TODO: remove unwrap
 
-    diesel::insert_into(refresh_tokens::table)
-        .values(&new_token)
-        .get_result::<RefreshToken>(conn)
-        .unwrap() // TODO: remove unwrap
+    let refresh_token = store
+        .create_refresh_token(&new_token)
+        .unwrap(); // TODO: remove unwrap
+    METRICS
+        .tokens_issued
+        .inc(&["refresh", &g.name, &c.identifier]);
+
+    Issued {
+        token,
+        record: refresh_token,
+    }
 }
 
 /// Generates an AccessTokenResponse.
 ///
 /// Returns: AccessTokenResponse --- the access token response object that
 /// should be sent to the caller.
-pub fn generate_token_response(at: AccessToken, rt: Option<RefreshToken>) -> AccessTokenResponse {
-    let access_token = at.token.hyphenated().to_string();
+pub fn generate_token_response(
+    at: Issued<AccessToken>,
+    rt: Option<Issued<RefreshToken>>,
+) -> AccessTokenResponse {
+    let access_token = at.token;
+    let at = at.record;
     let mut builder = AccessTokenResponseBuilder::default();
 
     builder
@@ -194,8 +399,8 @@ pub fn generate_token_response(at: AccessToken, rt: Option<RefreshToken>) -> Acc
 
     match rt {
         Some(refresh_token) => {
-            builder.refresh_token(refresh_token.token.hyphenated().to_string());
-            match refresh_token.expires_at {
+            builder.refresh_token(refresh_token.token);
+            match refresh_token.record.expires_at {
                 Some(expiry) => builder.refresh_expires_in(Some(
                     expiry
                         .signed_duration_since(Utc::now().naive_utc())
@@ -210,9 +415,93 @@ pub fn generate_token_response(at: AccessToken, rt: Option<RefreshToken>) -> Acc
     builder.build().unwrap() // TODO: remove unwrap
 }
 
-pub fn get_grant_type_by_name(conn: &PgConnection, name: &str) -> GrantType {
-    grant_types::table
-        .filter(grant_types::name.eq(name))
-        .first(conn)
+pub fn get_grant_type_by_name<S: GrantTypeStore + ?Sized>(store: &S, name: &str) -> GrantType {
+    store
+        .find_grant_type_by_name(name)
         .unwrap() // TODO: remove unwrap
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStore;
+
+    // The in-memory tables are shared by every test, so each test uses a
+    // client id no other test does. Clients are not looked up here.
+    fn client(id: i32, token_reuse_min_ttl: Option<i32>) -> Client {
+        Client {
+            id,
+            identifier: format!("reuse-{}", id),
+            secret: String::new(),
+            response_type: "confidential".to_owned(),
+            token_reuse_min_ttl,
+            issue_refresh_tokens: Some(false),
+            rotate_refresh_tokens: None,
+            disabled_at: None,
+        }
+    }
+
+    fn issue(store: &MemoryStore, c: &Client, g: &GrantType, scope: &str, expires_in: i64) -> i32 {
+        let token = secrets::generate_token(TokenKind::Access);
+        let now = Utc::now().naive_utc();
+        let record = store
+            .create_access_token(&NewAccessToken {
+                token_hash: secrets::hash_token(&token),
+                client_id: c.id,
+                grant_id: g.id,
+                scope: scope.to_owned(),
+                issued_at: now,
+                expires_at: now.add(Duration::seconds(expires_in)),
+                refresh_token_id: None,
+                auth_code_id: None,
+            })
+            .unwrap();
+        REUSABLE_TOKENS.remember(record.id, &token, record.expires_at);
+        record.id
+    }
+
+    fn reused(store: &MemoryStore, c: &Client, g: &GrantType, scope: &str) -> Option<i32> {
+        find_reusable_access_token(store, c, g, scope).map(|issued| issued.record.id)
+    }
+
+    #[test]
+    fn reuses_tokens_with_at_least_the_minimum_life_left() {
+        let store = MemoryStore::connect().unwrap();
+        let g = store.find_grant_type_by_name("client_credentials").unwrap();
+        let c = client(-2701, Some(300));
+
+        issue(&store, &c, &g, "read", 290);
+        assert_eq!(reused(&store, &c, &g, "read"), None);
+
+        let id = issue(&store, &c, &g, "read", 310);
+        let issued = find_reusable_access_token(&store, &c, &g, "read").unwrap();
+        assert_eq!(issued.record.id, id);
+        assert!(secrets::is_well_formed(&issued.token, TokenKind::Access));
+    }
+
+    #[test]
+    fn reuses_tokens_for_the_same_scope_set_only() {
+        let store = MemoryStore::connect().unwrap();
+        let g = store.find_grant_type_by_name("client_credentials").unwrap();
+        let c = client(-2702, Some(60));
+
+        let id = issue(&store, &c, &g, "read write", 600);
+        assert_eq!(reused(&store, &c, &g, "write read read"), Some(id));
+        assert_eq!(reused(&store, &c, &g, "read"), None);
+        assert_eq!(reused(&store, &c, &g, "read write admin"), None);
+    }
+
+    #[test]
+    fn does_not_reuse_revoked_tokens_or_for_clients_that_do_not_opt_in() {
+        let store = MemoryStore::connect().unwrap();
+        let g = store.find_grant_type_by_name("client_credentials").unwrap();
+        let c = client(-2703, Some(60));
+
+        let id = issue(&store, &c, &g, "read", 600);
+        assert_eq!(reused(&store, &client(-2703, None), &g, "read"), None);
+        store
+            .revoke_access_tokens(&[id], Utc::now().naive_utc())
+            .unwrap();
+        assert_eq!(reused(&store, &c, &g, "read"), None);
+    }
+}
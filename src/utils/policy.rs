@@ -0,0 +1,371 @@
+//! The utils::policy module answers the configurable questions about how a
+//! grant should be processed. Global defaults live in the `[oauth]` section of
+//! the config file, and individual clients may override them through their
+//! nullable policy columns, where `NULL` means "use the global setting".
+
+use crate::models::db::{Client, GrantType, TokenLifetime};
+use crate::models::responses::oauth2_error::OAuth2ErrorResponse;
+use crate::settings;
+use chrono::offset::Utc;
+use chrono::{Duration, NaiveDateTime};
+use std::cmp;
+use std::ops::Add;
+
+/// The scope that must be granted before a refresh token is allowed to live
+/// forever. See OpenID Connect Core (11).
+pub const OFFLINE_ACCESS_SCOPE: &str = "offline_access";
+
+/// Decides whether a successful request for the given grant type should
+/// include a refresh token in its response.
+///
+/// Returns: bool --- true if a refresh token should be issued.
+pub fn issues_refresh_token(c: &Client, grant_type: &str) -> bool {
+    match c.issue_refresh_tokens {
+        Some(issue) => issue,
+        None => settings::current()
+            .oauth
+            .refresh_policy
+            .issue_for_grants
+            .iter()
+            .any(|g| g == grant_type),
+    }
+}
+
+/// Decides whether a `refresh_token` request should be answered with a brand
+/// new refresh token, retiring the one that was presented.
+///
+/// Returns: bool --- true if the refresh token should be rotated.
+pub fn rotates_refresh_token(c: &Client) -> bool {
+    match c.rotate_refresh_tokens {
+        Some(rotate) => rotate,
+        None => settings::current().oauth.refresh_policy.rotate_on_refresh,
+    }
+}
+
+/// Works out the scope to validate for a `refresh_token` request. RFC 6749
+/// (6) makes the parameter optional, meaning the originally granted scope, but
+/// this can be turned off for deployments that want clients to be explicit.
+///
+/// Returns: Result<String, OAuth2Error>
+/// - Ok(String)       --- the scope that should be checked against the grant
+/// - Err(OAuth2Error) --- the scope was omitted and that is not allowed
+pub fn refresh_scope(
+    requested: Option<String>,
+    original: &str,
+) -> Result<String, OAuth2ErrorResponse> {
+    match requested {
+        Some(scope) => Ok(scope),
+        None if settings::current().oauth.refresh_policy.scope_defaults_to_original => {
+            Ok(original.to_owned())
+        }
+        None => Err(OAuth2ErrorResponse::InvalidRequest),
+    }
+}
+
+/// Works out the absolute expiry of a brand new grant, which no refresh token
+/// descended from it may outlive, however often it is used.
+///
+/// Returns: Option<NaiveDateTime> --- the end of the grant, or None when
+/// `refresh_token_max_lifetime` is -1.
+pub fn refresh_token_absolute_expiry() -> Option<NaiveDateTime> {
+    match settings::current().oauth.refresh_token_max_lifetime {
+        -1 => None,
+        val => Some(Utc::now().naive_utc().add(Duration::seconds(val))),
+    }
+}
+
+/// Works out when a refresh token should expire if it is not used again,
+/// starting from now, given its idle timeout (see `refresh_token_ttl`). The
+/// idle timeout slides forward on every use, but is always capped by the
+/// absolute expiry of the grant. A token that would otherwise never expire is
+/// only allowed when `offline_access` was granted; without it,
+/// `online_refresh_token_ttl` is used as the idle timeout instead.
+///
+/// Returns: Option<NaiveDateTime> --- the expiry, or None if it never expires.
+pub fn refresh_token_expiry(
+    idle_ttl: i64,
+    scope: &str,
+    absolute_expires_at: Option<NaiveDateTime>,
+) -> Option<NaiveDateTime> {
+    let now = Utc::now().naive_utc();
+    let idle_expiry = match idle_ttl {
+        -1 => None,
+        val => Some(now.add(Duration::seconds(val))),
+    };
+
+    let expiry = match (idle_expiry, absolute_expires_at) {
+        (Some(idle), Some(absolute)) => Some(cmp::min(idle, absolute)),
+        (idle, absolute) => idle.or(absolute),
+    };
+
+    let offline = scope.split(' ').any(|s| s == OFFLINE_ACCESS_SCOPE);
+    match expiry {
+        None if !offline => Some(now.add(Duration::seconds(
+            settings::current().oauth.online_refresh_token_ttl,
+        ))),
+        expiry => expiry,
+    }
+}
+
+/// Works out the lifetime of an access token issued to a client for a grant
+/// type and scope, taking the `token_lifetimes` overrides into account.
+///
+/// Returns: i64 --- the lifetime of the token, in seconds.
+pub fn access_token_ttl(
+    overrides: &[TokenLifetime],
+    c: &Client,
+    g: &GrantType,
+    scope: &str,
+) -> i64 {
+    resolve_ttl(
+        overrides,
+        c,
+        g,
+        scope,
+        settings::current().oauth.access_token_ttl,
+        access_override,
+    )
+}
+
+// Access tokens always expire, so an override that would make them never
+// expire (or expire on issue) is ignored, should one reach the table anyway.
+fn access_override(o: &TokenLifetime) -> Option<i32> {
+    o.access_token_ttl.filter(|&t| t > 0)
+}
+
+/// Works out the idle timeout of a refresh token issued to a client for a
+/// grant type and scope, taking the `token_lifetimes` overrides into account.
+///
+/// Returns: i64 --- the idle timeout in seconds, or -1 for none.
+pub fn refresh_token_ttl(
+    overrides: &[TokenLifetime],
+    c: &Client,
+    g: &GrantType,
+    scope: &str,
+) -> i64 {
+    resolve_ttl(
+        overrides,
+        c,
+        g,
+        scope,
+        settings::current().oauth.refresh_token_ttl,
+        |o| o.refresh_token_ttl,
+    )
+}
+
+// Overrides without a scope are ranked by how specific they are: one naming
+// both the client and the grant type beats one naming only the client, which
+// beats one naming only the grant type, which beats the config file. Overrides
+// for a scope are then applied on top, and the shortest lifetime of any scope
+// in the request wins, so that granting a sensitive scope can only ever
+// shorten a token's life.
+fn resolve_ttl<F>(
+    overrides: &[TokenLifetime],
+    c: &Client,
+    g: &GrantType,
+    scope: &str,
+    default: i64,
+    ttl: F,
+) -> i64
+where
+    F: Fn(&TokenLifetime) -> Option<i32>,
+{
+    let scopes: Vec<&str> = scope.split(' ').collect();
+    let applicable: Vec<&TokenLifetime> = overrides
+        .iter()
+        .filter(|o| o.client_id.map_or(true, |id| id == c.id))
+        .filter(|o| o.grant_id.map_or(true, |id| id == g.id))
+        .collect();
+
+    let base = applicable
+        .iter()
+        .filter(|o| o.scope.is_none())
+        .filter_map(|o| ttl(o).map(|t| (specificity(o), t)))
+        .max_by_key(|&(rank, _)| rank)
+        .map_or(default, |(_, t)| i64::from(t));
+
+    applicable
+        .iter()
+        .filter(|o| match o.scope {
+            Some(ref s) => scopes.contains(&s.as_str()),
+            None => false,
+        })
+        .filter_map(|o| ttl(o))
+        .fold(base, |acc, t| shortest(acc, i64::from(t)))
+}
+
+fn specificity(o: &TokenLifetime) -> u8 {
+    let mut rank = 0;
+    if o.client_id.is_some() {
+        rank += 2;
+    }
+    if o.grant_id.is_some() {
+        rank += 1;
+    }
+    rank
+}
+
+// -1 means "never expires", so it loses to any real lifetime.
+fn shortest(a: i64, b: i64) -> i64 {
+    match (a, b) {
+        (-1, b) => b,
+        (a, -1) => a,
+        (a, b) => cmp::min(a, b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT: i64 = 3600;
+
+    fn client(id: i32) -> Client {
+        Client {
+            id,
+            identifier: format!("client-{}", id),
+            secret: String::new(),
+            response_type: "token".to_owned(),
+            token_reuse_min_ttl: None,
+            issue_refresh_tokens: None,
+            rotate_refresh_tokens: None,
+            disabled_at: None,
+        }
+    }
+
+    fn grant(id: i32) -> GrantType {
+        GrantType {
+            id,
+            name: format!("grant-{}", id),
+        }
+    }
+
+    fn lifetime(
+        client_id: Option<i32>,
+        grant_id: Option<i32>,
+        scope: Option<&str>,
+        ttl: i32,
+    ) -> TokenLifetime {
+        TokenLifetime {
+            id: 0,
+            client_id,
+            grant_id,
+            scope: scope.map(str::to_owned),
+            access_token_ttl: Some(ttl),
+            refresh_token_ttl: None,
+        }
+    }
+
+    fn access_ttl(overrides: &[TokenLifetime], scope: &str) -> i64 {
+        resolve_ttl(
+            overrides,
+            &client(1),
+            &grant(1),
+            scope,
+            DEFAULT,
+            access_override,
+        )
+    }
+
+    fn refresh_ttl(overrides: &[TokenLifetime], scope: &str) -> i64 {
+        resolve_ttl(overrides, &client(1), &grant(1), scope, DEFAULT, |o| {
+            o.refresh_token_ttl
+        })
+    }
+
+    fn refresh_lifetime(scope: Option<&str>, ttl: i32) -> TokenLifetime {
+        let mut o = lifetime(Some(1), Some(1), scope, 0);
+        o.access_token_ttl = None;
+        o.refresh_token_ttl = Some(ttl);
+        o
+    }
+
+    #[test]
+    fn uses_the_default_without_overrides() {
+        assert_eq!(access_ttl(&[], "read"), DEFAULT);
+    }
+
+    #[test]
+    fn prefers_the_most_specific_override() {
+        let grant_only = lifetime(None, Some(1), None, 300);
+        let client_only = lifetime(Some(1), None, None, 200);
+        let both = lifetime(Some(1), Some(1), None, 100);
+
+        assert_eq!(access_ttl(std::slice::from_ref(&grant_only), "read"), 300);
+        assert_eq!(
+            access_ttl(&[grant_only.clone(), client_only.clone()], "read"),
+            200
+        );
+        assert_eq!(access_ttl(&[client_only, both, grant_only], "read"), 100);
+    }
+
+    #[test]
+    fn ignores_overrides_for_other_clients_and_grants() {
+        let overrides = [
+            lifetime(Some(2), None, None, 100),
+            lifetime(None, Some(2), None, 200),
+            lifetime(Some(1), Some(2), None, 300),
+            lifetime(Some(2), None, Some("read"), 400),
+        ];
+        assert_eq!(access_ttl(&overrides, "read"), DEFAULT);
+    }
+
+    #[test]
+    fn ignores_overrides_without_the_lifetime_asked_for() {
+        let mut refresh_only = lifetime(Some(1), Some(1), None, 100);
+        refresh_only.access_token_ttl = None;
+        refresh_only.refresh_token_ttl = Some(100);
+        assert_eq!(
+            access_ttl(&[refresh_only, lifetime(Some(1), None, None, 200)], "read"),
+            200
+        );
+    }
+
+    #[test]
+    fn scope_overrides_can_only_shorten_the_lifetime() {
+        let overrides = [
+            lifetime(Some(1), None, None, 600),
+            lifetime(None, None, Some("admin"), 60),
+            lifetime(Some(1), None, Some("write"), 120),
+            lifetime(None, None, Some("read"), 7200),
+        ];
+        assert_eq!(access_ttl(&overrides, "read"), 600);
+        assert_eq!(access_ttl(&overrides, "read write"), 120);
+        assert_eq!(access_ttl(&overrides, "write admin read"), 60);
+        assert_eq!(access_ttl(&overrides, "other"), 600);
+    }
+
+    #[test]
+    fn never_expiring_loses_to_any_lifetime() {
+        assert_eq!(shortest(-1, 60), 60);
+        assert_eq!(shortest(60, -1), 60);
+        assert_eq!(shortest(-1, -1), -1);
+
+        let never = refresh_lifetime(None, -1);
+        assert_eq!(refresh_ttl(std::slice::from_ref(&never), "read"), -1);
+        assert_eq!(
+            refresh_ttl(&[never, refresh_lifetime(Some("read"), 60)], "read"),
+            60
+        );
+    }
+
+    #[test]
+    fn refuses_access_tokens_that_never_expire() {
+        let never = lifetime(Some(1), Some(1), None, -1);
+        assert_eq!(access_ttl(std::slice::from_ref(&never), "read"), DEFAULT);
+        assert_eq!(
+            access_ttl(&[never, lifetime(None, None, Some("read"), 0)], "read"),
+            DEFAULT
+        );
+        assert_eq!(
+            access_ttl(
+                &[
+                    lifetime(Some(1), None, None, 600),
+                    lifetime(Some(1), Some(1), None, -1)
+                ],
+                "read"
+            ),
+            600
+        );
+    }
+}
@@ -0,0 +1,768 @@
+//! The utils::revocation module revokes tokens along with everything derived
+//! from them. Tokens form a tree: an authorization code is the root of every
+//! token issued for it, a rotated refresh token is the parent of its
+//! replacement, and an access token is the child of the refresh token it was
+//! issued with or minted from. Revoking any node revokes its descendants, so
+//! revoking the root of a grant kills the whole grant.
+//!
+//! Tokens can also be revoked in bulk, by client, scope and issue time or all
+//! at once, for when a client or a whole deployment is compromised, and a
+//! compromised client can be disabled along with all of its tokens. Every
+//! revocation is recorded in the `token_revocations` audit trail, which is
+//! also how other instances learn that their introspection cache is stale.
+
+use crate::INTROSPECTION_CACHE;
+use crate::REUSABLE_TOKENS;
+use crate::metrics::METRICS;
+use crate::models::db::{Client, NewRevocationBuilder};
+use crate::storage::{
+    ClientStore, CodeStore, RevocationStore, StorageError, StorageResult, TokenFilter, TokenStore,
+};
+use crate::utils::secrets::{self, TokenKind};
+use chrono::offset::Utc;
+use chrono::{DateTime, NaiveDateTime};
+use std::collections::HashSet;
+use std::fmt;
+
+// Ids are handed to the backend in chunks of this size, which keeps queries
+// well below the bound parameter limits of SQLite and MySQL.
+const BATCH_SIZE: usize = 500;
+
+/// What a revocation touched. Tokens that were already revoked are not
+/// counted.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Revoked {
+    pub access_tokens: usize,
+    pub refresh_tokens: usize,
+}
+
+/// Who is revoking tokens and why, for the audit trail.
+#[derive(Clone, Debug)]
+pub struct Audit {
+    pub actor: String,
+    pub reason: Option<String>,
+}
+
+/// Which tokens a bulk revocation applies to. Every criterion that is set
+/// must match. Revoking every token takes `all`, rather than leaving out
+/// every criterion, so that a forgotten argument never does it by accident.
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    /// The identifier of the client the tokens were issued to.
+    pub client: Option<String>,
+    /// A single scope the tokens must carry, among any others.
+    pub scope: Option<String>,
+    pub issued_after: Option<NaiveDateTime>,
+    pub issued_before: Option<NaiveDateTime>,
+    pub all: bool,
+}
+
+impl Selection {
+    fn has_criteria(&self) -> bool {
+        self.client.is_some()
+            || self.scope.is_some()
+            || self.issued_after.is_some()
+            || self.issued_before.is_some()
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.all {
+            return write!(f, "all");
+        }
+
+        let mut criteria = Vec::new();
+        if let Some(ref client) = self.client {
+            criteria.push(format!("client={}", client));
+        }
+        if let Some(ref scope) = self.scope {
+            criteria.push(format!("scope={}", scope));
+        }
+        if let Some(issued_after) = self.issued_after {
+            criteria.push(format!("issued_after={}", format_time(issued_after)));
+        }
+        if let Some(issued_before) = self.issued_before {
+            criteria.push(format!("issued_before={}", format_time(issued_before)));
+        }
+        write!(f, "{}", criteria.join(" "))
+    }
+}
+
+#[derive(Debug)]
+pub enum RevocationError {
+    /// The selection is incomplete or contradictory, or names a client that
+    /// does not exist.
+    InvalidSelection(String),
+    Storage(StorageError),
+}
+
+impl fmt::Display for RevocationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RevocationError::InvalidSelection(ref msg) => write!(f, "{}", msg),
+            RevocationError::Storage(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<StorageError> for RevocationError {
+    fn from(e: StorageError) -> RevocationError {
+        RevocationError::Storage(e)
+    }
+}
+
+/// Parses the RFC 3339 times bulk revocations are limited by, e.g.
+/// `2018-03-01T12:00:00Z`.
+///
+/// Returns: Result<NaiveDateTime, String>
+/// - Ok(NaiveDateTime)  --- the time in UTC
+/// - Err(String)        --- a message describing the malformed time
+pub fn parse_time(value: &str) -> Result<NaiveDateTime, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.naive_utc())
+        .map_err(|_| {
+            format!(
+                "`{}` is not an RFC 3339 time, e.g. 2018-03-01T12:00:00Z",
+                value
+            )
+        })
+}
+
+fn format_time(time: NaiveDateTime) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Revokes a single access token. Access tokens have no descendants.
+pub fn revoke_access_token<S>(store: &S, id: i32, audit: &Audit) -> StorageResult<Revoked>
+where
+    S: TokenStore + RevocationStore + ?Sized,
+{
+    revoke_tree(store, vec![id], Vec::new(), &format!("access_token={}", id), audit)
+}
+
+/// Revokes a refresh token, the refresh tokens that replaced it, and every
+/// access token issued with or minted from any of them.
+pub fn revoke_refresh_token<S>(store: &S, id: i32, audit: &Audit) -> StorageResult<Revoked>
+where
+    S: TokenStore + RevocationStore + ?Sized,
+{
+    revoke_tree(store, Vec::new(), vec![id], &format!("refresh_token={}", id), audit)
+}
+
+/// Revokes every token derived from an authorization code, and deletes the
+/// code so that it can no longer be exchanged.
+pub fn revoke_auth_code<S>(store: &S, auth_code_id: i32, audit: &Audit) -> StorageResult<Revoked>
+where
+    S: TokenStore + CodeStore + RevocationStore + ?Sized,
+{
+    // Codes are deleted once exchanged, so the code itself may well be gone.
+    match store.delete_auth_code(auth_code_id) {
+        Ok(()) | Err(StorageError::NotFound) => (),
+        Err(e) => return Err(e),
+    }
+    let access_ids = store.find_access_token_ids_by_auth_code(auth_code_id)?;
+    let refresh_ids = store.find_refresh_token_ids_by_auth_code(auth_code_id)?;
+    revoke_tree(
+        store,
+        access_ids,
+        refresh_ids,
+        &format!("auth_code={}", auth_code_id),
+        audit,
+    )
+}
+
+/// Revokes a token given the token itself, as a client holds it or a leak
+/// exposed it, along with everything derived from it. The token is looked up
+/// by its digest, like the token and introspection endpoints do.
+///
+/// Returns: Result<Revoked, RevocationError>
+/// - Ok(Revoked)              --- the tokens revoked
+/// - Err(InvalidSelection)    --- the token is malformed, or was never issued
+/// - Err(Storage)             --- the backend failed
+pub fn revoke_token<S>(store: &S, token: &str, audit: &Audit) -> Result<Revoked, RevocationError>
+where
+    S: TokenStore + RevocationStore + ?Sized,
+{
+    let digest = secrets::hash_token(token);
+    if secrets::is_well_formed(token, TokenKind::Refresh) {
+        let refresh_token = issued(store.find_refresh_token(&digest))?;
+        Ok(revoke_refresh_token(store, refresh_token.id, audit)?)
+    } else if secrets::is_well_formed(token, TokenKind::Access) {
+        let access_token = issued(store.find_access_token(&digest))?;
+        Ok(revoke_access_token(store, access_token.id, audit)?)
+    } else {
+        Err(RevocationError::InvalidSelection(
+            "not a token issued by this provider".to_owned(),
+        ))
+    }
+}
+
+fn issued<T>(found: StorageResult<T>) -> Result<T, RevocationError> {
+    match found {
+        Ok(token) => Ok(token),
+        Err(StorageError::NotFound) => Err(RevocationError::InvalidSelection(
+            "the token was never issued, or has been pruned".to_owned(),
+        )),
+        Err(e) => Err(RevocationError::Storage(e)),
+    }
+}
+
+/// Revokes every live token matching the selection, along with everything
+/// derived from them. With `dry_run`, only counts the tokens that would be
+/// revoked.
+///
+/// Returns: Result<Revoked, RevocationError>
+/// - Ok(Revoked)              --- the tokens revoked, or that would be
+/// - Err(InvalidSelection)    --- the selection cannot be acted on
+/// - Err(Storage)             --- the backend failed, possibly part way through
+pub fn revoke_matching<S>(
+    store: &S,
+    selection: &Selection,
+    audit: &Audit,
+    dry_run: bool,
+) -> Result<Revoked, RevocationError>
+where
+    S: ClientStore + TokenStore + RevocationStore + ?Sized,
+{
+    let filter = check_selection(store, selection)?;
+    revoke_filtered(store, selection, &filter, &selection.to_string(), audit, dry_run)
+}
+
+/// Disables a client and revokes every token it holds, so that it can no
+/// longer authenticate and none of its tokens introspect as active. The
+/// revocation is audited like any other, which is how other instances learn
+/// to drop the client's tokens from their introspection caches.
+///
+/// Returns: Result<Revoked, RevocationError>
+/// - Ok(Revoked)              --- the tokens revoked
+/// - Err(InvalidSelection)    --- there is no client with that identifier
+/// - Err(Storage)             --- the backend failed, possibly part way through
+pub fn disable_client<S>(store: &S, identifier: &str, audit: &Audit) -> Result<Revoked, RevocationError>
+where
+    S: ClientStore + TokenStore + RevocationStore + ?Sized,
+{
+    let selection = Selection {
+        client: Some(identifier.to_owned()),
+        ..Selection::default()
+    };
+    let filter = check_selection(store, &selection)?;
+    let client = find_client(store, identifier)?;
+
+    // The client is disabled first, so that it cannot be issued new tokens
+    // while the old ones are revoked.
+    store.set_client_disabled(client.id, Some(filter.live_at))?;
+    let description = format!("disable {}", selection);
+    revoke_filtered(store, &selection, &filter, &description, audit, false)
+}
+
+/// Enables a disabled client again. Tokens revoked when it was disabled stay
+/// revoked.
+///
+/// Returns: Result<Client, RevocationError>
+/// - Ok(Client)               --- the client, now enabled
+/// - Err(InvalidSelection)    --- there is no client with that identifier
+/// - Err(Storage)             --- the backend failed
+pub fn enable_client<S>(store: &S, identifier: &str, audit: &Audit) -> Result<Client, RevocationError>
+where
+    S: ClientStore + ?Sized,
+{
+    let client = find_client(store, identifier)?;
+    let client = store.set_client_disabled(client.id, None)?;
+    info!(
+        "{} enabled client {}, reason: {}",
+        audit.actor,
+        identifier,
+        audit.reason.as_ref().map_or("none given", |r| r.as_str())
+    );
+    Ok(client)
+}
+
+fn revoke_filtered<S>(
+    store: &S,
+    selection: &Selection,
+    filter: &TokenFilter,
+    description: &str,
+    audit: &Audit,
+    dry_run: bool,
+) -> Result<Revoked, RevocationError>
+where
+    S: TokenStore + RevocationStore + ?Sized,
+{
+    let mut tally = Tally::new(dry_run);
+    let result = revoke_pages(store, selection, filter, &mut tally);
+    if dry_run {
+        result?;
+        return Ok(tally.revoked);
+    }
+
+    // Whatever was revoked before a failure is audited all the same. Every
+    // token a selection by client matches belongs to that client.
+    match filter.client_id {
+        Some(client_id) => INTROSPECTION_CACHE.invalidate_client(client_id),
+        None => INTROSPECTION_CACHE.clear(),
+    }
+    let description = match result {
+        Ok(()) => description.to_owned(),
+        Err(_) => format!("{} (incomplete)", description),
+    };
+    let recorded = record(store, &description, audit, &tally.revoked);
+    result?;
+    recorded?;
+    Ok(tally.revoked)
+}
+
+fn check_selection<S>(store: &S, selection: &Selection) -> Result<TokenFilter, RevocationError>
+where
+    S: ClientStore + ?Sized,
+{
+    if selection.all && selection.has_criteria() {
+        return Err(RevocationError::InvalidSelection(
+            "revoking all tokens cannot be combined with other criteria".to_owned(),
+        ));
+    }
+    if !selection.all && !selection.has_criteria() {
+        return Err(RevocationError::InvalidSelection(
+            "select tokens by client, scope or issue time, or revoke all tokens explicitly"
+                .to_owned(),
+        ));
+    }
+    if let Some(ref scope) = selection.scope {
+        if scope.is_empty() || scope.contains(' ') {
+            return Err(RevocationError::InvalidSelection(
+                "tokens can only be selected by a single scope".to_owned(),
+            ));
+        }
+    }
+    if let (Some(after), Some(before)) = (selection.issued_after, selection.issued_before) {
+        if after >= before {
+            return Err(RevocationError::InvalidSelection(
+                "the issue time window is empty".to_owned(),
+            ));
+        }
+    }
+
+    let client_id = match selection.client {
+        Some(ref identifier) => Some(find_client(store, identifier)?.id),
+        None => None,
+    };
+
+    Ok(TokenFilter {
+        client_id,
+        issued_after: selection.issued_after,
+        issued_before: selection.issued_before,
+        live_at: Utc::now().naive_utc(),
+    })
+}
+
+fn find_client<S>(store: &S, identifier: &str) -> Result<Client, RevocationError>
+where
+    S: ClientStore + ?Sized,
+{
+    match store.find_client_by_identifier(identifier) {
+        Ok(client) => Ok(client),
+        Err(StorageError::NotFound) => Err(RevocationError::InvalidSelection(format!(
+            "no client with identifier `{}`",
+            identifier
+        ))),
+        Err(e) => Err(RevocationError::Storage(e)),
+    }
+}
+
+// Works through the matching tokens a page at a time. Refresh tokens go
+// first, so that the access tokens belonging to them are revoked with them.
+fn revoke_pages<S>(
+    store: &S,
+    selection: &Selection,
+    filter: &TokenFilter,
+    tally: &mut Tally,
+) -> StorageResult<()>
+where
+    S: TokenStore + RevocationStore + ?Sized,
+{
+    let scope = selection.scope.as_deref();
+
+    let mut after_id = 0;
+    loop {
+        let page = store.find_revocable_refresh_tokens(filter, after_id, BATCH_SIZE as i64)?;
+        let (ids, last_id) = match select_page(page, scope) {
+            Some(selected) => selected,
+            None => break,
+        };
+        let (access_ids, refresh_ids) = with_descendants(store, Vec::new(), ids)?;
+        tally.add(store, access_ids, refresh_ids)?;
+        after_id = last_id;
+    }
+
+    let mut after_id = 0;
+    loop {
+        let page = store.find_revocable_access_tokens(filter, after_id, BATCH_SIZE as i64)?;
+        let (ids, last_id) = match select_page(page, scope) {
+            Some(selected) => selected,
+            None => break,
+        };
+        tally.add(store, ids, Vec::new())?;
+        after_id = last_id;
+    }
+    Ok(())
+}
+
+// Keeps the tokens in a page that carry the scope, if any. Returns None once
+// there are no pages left.
+fn select_page(page: Vec<(i32, String)>, scope: Option<&str>) -> Option<(Vec<i32>, i32)> {
+    let last_id = match page.last() {
+        Some(&(id, _)) => id,
+        None => return None,
+    };
+    let ids = page
+        .into_iter()
+        .filter(|(_, token_scope)| {
+            scope.map_or(true, |s| token_scope.split(' ').any(|t| t == s))
+        })
+        .map(|(id, _)| id)
+        .collect();
+    Some((ids, last_id))
+}
+
+// Counts, or revokes, each token once, however many pages it turns up in.
+struct Tally {
+    dry_run: bool,
+    seen_access: HashSet<i32>,
+    seen_refresh: HashSet<i32>,
+    revoked: Revoked,
+}
+
+impl Tally {
+    fn new(dry_run: bool) -> Tally {
+        Tally {
+            dry_run,
+            seen_access: HashSet::new(),
+            seen_refresh: HashSet::new(),
+            revoked: Revoked::default(),
+        }
+    }
+
+    fn add<S>(
+        &mut self,
+        store: &S,
+        access_ids: Vec<i32>,
+        refresh_ids: Vec<i32>,
+    ) -> StorageResult<()>
+    where
+        S: TokenStore + ?Sized,
+    {
+        let seen_access = &mut self.seen_access;
+        let seen_refresh = &mut self.seen_refresh;
+        let access_ids: Vec<i32> = access_ids
+            .into_iter()
+            .filter(|id| seen_access.insert(*id))
+            .collect();
+        let refresh_ids: Vec<i32> = refresh_ids
+            .into_iter()
+            .filter(|id| seen_refresh.insert(*id))
+            .collect();
+
+        if self.dry_run {
+            self.revoked.access_tokens += access_ids.len();
+            self.revoked.refresh_tokens += refresh_ids.len();
+        } else {
+            let revoked = apply(store, &access_ids, &refresh_ids)?;
+            self.revoked.access_tokens += revoked.access_tokens;
+            self.revoked.refresh_tokens += revoked.refresh_tokens;
+        }
+        Ok(())
+    }
+}
+
+fn revoke_tree<S>(
+    store: &S,
+    access_ids: Vec<i32>,
+    refresh_ids: Vec<i32>,
+    selection: &str,
+    audit: &Audit,
+) -> StorageResult<Revoked>
+where
+    S: TokenStore + RevocationStore + ?Sized,
+{
+    let (access_ids, refresh_ids) = with_descendants(store, access_ids, refresh_ids)?;
+    let revoked = apply(store, &access_ids, &refresh_ids)?;
+    INTROSPECTION_CACHE.invalidate_ids(&access_ids);
+    record(store, selection, audit, &revoked)?;
+    Ok(revoked)
+}
+
+// Adds the unrevoked descendants of the refresh tokens, walking down the
+// rotation chains one generation at a time. The set guards against a corrupt
+// lineage looping back on itself.
+fn with_descendants<S>(
+    store: &S,
+    mut access_ids: Vec<i32>,
+    refresh_ids: Vec<i32>,
+) -> StorageResult<(Vec<i32>, Vec<i32>)>
+where
+    S: TokenStore + ?Sized,
+{
+    let mut seen: HashSet<i32> = refresh_ids.iter().cloned().collect();
+    let mut generation = refresh_ids;
+    while !generation.is_empty() {
+        let mut children = Vec::new();
+        for chunk in generation.chunks(BATCH_SIZE) {
+            for id in store.find_child_refresh_token_ids(chunk)? {
+                if seen.insert(id) {
+                    children.push(id);
+                }
+            }
+        }
+        generation = children;
+    }
+    let mut refresh_ids: Vec<i32> = seen.into_iter().collect();
+    refresh_ids.sort();
+
+    for chunk in refresh_ids.chunks(BATCH_SIZE) {
+        access_ids.extend(store.find_access_token_ids_by_refresh_tokens(chunk)?);
+    }
+    access_ids.sort();
+    access_ids.dedup();
+    Ok((access_ids, refresh_ids))
+}
+
+fn apply<S>(store: &S, access_ids: &[i32], refresh_ids: &[i32]) -> StorageResult<Revoked>
+where
+    S: TokenStore + ?Sized,
+{
+    let now = Utc::now().naive_utc();
+    let mut revoked = Revoked::default();
+    for chunk in refresh_ids.chunks(BATCH_SIZE) {
+        revoked.refresh_tokens += store.revoke_refresh_tokens(chunk, now)?;
+    }
+    for chunk in access_ids.chunks(BATCH_SIZE) {
+        revoked.access_tokens += store.revoke_access_tokens(chunk, now)?;
+    }
+    METRICS
+        .tokens_revoked
+        .inc_by(&["access"], revoked.access_tokens as u64);
+    METRICS
+        .tokens_revoked
+        .inc_by(&["refresh"], revoked.refresh_tokens as u64);
+
+    for id in access_ids {
+        REUSABLE_TOKENS.forget(*id);
+    }
+    Ok(revoked)
+}
+
+fn record<S>(store: &S, selection: &str, audit: &Audit, revoked: &Revoked) -> StorageResult<()>
+where
+    S: RevocationStore + ?Sized,
+{
+    info!(
+        "{} revoked {} access tokens and {} refresh tokens ({}), reason: {}",
+        audit.actor,
+        revoked.access_tokens,
+        revoked.refresh_tokens,
+        selection,
+        audit.reason.as_ref().map_or("none given", |r| r.as_str())
+    );
+
+    let entry = NewRevocationBuilder::default()
+        .revoked_at(Utc::now().naive_utc())
+        .actor(audit.actor.clone())
+        .reason(audit.reason.clone())
+        .selection(selection.to_owned())
+        .access_tokens(revoked.access_tokens as i32)
+        .refresh_tokens(revoked.refresh_tokens as i32)
+        .build()
+        .unwrap(); // TODO: remove unwrap
+    store.record_revocation(&entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::db::{NewAccessToken, NewRefreshToken};
+    use crate::storage::memory::MemoryStore;
+    use crate::storage::GrantTypeStore;
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    // The in-memory tables are shared by every test, so each test issues
+    // tokens to a client id no other test does, or with a scope of its own
+    // when it selects tokens in bulk. Clients are not looked up otherwise.
+    fn audit() -> Audit {
+        Audit {
+            actor: "revocation tests".to_owned(),
+            reason: None,
+        }
+    }
+
+    fn refresh_token(
+        store: &MemoryStore,
+        client_id: i32,
+        scope: &str,
+        parent_id: Option<i32>,
+    ) -> (String, i32) {
+        let token = secrets::generate_token(TokenKind::Refresh);
+        let now = Utc::now().naive_utc();
+        let record = store
+            .create_refresh_token(&NewRefreshToken {
+                token_hash: secrets::hash_token(&token),
+                client_id,
+                scope: scope.to_owned(),
+                issued_at: now,
+                expires_at: Some(now + Duration::hours(1)),
+                absolute_expires_at: None,
+                parent_id,
+                auth_code_id: None,
+            })
+            .unwrap();
+        (token, record.id)
+    }
+
+    fn access_token(
+        store: &MemoryStore,
+        client_id: i32,
+        scope: &str,
+        refresh_token_id: Option<i32>,
+    ) -> (String, i32) {
+        let token = secrets::generate_token(TokenKind::Access);
+        let grant = store.find_grant_type_by_name("password").unwrap();
+        let now = Utc::now().naive_utc();
+        let record = store
+            .create_access_token(&NewAccessToken {
+                token_hash: secrets::hash_token(&token),
+                client_id,
+                grant_id: grant.id,
+                scope: scope.to_owned(),
+                issued_at: now,
+                expires_at: now + Duration::hours(1),
+                refresh_token_id,
+                auth_code_id: None,
+            })
+            .unwrap();
+        (token, record.id)
+    }
+
+    fn refresh_revoked(store: &MemoryStore, token: &str) -> bool {
+        let found = store
+            .find_refresh_token(&secrets::hash_token(token))
+            .unwrap();
+        found.revoked_at.is_some()
+    }
+
+    fn access_revoked(store: &MemoryStore, token: &str) -> bool {
+        let found = store
+            .find_access_token(&secrets::hash_token(token))
+            .unwrap();
+        found.revoked_at.is_some()
+    }
+
+    #[test]
+    fn revokes_a_refresh_token_given_the_token_itself() {
+        let store = MemoryStore::connect().unwrap();
+        let (parent, parent_id) = refresh_token(&store, -3901, "read", None);
+        let (child, child_id) = refresh_token(&store, -3901, "read", Some(parent_id));
+        let (access, _) = access_token(&store, -3901, "read", Some(child_id));
+        let (unrelated, _) = access_token(&store, -3901, "read", None);
+
+        let revoked = revoke_token(&store, &parent, &audit()).unwrap();
+        assert_eq!((revoked.access_tokens, revoked.refresh_tokens), (1, 2));
+        assert!(refresh_revoked(&store, &parent));
+        assert!(refresh_revoked(&store, &child));
+        assert!(access_revoked(&store, &access));
+        assert!(!access_revoked(&store, &unrelated));
+    }
+
+    #[test]
+    fn revokes_an_access_token_given_the_token_itself() {
+        let store = MemoryStore::connect().unwrap();
+        let (refresh, refresh_id) = refresh_token(&store, -3902, "read", None);
+        let (access, _) = access_token(&store, -3902, "read", Some(refresh_id));
+
+        let revoked = revoke_token(&store, &access, &audit()).unwrap();
+        assert_eq!((revoked.access_tokens, revoked.refresh_tokens), (1, 0));
+        assert!(access_revoked(&store, &access));
+        assert!(!refresh_revoked(&store, &refresh));
+
+        let revoked = revoke_token(&store, &access, &audit()).unwrap();
+        assert_eq!((revoked.access_tokens, revoked.refresh_tokens), (0, 0));
+    }
+
+    #[test]
+    fn refuses_tokens_that_are_malformed_or_were_never_issued() {
+        let store = MemoryStore::connect().unwrap();
+        let never_issued = secrets::generate_token(TokenKind::Refresh);
+        let mut mangled = secrets::generate_token(TokenKind::Access);
+        mangled.pop();
+
+        for token in &[never_issued.as_str(), mangled.as_str(), ""] {
+            assert!(matches!(
+                revoke_token(&store, token, &audit()),
+                Err(RevocationError::InvalidSelection(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn revokes_every_descendant_of_a_refresh_token() {
+        let store = MemoryStore::connect().unwrap();
+        let (root, root_id) = refresh_token(&store, -3903, "read", None);
+        let (child, child_id) = refresh_token(&store, -3903, "read", Some(root_id));
+        let (sibling, sibling_id) = refresh_token(&store, -3903, "read", Some(root_id));
+        let (grandchild, grandchild_id) = refresh_token(&store, -3903, "read", Some(child_id));
+        let (root_access, _) = access_token(&store, -3903, "read", Some(root_id));
+        let (sibling_access, sibling_access_id) =
+            access_token(&store, -3903, "read", Some(sibling_id));
+        let (newest_access, _) = access_token(&store, -3903, "read", Some(grandchild_id));
+        let (other_grant, _) = refresh_token(&store, -3903, "read", None);
+
+        // Tokens revoked before are neither revoked again nor counted.
+        revoke_access_token(&store, sibling_access_id, &audit()).unwrap();
+
+        let revoked = revoke_refresh_token(&store, root_id, &audit()).unwrap();
+        assert_eq!((revoked.access_tokens, revoked.refresh_tokens), (2, 4));
+        for token in &[&root, &child, &sibling, &grandchild] {
+            assert!(refresh_revoked(&store, token));
+        }
+        for token in &[&root_access, &sibling_access, &newest_access] {
+            assert!(access_revoked(&store, token));
+        }
+        assert!(!refresh_revoked(&store, &other_grant));
+
+        // Revoking part of a revoked grant again revokes nothing.
+        let revoked = revoke_refresh_token(&store, child_id, &audit()).unwrap();
+        assert_eq!((revoked.access_tokens, revoked.refresh_tokens), (0, 0));
+    }
+
+    #[test]
+    fn counts_without_revoking_on_a_dry_run() {
+        let store = MemoryStore::connect().unwrap();
+        let scope = format!("dry-run-{}", Uuid::new_v4().simple());
+        let (root, root_id) = refresh_token(&store, -3904, &scope, None);
+        let (child, child_id) = refresh_token(&store, -3904, &scope, Some(root_id));
+        let (access, _) = access_token(&store, -3904, &scope, Some(child_id));
+        let (standalone, _) = access_token(&store, -3904, &format!("read {}", scope), None);
+        let (unselected, _) = access_token(&store, -3904, "read", None);
+        let selection = Selection {
+            scope: Some(scope.clone()),
+            ..Selection::default()
+        };
+
+        let counted = revoke_matching(&store, &selection, &audit(), true).unwrap();
+        assert_eq!((counted.access_tokens, counted.refresh_tokens), (2, 2));
+        assert!(!refresh_revoked(&store, &root));
+        assert!(!refresh_revoked(&store, &child));
+        assert!(!access_revoked(&store, &access));
+        assert!(!access_revoked(&store, &standalone));
+
+        let revoked = revoke_matching(&store, &selection, &audit(), false).unwrap();
+        assert_eq!((revoked.access_tokens, revoked.refresh_tokens), (2, 2));
+        assert!(refresh_revoked(&store, &root));
+        assert!(refresh_revoked(&store, &child));
+        assert!(access_revoked(&store, &access));
+        assert!(access_revoked(&store, &standalone));
+        assert!(!access_revoked(&store, &unselected));
+
+        let counted = revoke_matching(&store, &selection, &audit(), true).unwrap();
+        assert_eq!((counted.access_tokens, counted.refresh_tokens), (0, 0));
+    }
+}
@@ -0,0 +1,203 @@
+//! The utils::secrets module generates the tokens handed out to clients, and
+//! the digests they are stored as. Only the digest of a token ever reaches
+//! the database, so a token is lost once the response issuing it is sent.
+//!
+//! A token is a prefix naming its kind (`oa2p_at_` or `oa2p_rt_`), 43 random
+//! base62 characters (256 bits from the system CSPRNG) and a 6 character
+//! base62 CRC32 of everything before it. The prefix lets secret scanners
+//! recognise leaked tokens, and the checksum lets the provider throw away
+//! mistyped or made up tokens without looking them up.
+
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt::Write;
+
+const ALPHABET: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const RANDOM_LEN: usize = 43;
+const CHECKSUM_LEN: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+    Access,
+    Refresh,
+}
+
+impl TokenKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            TokenKind::Access => "oa2p_at_",
+            TokenKind::Refresh => "oa2p_rt_",
+        }
+    }
+}
+
+/// Generates the plaintext of a new token of the given kind.
+pub fn generate_token(kind: TokenKind) -> String {
+    let rng = SystemRandom::new();
+    let mut token = String::with_capacity(kind.prefix().len() + RANDOM_LEN + CHECKSUM_LEN);
+    token.push_str(kind.prefix());
+
+    let mut random = [0u8; 64];
+    let mut remaining = RANDOM_LEN;
+    while remaining > 0 {
+        rng.fill(&mut random)
+            .expect("The system random number generator failed");
+        // Bytes past the largest multiple of 62 are skipped, so that every
+        // character is equally likely.
+        for &byte in random.iter().filter(|&&b| b < 248).take(remaining) {
+            token.push(ALPHABET[(byte % 62) as usize] as char);
+            remaining -= 1;
+        }
+    }
+
+    let checksum = encode_checksum(crc32(token.as_bytes()));
+    token.push_str(&checksum);
+    token
+}
+
+/// Checks that a token has the prefix for its kind, the right length and a
+/// matching checksum. This says nothing about whether the token was ever
+/// issued.
+pub fn is_well_formed(token: &str, kind: TokenKind) -> bool {
+    let prefix = kind.prefix();
+    if token.len() != prefix.len() + RANDOM_LEN + CHECKSUM_LEN || !token.starts_with(prefix) {
+        return false;
+    }
+    if !token[prefix.len()..].bytes().all(|b| ALPHABET.contains(&b)) {
+        return false;
+    }
+
+    let (body, checksum) = token.split_at(token.len() - CHECKSUM_LEN);
+    encode_checksum(crc32(body.as_bytes())) == checksum
+}
+
+/// Computes the digest a token is stored and looked up by.
+///
+/// Returns: String --- the hex encoded SHA-256 digest of the token.
+pub fn hash_token(token: &str) -> String {
+    let digest = digest::digest(&digest::SHA256, token.as_bytes());
+    let mut hex = String::with_capacity(64);
+    for byte in digest.as_ref() {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+// The CRC-32 used by zlib and PNG, computed a bit at a time; tokens are short
+// enough that a lookup table is not worth it.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// Six base62 digits cover every u32, most significant digit first.
+fn encode_checksum(mut checksum: u32) -> String {
+    let mut digits = [b'0'; CHECKSUM_LEN];
+    for digit in digits.iter_mut().rev() {
+        *digit = ALPHABET[(checksum % 62) as usize];
+        checksum /= 62;
+    }
+    String::from_utf8_lossy(&digits).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Replaces the character at `index` with a different one from the alphabet.
+    fn with_changed_char(token: &str, index: usize) -> String {
+        let mut bytes = token.as_bytes().to_vec();
+        bytes[index] = if bytes[index] == b'0' { b'1' } else { b'0' };
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn generated_tokens_are_well_formed() {
+        for &kind in &[TokenKind::Access, TokenKind::Refresh] {
+            let token = generate_token(kind);
+            assert!(token.starts_with(kind.prefix()));
+            assert_eq!(token.len(), kind.prefix().len() + RANDOM_LEN + CHECKSUM_LEN);
+            assert!(is_well_formed(&token, kind));
+        }
+        assert_ne!(
+            generate_token(TokenKind::Access),
+            generate_token(TokenKind::Access)
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let token = generate_token(TokenKind::Access);
+        assert!(!is_well_formed(
+            &with_changed_char(&token, token.len() - 1),
+            TokenKind::Access
+        ));
+        assert!(!is_well_formed(
+            &with_changed_char(&token, 10),
+            TokenKind::Access
+        ));
+    }
+
+    #[test]
+    fn rejects_the_wrong_prefix() {
+        let access = generate_token(TokenKind::Access);
+        let refresh = generate_token(TokenKind::Refresh);
+        assert!(!is_well_formed(&access, TokenKind::Refresh));
+        assert!(!is_well_formed(&refresh, TokenKind::Access));
+        assert!(!is_well_formed(
+            &access.replacen("oa2p_", "oa3p_", 1),
+            TokenKind::Access
+        ));
+    }
+
+    #[test]
+    fn rejects_the_wrong_length() {
+        let token = generate_token(TokenKind::Refresh);
+        assert!(!is_well_formed(
+            &token[..token.len() - 1],
+            TokenKind::Refresh
+        ));
+        assert!(!is_well_formed(&format!("{}0", token), TokenKind::Refresh));
+        assert!(!is_well_formed("", TokenKind::Refresh));
+        assert!(!is_well_formed(
+            "6ba7b810-9dad-11d1-80b4-00c04fd430c8",
+            TokenKind::Refresh
+        ));
+    }
+
+    #[test]
+    fn rejects_characters_outside_the_alphabet() {
+        let token = generate_token(TokenKind::Access);
+        let mut bytes = token.into_bytes();
+        bytes[10] = b'-';
+        assert!(!is_well_formed(
+            &String::from_utf8(bytes).unwrap(),
+            TokenKind::Access
+        ));
+    }
+
+    #[test]
+    fn checksums_match_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(encode_checksum(0), "000000");
+        assert_eq!(encode_checksum(u32::MAX), "4gfFC3");
+    }
+
+    #[test]
+    fn hashes_tokens_with_sha256() {
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -4,24 +4,27 @@
 //! Stylistically these functions are named after the grant type they
 //! are processing, and conform to the following function signature, which
 //! gives them access to the underlying datastore as well as the entire request
-//! data sent by the caller.
+//! data sent by the caller. The datastore is anything implementing
+//! `storage::Storage`, so none of these functions depend on a particular
+//! backend.
 
-use diesel::pg::PgConnection;
-use models::requests::access_token::AccessTokenRequest;
-use models::responses::access_token::AccessTokenResponse;
-use models::responses::oauth2_error::OAuth2ErrorResponse;
-use utils;
-use web::headers::authorization_token::AuthorizationToken;
+use crate::models::requests::access_token::AccessTokenRequest;
+use crate::models::responses::access_token::AccessTokenResponse;
+use crate::models::responses::oauth2_error::OAuth2ErrorResponse;
+use crate::storage::Storage;
+use crate::utils;
+use crate::utils::policy;
+use crate::web::headers::authorization_token::AuthorizationToken;
 
 /// Processes a `client_credentials` request, and returns a Result on whether
 /// or not it was successful.
 ///
 /// Returns: Result<AccessTokenResponse, OAuth2Error>
-///          - Ok(AccessTokenResponse) if the request was accepted
+/// - Ok(AccessTokenResponse) if the request was accepted
 /// - Err(OAuth2Error) prefilled with an error message if something
-/// went wrong.
-pub fn client_credentials(
-    conn: &PgConnection,
+///   went wrong.
+pub fn client_credentials<S: Storage + ?Sized>(
+    store: &S,
     req: AccessTokenRequest,
     auth: AuthorizationToken,
 ) -> Result<AccessTokenResponse, OAuth2ErrorResponse> {
@@ -31,7 +34,7 @@ pub fn client_credentials(
     }
 
     // Ensure the client information from the request is valid
-    let client = utils::check_client_credentials(conn, &auth.user, &auth.pass)?;
+    let client = utils::check_client_credentials(store, &auth.user, &auth.pass)?;
 
     // Is the client a `confidential` client?
     // TODO: This works for now but we should do something better than string
@@ -41,45 +44,105 @@ pub fn client_credentials(
     }
 
     // Ensure valid grant type
-    let grant_type = utils::check_grant_type(conn, &req.grant_type.unwrap())?; // TODO: remove unwrap
+    let grant_type = utils::check_grant_type(store, &req.grant_type.unwrap())?; // TODO: remove unwrap
 
     let scope = &req.scope.unwrap(); // TODO: remove unwrap
-    let at = utils::generate_access_token(conn, &client, &grant_type, scope);
-    let rt = utils::generate_refresh_token(conn, &client, scope);
-    Ok(utils::generate_token_response(at, Some(rt)))
+
+    // Clients that opt in get their current token back rather than a new row.
+    // A new refresh token would defeat the point of not writing to the
+    // database, so tokens are only reused when none would be issued, which
+    // keeps a reused response the same shape as a new one.
+    let issues_refresh_token = policy::issues_refresh_token(&client, &grant_type.name);
+    if !issues_refresh_token {
+        if let Some(at) = utils::find_reusable_access_token(store, &client, &grant_type, scope) {
+            debug!("Reusing access token {} for client {}", at.record.id, client.id);
+            return Ok(utils::generate_token_response(at, None));
+        }
+    }
+
+    // The refresh token comes first, so that the access token issued with it
+    // can be revoked along with it.
+    let rt = if issues_refresh_token {
+        Some(utils::generate_refresh_token(store, &client, &grant_type, scope, None))
+    } else {
+        None
+    };
+    let at = utils::generate_access_token(
+        store,
+        &client,
+        &grant_type,
+        scope,
+        rt.as_ref().map(|rt| &rt.record),
+    );
+    Ok(utils::generate_token_response(at, rt))
 }
 
 /// Processes a `refresh_token` request, and returns a Result on whether or not
 /// it was successful.
 ///
 /// Returns: Result<AccessTokenResponse, OAuth2Error>
-///          - Ok(AccessTokenResponse) if the request was accepted
+/// - Ok(AccessTokenResponse) if the request was accepted
 /// - Err(OAuth2Error) prefilled with an error message if something
-/// went wrong.
-pub fn refresh_token(
-    conn: &PgConnection,
+///   went wrong.
+pub fn refresh_token<S: Storage + ?Sized>(
+    store: &S,
     req: AccessTokenRequest,
     auth: AuthorizationToken,
 ) -> Result<AccessTokenResponse, OAuth2ErrorResponse> {
     // If we arent given the required params in the payload, we can immediately
     // respond with `invalid_request`
-    if req.refresh_token.is_none() || req.scope.is_none() {
+    if req.refresh_token.is_none() {
         return Err(OAuth2ErrorResponse::InvalidRequest);
     }
 
     // Fetch the building blocks using request data. This means the client, refresh
     // token, and scope. For the client and refresh token, we should be able to
     // get hits out of the database.
-    let client = utils::check_client_credentials(conn, &auth.user, &auth.pass)?;
-    let refresh_token =
-        utils::check_refresh_token(conn, &client, &req.refresh_token.clone().unwrap())?; // TODO: Remove unwrap
-    let scope = utils::check_scope(conn, &req.scope.unwrap(), &refresh_token.scope.clone())?; // TODO: Remove unwrap
+    let client = utils::check_client_credentials(store, &auth.user, &auth.pass)?;
+    let presented_token = req.refresh_token.clone().unwrap(); // TODO: Remove unwrap
+    let refresh_token = utils::check_refresh_token(store, &client, &presented_token)?;
+    let requested_scope = policy::refresh_scope(req.scope, &refresh_token.scope)?;
+    let scope = utils::check_scope(&requested_scope, &refresh_token.scope.clone())?;
 
     // The request appears valid. Generate an access token and reply with it.
-    let grant_type = utils::get_grant_type_by_name(conn, "refresh_token");
-    let access_token = utils::generate_access_token(conn, &client, &grant_type, &scope);
+    let grant_type = utils::get_grant_type_by_name(store, "refresh_token");
+
+    // A rotated refresh token keeps the scope of the original grant, rather than
+    // the (possibly narrower) scope of this particular access token. The access
+    // token belongs to the refresh token the client is left holding.
+    if policy::rotates_refresh_token(&client) {
+        let new_refresh_token = utils::generate_refresh_token(
+            store,
+            &client,
+            &grant_type,
+            &refresh_token.scope,
+            Some(&refresh_token),
+        );
+        let access_token = utils::generate_access_token(
+            store,
+            &client,
+            &grant_type,
+            &scope,
+            Some(&new_refresh_token.record),
+        );
+        utils::expire_refresh_token(store, &refresh_token);
+        return Ok(utils::generate_token_response(
+            access_token,
+            Some(new_refresh_token),
+        ));
+    }
+
+    let access_token =
+        utils::generate_access_token(store, &client, &grant_type, &scope, Some(&refresh_token));
+
+    // The client already holds the plaintext of the token it presented, and
+    // gets the same token back.
+    let refresh_token = utils::touch_refresh_token(store, &client, &grant_type, &refresh_token);
     Ok(utils::generate_token_response(
         access_token,
-        Some(refresh_token),
+        Some(utils::Issued {
+            token: presented_token,
+            record: refresh_token,
+        }),
     ))
 }
@@ -0,0 +1,53 @@
+//! Handlers run on async workers, which must never block. Storage access and
+//! bcrypt verification block, so handlers do their work through
+//! `RequestContext::run`, on the runtime's blocking thread pool. The request
+//! ID and span are entered there, so messages logged and spans started by that
+//! work are tied to the request as before.
+
+use crate::logging;
+use crate::tracing::{self, SpanContext};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::tokio::task;
+use std::convert::Infallible;
+use std::panic;
+
+/// What ties work done for a request back to it.
+#[derive(Clone, Debug)]
+pub struct RequestContext {
+    request_id: Option<String>,
+    span: Option<SpanContext>,
+}
+
+impl RequestContext {
+    /// Runs blocking work for the request on the blocking thread pool. A
+    /// panic in the work is passed on to the handler.
+    pub async fn run<F, T>(self, work: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let result = task::spawn_blocking(move || {
+            let _request_id = logging::enter(self.request_id);
+            let _span = tracing::enter(self.span);
+            work()
+        })
+        .await;
+
+        match result {
+            Ok(value) => value,
+            Err(e) => panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestContext {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Infallible> {
+        Outcome::Success(RequestContext {
+            request_id: logging::request_id_of(req),
+            span: tracing::span_context_of(req),
+        })
+    }
+}
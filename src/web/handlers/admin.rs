@@ -0,0 +1,207 @@
+use crate::INTROSPECTION_CACHE;
+use crate::models::requests::client_status::ClientStatusRequest;
+use crate::models::requests::revocation::RevocationRequest;
+use crate::models::responses::json::JsonResponse;
+use crate::storage;
+use crate::utils::revocation::{self, Audit, RevocationError, Selection};
+use crate::web::context::RequestContext;
+use crate::web::headers::admin_token::AdminToken;
+use rocket::form::{Form, Strict};
+use rocket::http::Status;
+
+// The number of audit entries `GET /admin/revocations` returns.
+const HISTORY_LIMIT: i64 = 100;
+
+#[post("/admin/revocations", data = "<req>")]
+pub async fn revoke(
+    req: Option<Form<Strict<RevocationRequest>>>,
+    admin: Option<AdminToken>,
+    context: RequestContext,
+) -> JsonResponse {
+    let admin = match admin {
+        Some(admin) => admin,
+        None => return JsonResponse::error(Status::Unauthorized, "unauthorized"),
+    };
+    let request = match req {
+        Some(req) => req.into_inner().into_inner(),
+        None => return JsonResponse::error(Status::BadRequest, "malformed request"),
+    };
+    context.run(move || revoke_matching(&admin, &request)).await
+}
+
+fn revoke_matching(admin: &AdminToken, request: &RevocationRequest) -> JsonResponse {
+    let selection = match selection(request) {
+        Ok(selection) => selection,
+        Err(msg) => return JsonResponse::error(Status::BadRequest, &msg),
+    };
+    let audit = Audit {
+        actor: format!("admin:{}", admin.name),
+        reason: request.reason.clone(),
+    };
+    let dry_run = request.dry_run.unwrap_or(false);
+    if request.token.is_some() && (dry_run || selection.all || selection_given(request)) {
+        return JsonResponse::error(
+            Status::BadRequest,
+            "a token cannot be combined with a selection or a dry run",
+        );
+    }
+
+    let store = match storage::connect() {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Unable to connect to storage for a revocation: {}", e);
+            return JsonResponse::error(Status::ServiceUnavailable, "storage unavailable");
+        }
+    };
+    // The token itself is never echoed back or logged.
+    let (result, description) = match request.token {
+        Some(ref token) => (revocation::revoke_token(&*store, token, &audit), "token".to_owned()),
+        None => (
+            revocation::revoke_matching(&*store, &selection, &audit, dry_run),
+            selection.to_string(),
+        ),
+    };
+    match result {
+        Ok(revoked) => JsonResponse::ok(json!({
+            "dry_run": dry_run,
+            "selection": description,
+            "access_tokens": revoked.access_tokens,
+            "refresh_tokens": revoked.refresh_tokens,
+        })),
+        Err(RevocationError::InvalidSelection(msg)) => {
+            JsonResponse::error(Status::BadRequest, &msg)
+        }
+        Err(RevocationError::Storage(e)) => {
+            error!("Revocation of {} failed: {}", description, e);
+            JsonResponse::error(Status::InternalServerError, "revocation failed")
+        }
+    }
+}
+
+#[get("/admin/revocations")]
+pub async fn history(admin: Option<AdminToken>, context: RequestContext) -> JsonResponse {
+    if admin.is_none() {
+        return JsonResponse::error(Status::Unauthorized, "unauthorized");
+    }
+
+    let revocations = context
+        .run(|| storage::connect().and_then(|store| store.list_revocations(HISTORY_LIMIT)))
+        .await;
+    match revocations {
+        Ok(revocations) => JsonResponse::ok(json!({ "revocations": revocations })),
+        Err(e) => {
+            error!("Unable to list revocations: {}", e);
+            JsonResponse::error(Status::InternalServerError, "storage error")
+        }
+    }
+}
+
+#[post("/admin/clients/<identifier>/disable", data = "<req>")]
+pub async fn disable_client(
+    identifier: String,
+    req: Option<Form<Strict<ClientStatusRequest>>>,
+    admin: Option<AdminToken>,
+    context: RequestContext,
+) -> JsonResponse {
+    let audit = match client_audit(admin, req) {
+        Ok(audit) => audit,
+        Err(response) => return response,
+    };
+
+    let disabled = context
+        .run(move || {
+            let store = storage::connect().map_err(RevocationError::Storage)?;
+            revocation::disable_client(&*store, &identifier, &audit)
+        })
+        .await;
+    match disabled {
+        Ok(revoked) => JsonResponse::ok(json!({
+            "disabled": true,
+            "access_tokens": revoked.access_tokens,
+            "refresh_tokens": revoked.refresh_tokens,
+        })),
+        Err(e) => client_error(e),
+    }
+}
+
+#[post("/admin/clients/<identifier>/enable", data = "<req>")]
+pub async fn enable_client(
+    identifier: String,
+    req: Option<Form<Strict<ClientStatusRequest>>>,
+    admin: Option<AdminToken>,
+    context: RequestContext,
+) -> JsonResponse {
+    let audit = match client_audit(admin, req) {
+        Ok(audit) => audit,
+        Err(response) => return response,
+    };
+
+    let enabled = context
+        .run(move || {
+            let store = storage::connect().map_err(RevocationError::Storage)?;
+            revocation::enable_client(&*store, &identifier, &audit)
+        })
+        .await;
+    match enabled {
+        Ok(_) => JsonResponse::ok(json!({ "disabled": false })),
+        Err(e) => client_error(e),
+    }
+}
+
+/// The introspection cache's hit and miss counters, for sizing
+/// `cache.capacity`. They count since this instance started.
+#[get("/admin/cache")]
+pub async fn cache(admin: Option<AdminToken>) -> JsonResponse {
+    if admin.is_none() {
+        return JsonResponse::error(Status::Unauthorized, "unauthorized");
+    }
+    JsonResponse::ok(json!(INTROSPECTION_CACHE.stats()))
+}
+
+fn client_audit(
+    admin: Option<AdminToken>,
+    req: Option<Form<Strict<ClientStatusRequest>>>,
+) -> Result<Audit, JsonResponse> {
+    let admin = match admin {
+        Some(admin) => admin,
+        None => return Err(JsonResponse::error(Status::Unauthorized, "unauthorized")),
+    };
+    // The reason is optional, and so is the form holding it.
+    Ok(Audit {
+        actor: format!("admin:{}", admin.name),
+        reason: req.and_then(|req| req.into_inner().into_inner().reason),
+    })
+}
+
+fn client_error(e: RevocationError) -> JsonResponse {
+    match e {
+        RevocationError::InvalidSelection(msg) => JsonResponse::error(Status::NotFound, &msg),
+        RevocationError::Storage(e) => {
+            error!("Unable to change the status of a client: {}", e);
+            JsonResponse::error(Status::InternalServerError, "storage error")
+        }
+    }
+}
+
+fn selection_given(request: &RevocationRequest) -> bool {
+    request.client.is_some()
+        || request.scope.is_some()
+        || request.issued_after.is_some()
+        || request.issued_before.is_some()
+}
+
+fn selection(request: &RevocationRequest) -> Result<Selection, String> {
+    Ok(Selection {
+        client: request.client.clone(),
+        scope: request.scope.clone(),
+        issued_after: match request.issued_after {
+            Some(ref t) => Some(revocation::parse_time(t)?),
+            None => None,
+        },
+        issued_before: match request.issued_before {
+            Some(ref t) => Some(revocation::parse_time(t)?),
+            None => None,
+        },
+        all: request.all.unwrap_or(false),
+    })
+}
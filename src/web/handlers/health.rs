@@ -0,0 +1,29 @@
+use crate::health;
+use crate::models::responses::json::JsonResponse;
+use crate::web::context::RequestContext;
+use rocket::http::Status;
+
+#[get("/healthz")]
+pub fn healthz() -> JsonResponse {
+    JsonResponse::ok(json!({ "status": "ok" }))
+}
+
+#[get("/readyz")]
+pub async fn readyz(context: RequestContext) -> JsonResponse {
+    let readiness = context.run(health::readiness).await;
+    if !readiness.ready {
+        warn!("Not ready to serve requests: {:?}", readiness.checks);
+    }
+
+    JsonResponse {
+        status: if readiness.ready {
+            Status::Ok
+        } else {
+            Status::ServiceUnavailable
+        },
+        body: json!({
+            "status": if readiness.ready { "ready" } else { "not_ready" },
+            "checks": readiness.checks,
+        }),
+    }
+}
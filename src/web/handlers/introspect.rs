@@ -1,52 +1,89 @@
-use DB_POOL;
+use crate::INTROSPECTION_CACHE;
+use crate::metrics::METRICS;
+use crate::models::db::*;
+use crate::models::requests::introspect::IntrospectionRequest;
+use crate::models::responses::introspection_err::IntrospectionErrResponse;
+use crate::models::responses::introspection_ok::{IntrospectionOkResponse, IntrospectionOkResponseBuilder};
+use crate::storage::{self, StorageResult};
+use crate::utils;
+use crate::utils::secrets::{self, TokenKind};
+use crate::web::context::RequestContext;
+use crate::web::headers::authorization_token::AuthorizationToken;
 use chrono::offset::Utc;
-use diesel::prelude::*;
-use models::db::*;
-use models::requests::introspect::IntrospectionRequest;
-use models::responses::introspection_err::IntrospectionErrResponse;
-use models::responses::introspection_ok::{IntrospectionOkResponse, IntrospectionOkResponseBuilder};
-use persistence::*;
-use rocket::request::Form;
-use utils;
+use rocket::form::{Form, Strict};
 use uuid::Uuid;
-use web::headers::authorization_token::AuthorizationToken;
 
 #[post("/oauth/introspect", data = "<req>")]
-pub fn post(
-    req: Option<Form<IntrospectionRequest>>,
+pub async fn post(
+    req: Option<Form<Strict<IntrospectionRequest>>>,
+    auth: Option<AuthorizationToken>,
+    context: RequestContext,
+) -> Result<IntrospectionOkResponse, IntrospectionErrResponse> {
+    let req = req.map(|v| v.into_inner().into_inner());
+    context.run(move || introspect(req, auth)).await
+}
+
+fn introspect(
+    req: Option<IntrospectionRequest>,
     auth: Option<AuthorizationToken>,
 ) -> Result<IntrospectionOkResponse, IntrospectionErrResponse> {
     debug!("Checking validitity of a supposed auth token.");
-    let auth_token = auth.ok_or(utils::introspection_error())?;
+    let auth_token = auth.ok_or_else(|| inactive("no_credentials"))?;
 
     trace!("Introspect endpoint request: {:?}", req);
-    let request = req.map(|v| v.into_inner())
-        .ok_or(utils::introspection_error())?;
+    let request = req.ok_or_else(|| inactive("malformed_request"))?;
 
-    trace!("Attempting to get DB connection.");
-    let conn = &*DB_POOL.get().unwrap(); // TODO: remove unwrap
-    trace!("DB connection successfully established.");
+    trace!("Attempting to get a storage connection.");
+    let store = storage::connect().map_err(|_| inactive("storage_unavailable"))?;
+    trace!("Storage connection successfully established.");
 
     trace!("authenticating client credentials: {:?}", &auth_token);
-    let client = utils::check_client_credentials(&conn, &auth_token.user, &auth_token.pass)
-        .map_err(|_| utils::introspection_error())?;
+    let client = utils::check_client_credentials(&*store, &auth_token.user, &auth_token.pass)
+        .map_err(|_| inactive("invalid_client"))?;
 
-    // Tokens are always UUIDs
+    // Garbage is turned away before touching the cache or storage. Tokens
+    // issued before the current format are UUIDs.
+    // Malformed token  -->  not active
+    if !secrets::is_well_formed(&request.token, TokenKind::Access)
+        && Uuid::parse_str(&request.token).is_err()
+    {
+        debug!("Token is malformed.");
+        return Err(inactive("malformed_token"));
+    }
+
+    // Tokens are stored, and cached, by their digest
     // No token  -->  not active
-    trace!("Parsing token into UUID: {:?}", &request.token);
-    let token_as_uuid = Uuid::parse_str(&request.token).map_err(|_| utils::introspection_error())?;
+    let token_hash = secrets::hash_token(&request.token);
 
-    let opt_token: QueryResult<AccessToken> = access_tokens::table
-        .filter(access_tokens::token.eq(token_as_uuid))
-        .first(conn);
+    // Tokens revoked since the cache last checked must not be answered for
+    INTROSPECTION_CACHE.sync_revocations(|| store.latest_revocation_id());
+    let access_token = match INTROSPECTION_CACHE.get(&token_hash) {
+        Some(cached) => {
+            trace!("Access Token from cache: {:?}", cached);
+            cached
+        }
+        None => {
+            let opt_token: StorageResult<AccessToken> =
+                utils::find_access_token(&*store, &request.token);
 
-    trace!("Access Token from DB: {:?}", opt_token);
-    let access_token = opt_token.map_err(|_| utils::introspection_error())?;
+            trace!("Access Token from storage: {:?}", opt_token);
+            let fetched = opt_token.map_err(|_| inactive("unknown_token"))?;
+            INTROSPECTION_CACHE.insert(&token_hash, &fetched);
+            fetched
+        }
+    };
+    debug!("Introspection cache stats: {:?}", INTROSPECTION_CACHE.stats());
 
     // Make sure the authenticated client owns this token
     if client.id != access_token.client_id {
         debug!("Client ID mismatch.");
-        return Err(utils::introspection_error());
+        return Err(inactive("client_mismatch"));
+    }
+
+    // Revoked  -->  not active
+    if access_token.revoked_at.is_some() {
+        debug!("Token is revoked.");
+        return Err(inactive("revoked"));
     }
 
     // expires_at <= Now  -->  not active
@@ -56,7 +93,7 @@ pub fn post(
         .num_seconds() <= 0
     {
         debug!("Token is expired.");
-        return Err(utils::introspection_error());
+        return Err(inactive("expired"));
     }
 
     // That means that for our current implementation, the token itself is valid.
@@ -64,16 +101,24 @@ pub fn post(
         .active(true)
         .scope(Some(access_token.scope))
         .client_id(Some(client.identifier))
-        .exp(Some(access_token.expires_at.timestamp()))
-        .iat(Some(access_token.issued_at.timestamp()))
+        .exp(Some(access_token.expires_at.and_utc().timestamp()))
+        .iat(Some(access_token.issued_at.and_utc().timestamp()))
         .build()
         .unwrap(); // TODO: remove unwrap
     debug!("Token is valid: {:?}", response);
     info!(
-        "Client [{}] introspected on token [{}]",
+        "Client [{}] introspected on access token {}",
         response.client_id.clone().unwrap(),
-        request.token
+        access_token.id
     );
 
+    METRICS.introspections.inc(&["active", ""]);
     Ok(response)
 }
+
+// Every inactive answer looks the same to the caller, but the reason is
+// counted for the `oa2p_introspections_total` metric.
+fn inactive(reason: &str) -> IntrospectionErrResponse {
+    METRICS.introspections.inc(&["inactive", reason]);
+    utils::introspection_error()
+}
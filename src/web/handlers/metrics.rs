@@ -0,0 +1,11 @@
+use crate::metrics;
+use crate::web::context::RequestContext;
+use rocket::http::ContentType;
+
+#[get("/metrics")]
+pub async fn get(context: RequestContext) -> (ContentType, String) {
+    (
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        context.run(metrics::render).await,
+    )
+}
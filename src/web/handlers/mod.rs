@@ -1,2 +1,5 @@
+pub mod admin;
+pub mod health;
 pub mod introspect;
+pub mod metrics;
 pub mod token;
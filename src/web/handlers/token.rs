@@ -1,14 +1,24 @@
-use DB_POOL;
-use models::requests::access_token::AccessTokenRequest;
-use models::responses::access_token::AccessTokenResponse;
-use models::responses::oauth2_error::OAuth2ErrorResponse;
-use rocket::request::Form;
-use utils;
-use web::headers::authorization_token::AuthorizationToken;
+use crate::models::requests::access_token::AccessTokenRequest;
+use crate::models::responses::access_token::AccessTokenResponse;
+use crate::models::responses::oauth2_error::OAuth2ErrorResponse;
+use crate::storage;
+use crate::utils;
+use crate::web::context::RequestContext;
+use crate::web::headers::authorization_token::AuthorizationToken;
+use rocket::form::{Form, Strict};
 
 #[post("/oauth/token", data = "<req>")]
-pub fn post(
-    req: Option<Form<AccessTokenRequest>>,
+pub async fn post(
+    req: Option<Form<Strict<AccessTokenRequest>>>,
+    auth: Option<AuthorizationToken>,
+    context: RequestContext,
+) -> Result<AccessTokenResponse, OAuth2ErrorResponse> {
+    let req = req.map(|v| v.into_inner().into_inner());
+    context.run(move || grant(req, auth)).await
+}
+
+fn grant(
+    req: Option<AccessTokenRequest>,
     auth: Option<AuthorizationToken>,
 ) -> Result<AccessTokenResponse, OAuth2ErrorResponse> {
     trace!("Entering the token handler.");
@@ -16,12 +26,14 @@ pub fn post(
     let auth_token = auth.ok_or(OAuth2ErrorResponse::InvalidClient)?;
 
     trace!("Extracting access token");
-    debug!("token request: {:?}", &req);
-    let request = req.map(|v| v.into_inner())
-        .ok_or(OAuth2ErrorResponse::InvalidRequest)?;
+    debug!("token request: {:?}", req);
+    let request = req.ok_or(OAuth2ErrorResponse::InvalidRequest)?;
 
-    let conn = &*DB_POOL.get().unwrap(); // TODO: remove unwrap
-    trace!("Successfully grabbed connection from the database connection pool.");
+    let store = storage::connect().map_err(|e| {
+        error!("Unable to connect to storage for a token request: {}", e);
+        OAuth2ErrorResponse::ServerError
+    })?;
+    trace!("Successfully grabbed connection from the storage backend.");
 
     let grant_type = request
         .grant_type
@@ -29,8 +41,8 @@ pub fn post(
         .ok_or(OAuth2ErrorResponse::UnsupportedGrantType)?;
 
     let result = match grant_type.as_str() {
-        "client_credentials" => utils::token::client_credentials(conn, request, auth_token.clone()),
-        "refresh_token" => utils::token::refresh_token(conn, request, auth_token.clone()),
+        "client_credentials" => utils::token::client_credentials(&*store, request, auth_token.clone()),
+        "refresh_token" => utils::token::refresh_token(&*store, request, auth_token.clone()),
         _ => Err(OAuth2ErrorResponse::UnsupportedGrantType),
     };
     trace!("auth token endpoint response: {:?}", result);
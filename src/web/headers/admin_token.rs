@@ -0,0 +1,45 @@
+use crate::settings;
+use crate::utils::secrets;
+use ring::constant_time;
+use rocket::Request;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+
+/// A caller presenting one of the tokens in `admin.tokens` as a bearer token.
+/// Only the name the token is configured under is kept, for the audit trail.
+#[derive(Clone, Debug)]
+pub struct AdminToken {
+    pub name: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        let token = match req.headers().get_one("Authorization") {
+            Some(value) if value.starts_with("Bearer ") => &value["Bearer ".len()..],
+            _ => return Outcome::Error((Status::Unauthorized, ())),
+        };
+
+        // Every configured digest is compared in constant time, so the
+        // response time says nothing about how close a guess was.
+        let token_hash = secrets::hash_token(token);
+        let settings = settings::current();
+        let mut found = None;
+        for (name, admin_hash) in &settings.admin.tokens {
+            let matches = constant_time::verify_slices_are_equal(
+                token_hash.as_bytes(),
+                admin_hash.to_lowercase().as_bytes(),
+            ).is_ok();
+            if matches {
+                found = Some(name.clone());
+            }
+        }
+
+        match found {
+            Some(name) => Outcome::Success(AdminToken { name }),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
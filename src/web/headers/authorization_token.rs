@@ -1,8 +1,6 @@
-use base64;
-use rocket::Outcome::{self, Failure, Success};
 use rocket::Request;
 use rocket::http::{Header, Status};
-use rocket::request::FromRequest;
+use rocket::request::{FromRequest, Outcome};
 use std::convert::From;
 use std::fmt;
 
@@ -23,51 +21,37 @@ impl fmt::Debug for AuthorizationToken {
     }
 }
 
-impl<'a, 'r> FromRequest<'a, 'r> for AuthorizationToken {
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthorizationToken {
     type Error = ();
 
-    fn from_request(req: &'a Request<'r>) -> Outcome<Self, (Status, ()), ()> {
-        // TODO: clean this up
-        // Check for two parts when splitting components and user/pass as may return HTTP 500 Enforce an authorization
-        // header...
-        let components_vec = match req.headers().get_one("Authorization") {
-            Some(v) => v,
-            None => return Failure((Status::Unauthorized, ())),
-        };
-
-        // That has two parts, the first of which is "Basic" ...
-        let components = components_vec.split(' ').collect::<Vec<&str>>();
-        let base64_value = match components[0] {
-            "Basic" => components[1],
-            _ => return Failure((Status::Unauthorized, ())),
-        };
-
-        // where the second part is a base 64 string...
-        let decoded_value = match base64::decode(base64_value) {
-            Ok(decoded) => decoded,
-            Err(_) => return Failure((Status::Unauthorized, ())),
-        };
-
-        // which maps down to valid UTF8 characters, of the form XXXXXXX:YYYYYYY
-        match String::from_utf8(decoded_value) {
-            Ok(decoded_str) => {
-                let parts = decoded_str.split(':').collect::<Vec<&str>>();
-                Success(AuthorizationToken {
-                    user: parts[0].to_owned(),
-                    pass: parts[1].to_owned(),
-                })
-            }
-            Err(_) => Failure((Status::Unauthorized, ())),
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        match req.headers().get_one("Authorization").and_then(parse) {
+            Some(token) => Outcome::Success(token),
+            None => Outcome::Error((Status::Unauthorized, ())),
         }
     }
 }
 
+// Enforces an authorization header of two parts, the first of which is
+// "Basic", where the second part is a base 64 string of valid UTF-8 of the
+// form XXXXXXX:YYYYYYY. The password may contain colons, the user may not.
+fn parse(header: &str) -> Option<AuthorizationToken> {
+    let base64_value = match header.split_once(' ') {
+        Some(("Basic", value)) => value,
+        _ => return None,
+    };
+    let decoded_value = base64::decode(base64_value).ok()?;
+    let decoded_str = String::from_utf8(decoded_value).ok()?;
+    let (user, pass) = decoded_str.split_once(':')?;
+    Some(AuthorizationToken {
+        user: user.to_owned(),
+        pass: pass.to_owned(),
+    })
+}
+
 impl From<AuthorizationToken> for Header<'static> {
     fn from(token: AuthorizationToken) -> Header<'static> {
-        println!(
-            "{}",
-            base64::encode(&format!("{}:{}", token.user, token.pass))
-        );
         Header::new(
             "Authorization",
             format!(
@@ -77,3 +61,34 @@ impl From<AuthorizationToken> for Header<'static> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", base64::encode(credentials))
+    }
+
+    #[test]
+    fn parses_basic_credentials() {
+        let token = parse(&basic("abcd1234:pass:word")).unwrap();
+        assert_eq!(token.user, "abcd1234");
+        assert_eq!(token.pass, "pass:word");
+    }
+
+    #[test]
+    fn refuses_malformed_headers() {
+        for header in &[
+            String::new(),
+            "Basic".to_owned(),
+            "Basic ".to_owned(),
+            "Bearer abcd1234".to_owned(),
+            "Basic not-base64!".to_owned(),
+            format!("Basic {}", base64::encode(&[0xff, 0xfe, b':'])),
+            basic("no colon"),
+        ] {
+            assert!(parse(header).is_none(), "{}", header);
+        }
+    }
+}
@@ -1 +1,2 @@
+pub mod admin_token;
 pub mod authorization_token;
@@ -1,2 +1,3 @@
+pub mod context;
 pub mod handlers;
 pub mod headers;